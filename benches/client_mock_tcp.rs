@@ -8,7 +8,7 @@ extern crate test;
 use std::fmt::{mod, Show};
 use std::str::from_str;
 use std::io::{IoResult, MemReader};
-use std::io::net::ip::{SocketAddr, ToSocketAddr};
+use std::io::net::ip::{SocketAddr, Port};
 use std::os;
 use std::path::BytesContainer;
 
@@ -95,7 +95,7 @@ impl net::NetworkStream for MockStream {
 struct MockConnector;
 
 impl net::NetworkConnector<MockStream> for MockConnector {
-    fn connect<To: ToSocketAddr>(&mut self, _addr: To, _scheme: &str) -> IoResult<MockStream> {
+    fn connect(&mut self, _host: &str, _port: Port, _scheme: &str) -> IoResult<MockStream> {
         Ok(MockStream::new())
     }
 
@@ -0,0 +1,199 @@
+//! Client Response Caching
+use std::collections::HashMap;
+
+use time::{Timespec, get_time};
+use url::Url;
+
+use header::Headers;
+use header::common::{CacheControl, ContentLength, Date, Etag, Expires, LastModified,
+                     TransferEncoding};
+use header::common::cache_control::CacheDirective::{NoCache, NoStore, MaxAge};
+use status::StatusCode;
+use version::HttpVersion;
+use client::Response;
+
+/// A store of responses, used by a `Client` to cache responses to GET
+/// requests, as described in [RFC7234](http://tools.ietf.org/html/rfc7234).
+pub trait CacheStore: Send {
+    /// Get the entry stored for `url`, if there is one.
+    fn get(&mut self, url: &Url) -> Option<CacheEntry>;
+
+    /// Store an entry for `url`, replacing any existing entry.
+    fn put(&mut self, url: &Url, entry: CacheEntry);
+
+    /// Remove the entry stored for `url`.
+    fn remove(&mut self, url: &Url);
+}
+
+/// A response kept in a `CacheStore`.
+#[deriving(Clone)]
+pub struct CacheEntry {
+    /// The status of the response.
+    pub status: StatusCode,
+    /// The HTTP version of the response.
+    pub version: HttpVersion,
+    /// The headers of the response.
+    pub headers: Headers,
+    /// The whole body of the response.
+    pub body: Vec<u8>,
+    /// When the response was received, or last revalidated.
+    pub stored_at: Timespec,
+}
+
+impl CacheEntry {
+    /// Create an entry from the parts of a response received just now.
+    pub fn new(status: StatusCode, version: HttpVersion, mut headers: Headers, body: Vec<u8>) -> CacheEntry {
+        // The body is whole now, however it was framed on the wire.
+        headers.remove::<TransferEncoding>();
+        headers.set(ContentLength(body.len()));
+        CacheEntry {
+            status: status,
+            version: version,
+            headers: headers,
+            body: body,
+            stored_at: get_time(),
+        }
+    }
+
+    /// Whether a response with these headers may be stored at all.
+    ///
+    /// It may not if it asks not to be, or if it could never be served
+    /// from the cache, lacking both a freshness lifetime and validators.
+    pub fn is_storable(headers: &Headers) -> bool {
+        match headers.get::<CacheControl>() {
+            Some(cc) if cc.contains(&NoStore) => return false,
+            _ => ()
+        }
+        freshness_lifetime(headers).is_some() || has_validators(headers)
+    }
+
+    /// Whether this entry can be served at `now` without revalidating it.
+    pub fn is_fresh(&self, now: Timespec) -> bool {
+        match freshness_lifetime(&self.headers) {
+            Some(lifetime) => now.sec - self.stored_at.sec < lifetime,
+            None => false
+        }
+    }
+
+    /// Whether this entry has an `Etag` or `Last-Modified` to revalidate it with.
+    pub fn can_revalidate(&self) -> bool {
+        has_validators(&self.headers)
+    }
+
+    /// Create a Response from this entry.
+    pub fn to_response(&self) -> Response {
+        Response::from_parts(self.status, self.version, self.headers.clone(), self.body.clone())
+    }
+}
+
+/// The seconds after being received that a response is fresh for, if known.
+fn freshness_lifetime(headers: &Headers) -> Option<i64> {
+    match headers.get::<CacheControl>() {
+        Some(cc) => {
+            if cc.contains(&NoCache) {
+                return Some(0);
+            }
+            for directive in cc.iter() {
+                match *directive {
+                    MaxAge(secs) => return Some(secs as i64),
+                    _ => ()
+                }
+            }
+        },
+        None => ()
+    }
+    match (headers.get::<Expires>(), headers.get::<Date>()) {
+        (Some(expires), Some(date)) => Some(expires.to_timespec().sec - date.to_timespec().sec),
+        (Some(expires), None) => Some(expires.to_timespec().sec - get_time().sec),
+        _ => None
+    }
+}
+
+fn has_validators(headers: &Headers) -> bool {
+    headers.has::<Etag>() || headers.has::<LastModified>()
+}
+
+/// A `CacheStore` which keeps all entries in memory.
+#[deriving(Default)]
+pub struct MemoryCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl MemoryCache {
+    /// Create a new, empty MemoryCache.
+    pub fn new() -> MemoryCache {
+        MemoryCache { entries: HashMap::new() }
+    }
+}
+
+impl CacheStore for MemoryCache {
+    fn get(&mut self, url: &Url) -> Option<CacheEntry> {
+        self.entries.get(&url.serialize_no_fragment()).map(|entry| entry.clone())
+    }
+
+    fn put(&mut self, url: &Url, entry: CacheEntry) {
+        self.entries.insert(url.serialize_no_fragment(), entry);
+    }
+
+    fn remove(&mut self, url: &Url) {
+        self.entries.remove(&url.serialize_no_fragment());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use time::get_time;
+    use url::Url;
+
+    use header::Headers;
+    use header::common::{CacheControl, Etag};
+    use header::common::cache_control::CacheDirective::{MaxAge, NoCache, NoStore};
+    use status::StatusCode;
+    use version::HttpVersion::Http11;
+    use super::{CacheEntry, CacheStore, MemoryCache};
+
+    fn entry(headers: Headers) -> CacheEntry {
+        CacheEntry::new(StatusCode::Ok, Http11, headers, b"hello".to_vec())
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut headers = Headers::new();
+        headers.set(CacheControl(vec![MaxAge(60)]));
+        assert!(CacheEntry::is_storable(&headers));
+        let entry = entry(headers);
+        assert!(entry.is_fresh(get_time()));
+        assert!(!entry.is_fresh(get_time() + Duration::seconds(61)));
+    }
+
+    #[test]
+    fn test_no_cache_needs_revalidation() {
+        let mut headers = Headers::new();
+        headers.set(CacheControl(vec![NoCache]));
+        headers.set(Etag { weak: false, tag: "foo".into_string() });
+        assert!(CacheEntry::is_storable(&headers));
+        let entry = entry(headers);
+        assert!(!entry.is_fresh(get_time()));
+        assert!(entry.can_revalidate());
+    }
+
+    #[test]
+    fn test_not_storable() {
+        let mut headers = Headers::new();
+        assert!(!CacheEntry::is_storable(&headers));
+        headers.set(CacheControl(vec![NoStore, MaxAge(60)]));
+        assert!(!CacheEntry::is_storable(&headers));
+    }
+
+    #[test]
+    fn test_memory_cache() {
+        let mut cache = MemoryCache::new();
+        let url = Url::parse("http://example.dom/foo#bar").unwrap();
+        cache.put(&url, entry(Headers::new()));
+        let mut res = cache.get(&Url::parse("http://example.dom/foo").unwrap()).unwrap().to_response();
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+        cache.remove(&url);
+        assert!(cache.get(&url).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+//! Client Request Cancellation
+use std::sync::{Arc, Mutex};
+
+use net::NetworkStream;
+
+/// A handle that cancels a request from another task.
+///
+/// Cancelling shuts down the connection of the request, so that a read or
+/// write blocked on it returns at once. The request then fails with an
+/// `HttpCancelledError`, and reading its `Response` fails too. Clones of a
+/// handle cancel the same request.
+#[deriving(Clone)]
+pub struct CancelHandle {
+    inner: Arc<Mutex<CancelState>>,
+}
+
+struct CancelState {
+    cancelled: bool,
+    stream: Option<Box<NetworkStream + Send>>,
+    // how many streams have been watched, so that one is only unwatched if
+    // it is still the one watched
+    generation: uint,
+}
+
+impl CancelHandle {
+    /// Create a handle, not yet watching any connection.
+    pub fn new() -> CancelHandle {
+        CancelHandle {
+            inner: Arc::new(Mutex::new(CancelState {
+                cancelled: false,
+                stream: None,
+                generation: 0,
+            }))
+        }
+    }
+
+    /// Cancel the request, shutting down its connection.
+    pub fn cancel(&self) {
+        let mut state = self.inner.lock();
+        state.cancelled = true;
+        match state.stream {
+            Some(ref mut stream) => {
+                debug!("cancelling request");
+                let _ = stream.shutdown();
+            },
+            None => ()
+        }
+    }
+
+    /// Whether `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().cancelled
+    }
+
+    /// Watch `stream`, a clone of the connection of the request, so that it
+    /// is shut down when cancelled. If already cancelled, it is shut down now.
+    ///
+    /// Returns the generation to `unwatch` it with.
+    #[doc(hidden)]
+    pub fn watch(&self, mut stream: Box<NetworkStream + Send>) -> uint {
+        let mut state = self.inner.lock();
+        if state.cancelled {
+            let _ = stream.shutdown();
+        }
+        state.stream = Some(stream);
+        state.generation += 1;
+        state.generation
+    }
+
+    /// Stop watching the stream `watch` returned `generation` for, once the
+    /// request is done with it, unless another has been watched since. A
+    /// stream released to a pool is then never shut down by a late cancel.
+    #[doc(hidden)]
+    pub fn unwatch(&self, generation: uint) {
+        let mut state = self.inner.lock();
+        if state.generation == generation {
+            state.stream = None;
+        }
+    }
+
+    /// Whether a stream is watched.
+    #[cfg(test)]
+    pub fn is_watching(&self) -> bool {
+        self.inner.lock().stream.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mock::MockStream;
+    use net::NetworkStream;
+    use super::CancelHandle;
+
+    #[test]
+    fn test_cancel() {
+        let handle = CancelHandle::new();
+        assert!(!handle.is_cancelled());
+        handle.watch(box MockStream::new() as Box<NetworkStream + Send>);
+        handle.clone().cancel();
+        assert!(handle.is_cancelled());
+    }
+
+    #[test]
+    fn test_unwatch() {
+        let handle = CancelHandle::new();
+        let first = handle.watch(box MockStream::new() as Box<NetworkStream + Send>);
+        let second = handle.watch(box MockStream::new() as Box<NetworkStream + Send>);
+        // a stale generation leaves the stream watched since alone
+        handle.unwatch(first);
+        assert!(handle.inner.lock().stream.is_some());
+        handle.unwatch(second);
+        assert!(handle.inner.lock().stream.is_none());
+    }
+}
@@ -0,0 +1,464 @@
+//! Client Cookie Storage
+use std::ascii::{AsciiExt, OwnedAsciiExt};
+use std::io::{IoError, IoResult, InvalidInput};
+
+use cookie::Cookie;
+#[cfg(feature = "json")]
+use serialize::json;
+use time::{Timespec, get_time};
+use url::Url;
+
+use header::common::{Cookies, SetCookie};
+use super::expires_after;
+
+/// A store of cookies received by a `Client`.
+///
+/// Cookies are stored following the domain, path, and expiry rules of
+/// [RFC6265](http://tools.ietf.org/html/rfc6265#section-5.3), and are
+/// only returned for URLs they match.
+#[deriving(Clone, Default)]
+pub struct CookieStore {
+    cookies: Vec<StoredCookie>,
+}
+
+#[deriving(Clone)]
+struct StoredCookie {
+    cookie: Cookie,
+    domain: String,
+    host_only: bool,
+    path: String,
+    expires: Option<Timespec>,
+}
+
+impl StoredCookie {
+    fn is_expired(&self, now: Timespec) -> bool {
+        match self.expires {
+            Some(expires) => expires <= now,
+            None => false
+        }
+    }
+
+    fn matches(&self, host: &str, path: &str, secure: bool) -> bool {
+        if self.cookie.secure && !secure {
+            return false;
+        }
+        let domain_match = if self.host_only {
+            host == self.domain[]
+        } else {
+            domain_matches(host, self.domain[])
+        };
+        domain_match && path_matches(path, self.path[])
+    }
+
+    fn same_as(&self, other: &StoredCookie) -> bool {
+        self.cookie.name == other.cookie.name &&
+            self.domain == other.domain &&
+            self.path == other.path
+    }
+}
+
+impl CookieStore {
+    /// Create a new, empty CookieStore.
+    pub fn new() -> CookieStore {
+        CookieStore { cookies: Vec::new() }
+    }
+
+    /// Store the cookies of a `Set-Cookie` header, received from `url`.
+    ///
+    /// Cookies for a domain that `url` cannot set cookies for are ignored,
+    /// and cookies which have already expired remove any cookie they replace.
+    pub fn store(&mut self, url: &Url, set_cookie: &SetCookie) {
+        let host = match url.serialize_host() {
+            Some(host) => host.into_ascii_lower(),
+            None => return
+        };
+        let now = get_time();
+        for cookie in set_cookie.iter() {
+            let (domain, host_only) = match cookie.domain {
+                Some(ref domain) => {
+                    let domain = domain[].trim_left_chars('.').to_ascii_lower();
+                    if !domain_matches(host[], domain[]) {
+                        debug!("ignoring cookie {} for domain {} from {}", cookie.name, domain, host);
+                        continue;
+                    }
+                    (domain, false)
+                },
+                None => (host.clone(), true)
+            };
+            let path = match cookie.path {
+                Some(ref path) if path[].starts_with("/") => path.clone(),
+                _ => default_path(url)
+            };
+            let expires = match (cookie.max_age, cookie.expires) {
+                (Some(max_age), _) => Some(expires_after(now, max_age)),
+                (None, Some(ref tm)) => Some(tm.to_timespec()),
+                (None, None) => None
+            };
+
+            let stored = StoredCookie {
+                cookie: cookie.clone(),
+                domain: domain,
+                host_only: host_only,
+                path: path,
+                expires: expires,
+            };
+            self.insert(stored, now);
+        }
+    }
+
+    /// Replaces any cookie with the same name, domain, and path, unless the
+    /// new one has already expired.
+    fn insert(&mut self, stored: StoredCookie, now: Timespec) {
+        self.cookies.retain(|c| !c.same_as(&stored));
+        if !stored.is_expired(now) {
+            debug!("storing cookie {} for {}{}", stored.cookie.name, stored.domain, stored.path);
+            self.cookies.push(stored);
+        }
+    }
+
+    /// Get a `Cookie` header of all unexpired cookies that match `url`,
+    /// if there are any.
+    ///
+    /// Cookies with longer paths are listed first.
+    pub fn cookies_for(&self, url: &Url) -> Option<Cookies> {
+        let host = match url.serialize_host() {
+            Some(host) => host.into_ascii_lower(),
+            None => return None
+        };
+        let path = url.serialize_path().unwrap_or_else(|| "/".into_string());
+        let secure = url.scheme[] == "https";
+        let now = get_time();
+
+        let mut matching = self.cookies.iter()
+            .filter(|c| !c.is_expired(now) && c.matches(host[], path[], secure))
+            .collect::<Vec<&StoredCookie>>();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        Some(Cookies(matching.into_iter().map(|c| c.cookie.clone()).collect()))
+    }
+
+    /// Remove all cookies from this store.
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    /// Write the unexpired cookies of this store in the Netscape
+    /// `cookies.txt` format, as used by curl and wget.
+    ///
+    /// Session cookies are written with an expiry of `0`, and are loaded
+    /// back as session cookies.
+    pub fn save_netscape<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        try!(w.write_str("# Netscape HTTP Cookie File\n"));
+        let now = get_time();
+        for c in self.cookies.iter().filter(|c| !c.is_expired(now)) {
+            try!(write!(w, "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                        if c.cookie.httponly { HTTP_ONLY_PREFIX } else { "" },
+                        if c.host_only { "" } else { "." }, c.domain,
+                        flag(!c.host_only), c.path, flag(c.cookie.secure),
+                        c.expires.map_or(0, |expires| expires.sec),
+                        c.cookie.name, c.cookie.value));
+        }
+        Ok(())
+    }
+
+    /// Read a store from cookies in the Netscape `cookies.txt` format.
+    ///
+    /// Blank lines and comments are skipped, as are cookies that have
+    /// expired. A line that is not a valid cookie is an `InvalidInput` error.
+    pub fn load_netscape<B: Buffer>(r: &mut B) -> IoResult<CookieStore> {
+        let mut store = CookieStore::new();
+        let now = get_time();
+        for line in r.lines() {
+            let line = try!(line);
+            let line = line[].trim_right_chars(|c: char| c == '\r' || c == '\n');
+            let (line, httponly) = if line.starts_with(HTTP_ONLY_PREFIX) {
+                (line[HTTP_ONLY_PREFIX.len()..], true)
+            } else {
+                (line, false)
+            };
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() != 7 {
+                return Err(invalid_cookie_file(line));
+            }
+            let (include_subdomains, secure) = match (parse_flag(fields[1]), parse_flag(fields[3])) {
+                (Some(include_subdomains), Some(secure)) => (include_subdomains, secure),
+                _ => return Err(invalid_cookie_file(line))
+            };
+            let expires = match from_str::<i64>(fields[4]) {
+                Some(0) => None,
+                Some(sec) => Some(Timespec::new(sec, 0)),
+                None => return Err(invalid_cookie_file(line))
+            };
+            store.insert(stored_cookie(fields[5].into_string(), fields[6].into_string(),
+                                       fields[0], !include_subdomains, fields[2].into_string(),
+                                       secure, httponly, expires), now);
+        }
+        Ok(store)
+    }
+
+    /// Write the unexpired cookies of this store as a JSON array.
+    ///
+    /// Each cookie is an object with `name`, `value`, `domain`, `host_only`,
+    /// `path`, `secure`, `http_only`, and `expires` keys, where `expires` is
+    /// in seconds since the epoch, or `null` for a session cookie.
+    #[cfg(feature = "json")]
+    pub fn save_json<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        let now = get_time();
+        let cookies = self.cookies.iter().filter(|c| !c.is_expired(now)).map(|c| JsonCookie {
+            name: c.cookie.name.clone(),
+            value: c.cookie.value.clone(),
+            domain: c.domain.clone(),
+            host_only: c.host_only,
+            path: c.path.clone(),
+            secure: c.cookie.secure,
+            http_only: c.cookie.httponly,
+            expires: c.expires.map(|expires| expires.sec),
+        }).collect::<Vec<JsonCookie>>();
+        w.write_str(json::encode(&cookies)[])
+    }
+
+    /// Read a store from cookies written by `save_json`.
+    ///
+    /// Cookies that have expired are skipped. Invalid JSON is an
+    /// `InvalidInput` error.
+    #[cfg(feature = "json")]
+    pub fn load_json<R: Reader>(r: &mut R) -> IoResult<CookieStore> {
+        let text = try!(r.read_to_string());
+        let cookies: Vec<JsonCookie> = match json::decode(text[]) {
+            Ok(cookies) => cookies,
+            Err(e) => return Err(IoError {
+                kind: InvalidInput,
+                desc: "Invalid JSON cookie file",
+                detail: Some(e.to_string())
+            })
+        };
+        let mut store = CookieStore::new();
+        let now = get_time();
+        for c in cookies.into_iter() {
+            store.insert(stored_cookie(c.name, c.value, c.domain[], c.host_only, c.path,
+                                       c.secure, c.http_only, c.expires.map(|sec| Timespec::new(sec, 0))), now);
+        }
+        Ok(store)
+    }
+}
+
+/// A cookie as it is written by `CookieStore::save_json`.
+#[cfg(feature = "json")]
+#[deriving(Encodable, Decodable)]
+struct JsonCookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    expires: Option<i64>,
+}
+
+/// Marks an HttpOnly cookie in a Netscape cookie file, as curl does.
+static HTTP_ONLY_PREFIX: &'static str = "#HttpOnly_";
+
+/// A cookie loaded from a file, rather than received in a `Set-Cookie` header.
+fn stored_cookie(name: String, value: String, domain: &str, host_only: bool, path: String,
+                 secure: bool, httponly: bool, expires: Option<Timespec>) -> StoredCookie {
+    let domain = domain.trim_left_chars('.').to_ascii_lower();
+    let mut cookie = Cookie::new(name, value);
+    cookie.secure = secure;
+    cookie.httponly = httponly;
+    if !host_only {
+        cookie.domain = Some(domain.clone());
+    }
+    cookie.path = Some(path.clone());
+    StoredCookie {
+        cookie: cookie,
+        domain: domain,
+        host_only: host_only,
+        path: path,
+        expires: expires,
+    }
+}
+
+fn flag(value: bool) -> &'static str {
+    if value { "TRUE" } else { "FALSE" }
+}
+
+fn parse_flag(s: &str) -> Option<bool> {
+    match s {
+        "TRUE" => Some(true),
+        "FALSE" => Some(false),
+        _ => None
+    }
+}
+
+fn invalid_cookie_file(line: &str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Invalid line in cookie file",
+        detail: Some(line.into_string())
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || (host.ends_with(domain) &&
+                       host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path || (path.starts_with(cookie_path) &&
+                            (cookie_path.ends_with("/") ||
+                             path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+fn default_path(url: &Url) -> String {
+    match url.serialize_path() {
+        Some(path) => match path[].rfind('/') {
+            Some(0) | None => "/".into_string(),
+            Some(i) => path[..i].into_string()
+        },
+        None => "/".into_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, MemWriter};
+    use cookie::Cookie;
+    use url::Url;
+
+    use header::common::{Cookies, SetCookie};
+    use super::CookieStore;
+
+    fn set_cookie(s: &str) -> SetCookie {
+        SetCookie(vec![from_str(s).unwrap()])
+    }
+
+    fn names(cookies: Option<Cookies>) -> Vec<String> {
+        cookies.map(|c| c.iter().map(|c| c.name.clone()).collect()).unwrap_or(vec![])
+    }
+
+    #[test]
+    fn test_host_only() {
+        let mut store = CookieStore::new();
+        store.store(&Url::parse("http://example.dom/").unwrap(), &set_cookie("a=1"));
+        assert_eq!(names(store.cookies_for(&Url::parse("http://example.dom/").unwrap())), vec!["a".into_string()]);
+        assert_eq!(store.cookies_for(&Url::parse("http://www.example.dom/").unwrap()), None);
+    }
+
+    #[test]
+    fn test_huge_max_age() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Max-Age=18446744073709551615"));
+        assert_eq!(names(store.cookies_for(&url)), vec!["a".into_string()]);
+    }
+
+    #[test]
+    fn test_domain() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://www.example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Domain=.example.dom"));
+        store.store(&url, &set_cookie("b=2; Domain=other.dom"));
+        assert_eq!(names(store.cookies_for(&Url::parse("http://example.dom/").unwrap())), vec!["a".into_string()]);
+        assert_eq!(names(store.cookies_for(&Url::parse("http://api.example.dom/").unwrap())), vec!["a".into_string()]);
+        assert_eq!(store.cookies_for(&Url::parse("http://notexample.dom/").unwrap()), None);
+    }
+
+    #[test]
+    fn test_path() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Path=/foo"));
+        store.store(&url, &set_cookie("b=2; Path=/"));
+        assert_eq!(names(store.cookies_for(&Url::parse("http://example.dom/foo/bar").unwrap())),
+                   vec!["a".into_string(), "b".into_string()]);
+        assert_eq!(names(store.cookies_for(&Url::parse("http://example.dom/foobar").unwrap())),
+                   vec!["b".into_string()]);
+    }
+
+    #[test]
+    fn test_secure() {
+        let mut store = CookieStore::new();
+        store.store(&Url::parse("https://example.dom/").unwrap(), &set_cookie("a=1; Secure"));
+        assert_eq!(store.cookies_for(&Url::parse("http://example.dom/").unwrap()), None);
+        assert!(store.cookies_for(&Url::parse("https://example.dom/").unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_replace_and_expire() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Path=/"));
+        store.store(&url, &set_cookie("a=2; Path=/"));
+        let cookies = store.cookies_for(&url).unwrap();
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].value[], "2");
+
+        store.store(&url, &set_cookie("a=2; Path=/; Max-Age=0"));
+        assert_eq!(store.cookies_for(&url), None);
+    }
+
+    #[test]
+    fn test_session_cookie_kept() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://example.dom/").unwrap();
+        store.store(&url, &SetCookie(vec![Cookie::new("a".into_string(), "1".into_string())]));
+        assert!(store.cookies_for(&url).is_some());
+        store.clear();
+        assert_eq!(store.cookies_for(&url), None);
+    }
+
+    #[test]
+    fn test_netscape_round_trip() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("https://www.example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Domain=example.dom; Max-Age=600; Secure; HttpOnly"));
+        store.store(&url, &set_cookie("b=2; Path=/foo"));
+
+        let mut w = MemWriter::new();
+        store.save_netscape(&mut w).unwrap();
+        let loaded = CookieStore::load_netscape(&mut BufReader::new(w.get_ref())).unwrap();
+        assert_eq!(names(loaded.cookies_for(&Url::parse("https://api.example.dom/").unwrap())),
+                   vec!["a".into_string()]);
+        assert_eq!(names(loaded.cookies_for(&Url::parse("http://www.example.dom/foo").unwrap())),
+                   vec!["b".into_string()]);
+        assert!(loaded.cookies[0].cookie.httponly);
+        assert!(loaded.cookies[1].expires.is_none());
+    }
+
+    #[test]
+    fn test_load_netscape() {
+        let file = b"# Netscape HTTP Cookie File\n\
+                     \n\
+                     .example.dom\tTRUE\t/\tFALSE\t0\ta\t1\n\
+                     #HttpOnly_example.dom\tFALSE\t/\tFALSE\t0\tb\t\n\
+                     example.dom\tFALSE\t/\tFALSE\t1\texpired\t3\n";
+        let store = CookieStore::load_netscape(&mut BufReader::new(file)).unwrap();
+        assert_eq!(names(store.cookies_for(&Url::parse("http://example.dom/").unwrap())),
+                   vec!["a".into_string(), "b".into_string()]);
+        assert_eq!(names(store.cookies_for(&Url::parse("http://www.example.dom/").unwrap())),
+                   vec!["a".into_string()]);
+
+        let invalid = b"example.dom\tMAYBE\t/\tFALSE\t0\ta\t1\n";
+        assert!(CookieStore::load_netscape(&mut BufReader::new(invalid)).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_round_trip() {
+        let mut store = CookieStore::new();
+        let url = Url::parse("http://example.dom/").unwrap();
+        store.store(&url, &set_cookie("a=1; Max-Age=600"));
+
+        let mut w = MemWriter::new();
+        store.save_json(&mut w).unwrap();
+        let loaded = CookieStore::load_json(&mut BufReader::new(w.get_ref())).unwrap();
+        assert_eq!(names(loaded.cookies_for(&url)), vec!["a".into_string()]);
+        assert!(CookieStore::load_json(&mut BufReader::new(b"{")).is_err());
+    }
+}
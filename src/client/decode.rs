@@ -0,0 +1,1055 @@
+//! Decoding of compressed response bodies, and compression of request
+//! bodies.
+use std::cmp;
+use std::default::Default;
+use std::io::{IoError, IoResult, EndOfFile, InvalidInput, MemWriter, standard_error};
+use std::mem;
+
+use header::common::transfer_encoding::Encoding;
+use header::common::transfer_encoding::Encoding::{Gzip, Deflate};
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// A decoder of one content-coding, used to decompress response bodies.
+pub trait ContentDecoder: Send {
+    /// Start decoding a body.
+    fn decoder(&self) -> Box<Decoder + Send>;
+}
+
+/// The decoding of one body, a piece at a time, as it is read.
+pub trait Decoder {
+    /// Read decoded bytes into `buf`, reading as much of the encoded `body`
+    /// as that takes, as `Reader::read` does.
+    ///
+    /// A body that is not validly encoded is an `InvalidInput` error, and
+    /// the end of the decoded body is an `EndOfFile` error.
+    fn read(&mut self, body: &mut Reader, buf: &mut [u8]) -> IoResult<uint>;
+}
+
+struct Gunzip;
+
+impl ContentDecoder for Gunzip {
+    fn decoder(&self) -> Box<Decoder + Send> {
+        box Inflater::new(Wrapper::Gzip) as Box<Decoder + Send>
+    }
+}
+
+struct Inflate;
+
+impl ContentDecoder for Inflate {
+    fn decoder(&self) -> Box<Decoder + Send> {
+        box Inflater::new(Wrapper::Zlib) as Box<Decoder + Send>
+    }
+}
+
+/// The content-codings that responses can be decompressed from, each with
+/// its decoder.
+///
+/// `gzip` and `deflate` are built in. Others, such as `br`, can be added
+/// with `register`.
+pub struct Decoders {
+    decoders: Vec<(Encoding, Box<ContentDecoder + Send>)>,
+}
+
+impl Decoders {
+    /// Create a new Decoders, with the built in `gzip` and `deflate`.
+    pub fn new() -> Decoders {
+        let mut decoders = Decoders { decoders: Vec::new() };
+        decoders.register(Gzip, Gunzip);
+        decoders.register(Deflate, Inflate);
+        decoders
+    }
+
+    /// Register the decoder of `coding`, replacing any it already has.
+    pub fn register<D: ContentDecoder>(&mut self, coding: Encoding, decoder: D) {
+        let decoder = box decoder as Box<ContentDecoder + Send>;
+        for entry in self.decoders.iter_mut() {
+            if entry.0 == coding {
+                entry.1 = decoder;
+                return;
+            }
+        }
+        self.decoders.push((coding, decoder));
+    }
+
+    /// Get the decoder of `coding`, if one is registered.
+    pub fn get(&self, coding: &Encoding) -> Option<&Box<ContentDecoder + Send>> {
+        self.decoders.iter().find(|&&(ref registered, _)| registered == coding)
+            .map(|&(_, ref decoder)| decoder)
+    }
+
+    /// The registered content-codings, in the order they were registered.
+    pub fn encodings(&self) -> Vec<Encoding> {
+        self.decoders.iter().map(|&(ref coding, _)| coding.clone()).collect()
+    }
+}
+
+impl Default for Decoders {
+    fn default() -> Decoders {
+        Decoders::new()
+    }
+}
+
+/// Decode a whole body with `decoder`, returning `None` if it is not validly
+/// encoded.
+#[cfg(test)]
+fn decode_all(mut decoder: Box<Decoder + Send>, data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = ::std::io::BufReader::new(data);
+    let mut decoded = Vec::new();
+    let mut buf = [0u8, ..4096];
+    loop {
+        match decoder.read(&mut body, &mut buf) {
+            Ok(count) => decoded.push_all(buf.slice_to(count)),
+            Err(ref e) if e.kind == EndOfFile => return Some(decoded),
+            Err(_) => return None
+        }
+    }
+}
+
+/// Decode a whole body with the `gzip` content-coding, as described in
+/// [RFC1952](http://tools.ietf.org/html/rfc1952).
+#[cfg(test)]
+pub fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    decode_all(Gunzip.decoder(), data)
+}
+
+/// Decode a whole body with the `deflate` content-coding.
+///
+/// This should be zlib wrapped, but some servers send raw deflate data, so
+/// that is accepted as well.
+#[cfg(test)]
+pub fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    decode_all(Inflate.decoder(), data)
+}
+
+/// Encode a whole body with the `gzip` content-coding.
+pub fn gzip(data: &[u8]) -> IoResult<Vec<u8>> {
+    let mut encoder = GzipEncoder::new(MemWriter::new());
+    try!(encoder.write(data));
+    encoder.finish().map(|w| w.unwrap())
+}
+
+/// The CRC-32 of `data`, as the trailer of gzip has.
+fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0u32, data)
+}
+
+/// Carry on the CRC-32 `crc` of what came before `data`, uncomplemented.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data.iter() {
+        crc = CRC_TABLE[((crc ^ b as u32) & 0xff) as uint] ^ (crc >> 8);
+    }
+    crc
+}
+
+// the CRC-32 of each byte value, with the polynomial 0xedb88320
+static CRC_TABLE: [u32, ..256] = [
+    0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f,
+    0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988,
+    0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2,
+    0xf3b97148, 0x84be41de, 0x1adad47d, 0x6ddde4eb, 0xf4d4b551, 0x83d385c7,
+    0x136c9856, 0x646ba8c0, 0xfd62f97a, 0x8a65c9ec, 0x14015c4f, 0x63066cd9,
+    0xfa0f3d63, 0x8d080df5, 0x3b6e20c8, 0x4c69105e, 0xd56041e4, 0xa2677172,
+    0x3c03e4d1, 0x4b04d447, 0xd20d85fd, 0xa50ab56b, 0x35b5a8fa, 0x42b2986c,
+    0xdbbbc9d6, 0xacbcf940, 0x32d86ce3, 0x45df5c75, 0xdcd60dcf, 0xabd13d59,
+    0x26d930ac, 0x51de003a, 0xc8d75180, 0xbfd06116, 0x21b4f4b5, 0x56b3c423,
+    0xcfba9599, 0xb8bda50f, 0x2802b89e, 0x5f058808, 0xc60cd9b2, 0xb10be924,
+    0x2f6f7c87, 0x58684c11, 0xc1611dab, 0xb6662d3d, 0x76dc4190, 0x01db7106,
+    0x98d220bc, 0xefd5102a, 0x71b18589, 0x06b6b51f, 0x9fbfe4a5, 0xe8b8d433,
+    0x7807c9a2, 0x0f00f934, 0x9609a88e, 0xe10e9818, 0x7f6a0dbb, 0x086d3d2d,
+    0x91646c97, 0xe6635c01, 0x6b6b51f4, 0x1c6c6162, 0x856530d8, 0xf262004e,
+    0x6c0695ed, 0x1b01a57b, 0x8208f4c1, 0xf50fc457, 0x65b0d9c6, 0x12b7e950,
+    0x8bbeb8ea, 0xfcb9887c, 0x62dd1ddf, 0x15da2d49, 0x8cd37cf3, 0xfbd44c65,
+    0x4db26158, 0x3ab551ce, 0xa3bc0074, 0xd4bb30e2, 0x4adfa541, 0x3dd895d7,
+    0xa4d1c46d, 0xd3d6f4fb, 0x4369e96a, 0x346ed9fc, 0xad678846, 0xda60b8d0,
+    0x44042d73, 0x33031de5, 0xaa0a4c5f, 0xdd0d7cc9, 0x5005713c, 0x270241aa,
+    0xbe0b1010, 0xc90c2086, 0x5768b525, 0x206f85b3, 0xb966d409, 0xce61e49f,
+    0x5edef90e, 0x29d9c998, 0xb0d09822, 0xc7d7a8b4, 0x59b33d17, 0x2eb40d81,
+    0xb7bd5c3b, 0xc0ba6cad, 0xedb88320, 0x9abfb3b6, 0x03b6e20c, 0x74b1d29a,
+    0xead54739, 0x9dd277af, 0x04db2615, 0x73dc1683, 0xe3630b12, 0x94643b84,
+    0x0d6d6a3e, 0x7a6a5aa8, 0xe40ecf0b, 0x9309ff9d, 0x0a00ae27, 0x7d079eb1,
+    0xf00f9344, 0x8708a3d2, 0x1e01f268, 0x6906c2fe, 0xf762575d, 0x806567cb,
+    0x196c3671, 0x6e6b06e7, 0xfed41b76, 0x89d32be0, 0x10da7a5a, 0x67dd4acc,
+    0xf9b9df6f, 0x8ebeeff9, 0x17b7be43, 0x60b08ed5, 0xd6d6a3e8, 0xa1d1937e,
+    0x38d8c2c4, 0x4fdff252, 0xd1bb67f1, 0xa6bc5767, 0x3fb506dd, 0x48b2364b,
+    0xd80d2bda, 0xaf0a1b4c, 0x36034af6, 0x41047a60, 0xdf60efc3, 0xa867df55,
+    0x316e8eef, 0x4669be79, 0xcb61b38c, 0xbc66831a, 0x256fd2a0, 0x5268e236,
+    0xcc0c7795, 0xbb0b4703, 0x220216b9, 0x5505262f, 0xc5ba3bbe, 0xb2bd0b28,
+    0x2bb45a92, 0x5cb36a04, 0xc2d7ffa7, 0xb5d0cf31, 0x2cd99e8b, 0x5bdeae1d,
+    0x9b64c2b0, 0xec63f226, 0x756aa39c, 0x026d930a, 0x9c0906a9, 0xeb0e363f,
+    0x72076785, 0x05005713, 0x95bf4a82, 0xe2b87a14, 0x7bb12bae, 0x0cb61b38,
+    0x92d28e9b, 0xe5d5be0d, 0x7cdcefb7, 0x0bdbdf21, 0x86d3d2d4, 0xf1d4e242,
+    0x68ddb3f8, 0x1fda836e, 0x81be16cd, 0xf6b9265b, 0x6fb077e1, 0x18b74777,
+    0x88085ae6, 0xff0f6a70, 0x66063bca, 0x11010b5c, 0x8f659eff, 0xf862ae69,
+    0x616bffd3, 0x166ccf45, 0xa00ae278, 0xd70dd2ee, 0x4e048354, 0x3903b3c2,
+    0xa7672661, 0xd06016f7, 0x4969474d, 0x3e6e77db, 0xaed16a4a, 0xd9d65adc,
+    0x40df0b66, 0x37d83bf0, 0xa9bcae53, 0xdebb9ec5, 0x47b2cf7f, 0x30b5ffe9,
+    0xbdbdf21c, 0xcabac28a, 0x53b39330, 0x24b4a3a6, 0xbad03605, 0xcdd70693,
+    0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94,
+    0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d
+];
+
+/// Carry on the Adler-32 `adler` of what came before `data`, as the trailer
+/// of zlib has.
+fn adler32_update(adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn invalid(detail: &str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Invalid compressed body",
+        detail: Some(detail.into_string())
+    }
+}
+
+/// What a deflate stream is wrapped in.
+#[deriving(PartialEq)]
+enum Wrapper {
+    /// A gzip header and trailer, as RFC1952 describes.
+    Gzip,
+    /// A zlib header and trailer, as RFC1950 describes, or none at all.
+    Zlib,
+    /// None, once a stream without a zlib header has been found.
+    Raw,
+}
+
+/// Where an `Inflater` is in a deflate stream.
+#[deriving(PartialEq)]
+enum Block {
+    /// Before the header of a block, or after the last one.
+    Header,
+    /// In a stored block, with as many bytes left.
+    Stored(uint),
+    /// In a compressed block, with its codes.
+    Codes,
+    /// Past the trailer.
+    Done,
+}
+
+// the most bits of a code
+const MAX_BITS: uint = 15;
+// the most bits of a code decoded by looking it up
+const FAST_BITS: uint = 9;
+// how far back a match may reach
+const WINDOW_SIZE: uint = 32 * 1024;
+// the most bits a length and distance, with their extra bits, take
+const MAX_SYMBOL_BITS: uint = 48;
+
+static LENGTH_BASE: [u16, ..29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+                                   43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8, ..29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3,
+                                   4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [u16, ..30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                                 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+                                 16385, 24577];
+static DIST_EXTRA: [u8, ..30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9,
+                                 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the order the lengths of the code length code are sent in
+static CODE_LENGTH_ORDER: [uint, ..19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                         14, 1, 15];
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols in the order of their codes.
+struct Huffman {
+    counts: [u16, ..MAX_BITS + 1],
+    symbols: Vec<u16>,
+    // the symbol and length, as `symbol << 4 | length`, of the code that
+    // each `FAST_BITS` of input start with, or zero if it is longer
+    fast: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code of symbols with the bit lengths `lengths`, unless it has
+    /// more codes than it has room for.
+    fn new(lengths: &[u8]) -> IoResult<Huffman> {
+        let mut counts = [0u16, ..MAX_BITS + 1];
+        for &len in lengths.iter() {
+            counts[len as uint] += 1;
+        }
+        let mut left = 1i;
+        for len in range(1, MAX_BITS + 1) {
+            left = (left << 1) - counts[len] as int;
+            if left < 0 {
+                return Err(invalid("over-subscribed code"));
+            }
+        }
+        let mut offsets = [0u16, ..MAX_BITS + 1];
+        for len in range(1, MAX_BITS) {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = Vec::from_elem(lengths.len(), 0u16);
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as uint] as uint] = symbol as u16;
+                offsets[len as uint] += 1;
+            }
+        }
+        // codes come first bit first, so each is reversed to index the table
+        let mut fast = Vec::from_elem(1 << FAST_BITS, 0u16);
+        let (mut code, mut index) = (0u, 0u);
+        for len in range(1, FAST_BITS + 1) {
+            for _ in range(0, counts[len]) {
+                let entry = symbols[index] << 4 | len as u16;
+                let mut at = reverse(code, len);
+                while at < 1 << FAST_BITS {
+                    fast[at] = entry;
+                    at += 1 << len;
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+        Ok(Huffman { counts: counts, symbols: symbols, fast: fast })
+    }
+
+    /// The codes of a block compressed with fixed codes.
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0u8, ..288];
+        for (symbol, len) in lengths.iter_mut().enumerate() {
+            *len = match symbol {
+                0...143 => 8,
+                144...255 => 9,
+                256...279 => 7,
+                _ => 8
+            };
+        }
+        (Huffman::new(lengths.as_slice()).unwrap(), Huffman::new([5u8, ..30].as_slice()).unwrap())
+    }
+}
+
+/// Decompresses a deflate stream, as described in
+/// [RFC1951](http://tools.ietf.org/html/rfc1951), as it is read, keeping
+/// no more of it in memory than a match may copy from.
+struct Inflater {
+    wrapper: Wrapper,
+    started: bool,
+    block: Block,
+    last: bool,
+    // input read from the body but not yet used
+    input: [u8, ..4096],
+    pos: uint,
+    len: uint,
+    // bits taken from the input but not yet used, from the lowest
+    bits: u64,
+    nbits: uint,
+    // the codes of the current block
+    lit: Option<Huffman>,
+    dist: Option<Huffman>,
+    // the bytes decoded last, which a match copies from
+    window: Vec<u8>,
+    total: u64,
+    // what is left to copy of the current match, and from how far back
+    copy: (uint, uint),
+    // the checksum of the trailer, carried on over what is decoded
+    check: u32,
+}
+
+impl Inflater {
+    fn new(wrapper: Wrapper) -> Inflater {
+        Inflater {
+            check: if wrapper == Wrapper::Gzip { !0u32 } else { 1 },
+            wrapper: wrapper,
+            started: false,
+            block: Block::Header,
+            last: false,
+            input: [0u8, ..4096],
+            pos: 0,
+            len: 0,
+            bits: 0,
+            nbits: 0,
+            lit: None,
+            dist: None,
+            window: Vec::from_elem(WINDOW_SIZE, 0u8),
+            total: 0,
+            copy: (0, 0),
+        }
+    }
+
+    /// Read more of the body into the input, which must all have been used.
+    fn fill(&mut self, body: &mut Reader) -> IoResult<()> {
+        self.pos = 0;
+        self.len = 0;
+        match body.read(&mut self.input) {
+            Ok(count) => {
+                self.len = count;
+                Ok(())
+            },
+            Err(ref e) if e.kind == EndOfFile => Err(invalid("truncated")),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// The next byte of the body, after any whole bytes of bits left.
+    fn byte(&mut self, body: &mut Reader) -> IoResult<u8> {
+        if self.nbits >= 8 {
+            let byte = self.bits as u8;
+            self.bits >>= 8;
+            self.nbits -= 8;
+            return Ok(byte);
+        }
+        while self.pos == self.len {
+            try!(self.fill(body));
+        }
+        self.pos += 1;
+        Ok(self.input[self.pos - 1])
+    }
+
+    /// The next `n` bits of the body.
+    fn bits(&mut self, body: &mut Reader, n: uint) -> IoResult<uint> {
+        while self.nbits < n {
+            while self.pos == self.len {
+                try!(self.fill(body));
+            }
+            self.bits |= (self.input[self.pos] as u64) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+        let value = (self.bits & ((1 << n) - 1)) as uint;
+        self.bits >>= n;
+        self.nbits -= n;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary, as stored blocks and trailers start at.
+    fn align(&mut self) {
+        let skip = self.nbits % 8;
+        self.bits >>= skip;
+        self.nbits -= skip;
+    }
+
+    /// Whether a whole symbol can be decoded without reading the body.
+    fn has_symbol(&self) -> bool {
+        self.nbits + (self.len - self.pos) * 8 >= MAX_SYMBOL_BITS
+    }
+
+    /// The next symbol of the body, as coded by `code`.
+    fn symbol(&mut self, body: &mut Reader, code: &Huffman) -> IoResult<uint> {
+        while self.nbits <= 56 && self.pos < self.len {
+            self.bits |= (self.input[self.pos] as u64) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+        let entry = code.fast[(self.bits & ((1 << FAST_BITS) - 1)) as uint];
+        let len = (entry & 0xf) as uint;
+        if len > 0 && len <= self.nbits {
+            self.bits >>= len;
+            self.nbits -= len;
+            return Ok((entry >> 4) as uint);
+        }
+        // a longer code, or one near the end of the body, a bit at a time
+        let (mut code_bits, mut first, mut index) = (0i, 0i, 0i);
+        for len in range(1, MAX_BITS + 1) {
+            code_bits |= try!(self.bits(body, 1)) as int;
+            let count = code.counts[len] as int;
+            if code_bits - first < count {
+                return Ok(code.symbols[(index + code_bits - first) as uint] as uint);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code_bits <<= 1;
+        }
+        Err(invalid("unknown code"))
+    }
+
+    /// Keep `byte` as the latest decoded, and carry on the checksum with it.
+    fn push(&mut self, byte: u8) {
+        let at = (self.total % WINDOW_SIZE as u64) as uint;
+        self.window[at] = byte;
+        self.total += 1;
+        self.check = match self.wrapper {
+            Wrapper::Gzip => crc32_update(self.check, &[byte]),
+            Wrapper::Zlib => adler32_update(self.check, &[byte]),
+            Wrapper::Raw => self.check
+        };
+    }
+
+    /// Read the codes of a block compressed with dynamic codes.
+    fn read_codes(&mut self, body: &mut Reader) -> IoResult<()> {
+        let nlen = try!(self.bits(body, 5)) + 257;
+        let ndist = try!(self.bits(body, 5)) + 1;
+        let ncode = try!(self.bits(body, 4)) + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(invalid("too many codes"));
+        }
+        let mut lengths = [0u8, ..19];
+        for &symbol in CODE_LENGTH_ORDER.slice_to(ncode).iter() {
+            lengths[symbol] = try!(self.bits(body, 3)) as u8;
+        }
+        let length_code = try!(Huffman::new(lengths.as_slice()));
+
+        let mut lengths = Vec::from_elem(nlen + ndist, 0u8);
+        let mut index = 0;
+        while index < nlen + ndist {
+            let symbol = try!(self.symbol(body, &length_code));
+            if symbol < 16 {
+                lengths[index] = symbol as u8;
+                index += 1;
+                continue;
+            }
+            let (len, repeat) = match symbol {
+                16 if index == 0 => return Err(invalid("repeat with no length")),
+                16 => (lengths[index - 1], 3 + try!(self.bits(body, 2))),
+                17 => (0, 3 + try!(self.bits(body, 3))),
+                _ => (0, 11 + try!(self.bits(body, 7)))
+            };
+            if index + repeat > nlen + ndist {
+                return Err(invalid("too many lengths"));
+            }
+            for _ in range(0, repeat) {
+                lengths[index] = len;
+                index += 1;
+            }
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("no end of block code"));
+        }
+        self.lit = Some(try!(Huffman::new(lengths[..nlen])));
+        self.dist = Some(try!(Huffman::new(lengths[nlen..])));
+        Ok(())
+    }
+
+    /// Read the header of the next block, or the trailer after the last.
+    fn read_block_header(&mut self, body: &mut Reader) -> IoResult<()> {
+        if self.last {
+            self.align();
+            try!(self.read_trailer(body));
+            self.block = Block::Done;
+            return Ok(());
+        }
+        self.last = try!(self.bits(body, 1)) == 1;
+        match try!(self.bits(body, 2)) {
+            0 => {
+                self.align();
+                let len = try!(self.byte(body)) as uint | (try!(self.byte(body)) as uint << 8);
+                let nlen = try!(self.byte(body)) as uint | (try!(self.byte(body)) as uint << 8);
+                if len != !nlen & 0xffff {
+                    return Err(invalid("stored block length"));
+                }
+                self.block = Block::Stored(len);
+            },
+            1 => {
+                let (lit, dist) = Huffman::fixed();
+                self.lit = Some(lit);
+                self.dist = Some(dist);
+                self.block = Block::Codes;
+            },
+            2 => {
+                try!(self.read_codes(body));
+                self.block = Block::Codes;
+            },
+            _ => return Err(invalid("block type"))
+        }
+        Ok(())
+    }
+
+    /// Decode the next symbol of a compressed block.
+    fn read_symbol(&mut self, body: &mut Reader) -> IoResult<()> {
+        let lit = self.lit.take().unwrap();
+        let symbol = self.symbol(body, &lit);
+        self.lit = Some(lit);
+        let symbol = try!(symbol);
+        if symbol < 256 {
+            self.copy = (1, 0);
+            self.push(symbol as u8);
+            return Ok(());
+        }
+        if symbol == 256 {
+            self.block = Block::Header;
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= 29 {
+            return Err(invalid("length code"));
+        }
+        let len = LENGTH_BASE[symbol] as uint + try!(self.bits(body, LENGTH_EXTRA[symbol] as uint));
+        let dist = self.dist.take().unwrap();
+        let symbol = self.symbol(body, &dist);
+        self.dist = Some(dist);
+        let symbol = try!(symbol);
+        if symbol >= 30 {
+            return Err(invalid("distance code"));
+        }
+        let dist = DIST_BASE[symbol] as uint + try!(self.bits(body, DIST_EXTRA[symbol] as uint));
+        if dist as u64 > self.total {
+            return Err(invalid("distance too far back"));
+        }
+        // the literal of the symbol is the next byte out
+        self.copy = (len, dist);
+        Ok(())
+    }
+
+    /// Read the header before the deflate stream.
+    fn read_header(&mut self, body: &mut Reader) -> IoResult<()> {
+        match self.wrapper {
+            Wrapper::Gzip => {
+                // ID1 ID2 CM FLG MTIME(4) XFL OS
+                let mut header = [0u8, ..10];
+                for b in header.iter_mut() {
+                    *b = try!(self.byte(body));
+                }
+                if header[0] != 0x1f || header[1] != 0x8b || header[2] != 8 {
+                    return Err(invalid("not gzip"));
+                }
+                let flags = header[3];
+                if flags & FEXTRA != 0 {
+                    let xlen = try!(self.byte(body)) as uint | (try!(self.byte(body)) as uint << 8);
+                    for _ in range(0, xlen) {
+                        try!(self.byte(body));
+                    }
+                }
+                for &flag in [FNAME, FCOMMENT].iter() {
+                    if flags & flag != 0 {
+                        while try!(self.byte(body)) != 0 {}
+                    }
+                }
+                if flags & FHCRC != 0 {
+                    try!(self.byte(body));
+                    try!(self.byte(body));
+                }
+            },
+            Wrapper::Zlib => {
+                let cmf = try!(self.byte(body)) as uint;
+                let flg = try!(self.byte(body)) as uint;
+                if cmf & 0x0f != 8 || (cmf << 8 | flg) % 31 != 0 {
+                    // they are the start of the deflate stream instead
+                    debug!("deflate body without a zlib header");
+                    self.bits = (cmf | flg << 8) as u64;
+                    self.nbits = 16;
+                    self.wrapper = Wrapper::Raw;
+                }
+            },
+            Wrapper::Raw => ()
+        }
+        Ok(())
+    }
+
+    /// Read the trailer after the deflate stream, and check it.
+    fn read_trailer(&mut self, body: &mut Reader) -> IoResult<()> {
+        match self.wrapper {
+            Wrapper::Gzip => {
+                let mut trailer = [0u32, ..2];
+                for word in trailer.iter_mut() {
+                    for shift in range(0, 4u) {
+                        *word |= (try!(self.byte(body)) as u32) << (shift * 8);
+                    }
+                }
+                if trailer[0] != !self.check || trailer[1] != self.total as u32 {
+                    return Err(invalid("gzip trailer mismatch"));
+                }
+            },
+            Wrapper::Zlib => {
+                let mut adler = 0u32;
+                for _ in range(0, 4u) {
+                    adler = adler << 8 | try!(self.byte(body)) as u32;
+                }
+                if adler != self.check {
+                    return Err(invalid("zlib trailer mismatch"));
+                }
+            },
+            Wrapper::Raw => ()
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for Inflater {
+    fn read(&mut self, body: &mut Reader, buf: &mut [u8]) -> IoResult<uint> {
+        if !self.started {
+            try!(self.read_header(body));
+            self.started = true;
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            let (len, dist) = self.copy;
+            if len > 0 {
+                // a literal is pushed already, and a match copied a byte at a time
+                if dist > 0 {
+                    let at = ((self.total - dist as u64) % WINDOW_SIZE as u64) as uint;
+                    let byte = self.window[at];
+                    self.push(byte);
+                }
+                let at = ((self.total - 1) % WINDOW_SIZE as u64) as uint;
+                buf[count] = self.window[at];
+                count += 1;
+                self.copy = (len - 1, dist);
+                continue;
+            }
+            // what is decoded already is given back rather than wait for more
+            if count > 0 && !self.has_symbol() {
+                break;
+            }
+            match self.block {
+                Block::Header => try!(self.read_block_header(body)),
+                Block::Stored(0) => self.block = Block::Header,
+                Block::Stored(left) => {
+                    let byte = try!(self.byte(body));
+                    self.push(byte);
+                    self.copy = (1, 0);
+                    self.block = Block::Stored(left - 1);
+                },
+                Block::Codes => try!(self.read_symbol(body)),
+                Block::Done => break
+            }
+        }
+        if count == 0 && self.block == Block::Done {
+            return Err(standard_error(EndOfFile));
+        }
+        Ok(count)
+    }
+}
+
+// the fewest bytes a match is made of
+const MIN_MATCH: uint = 3;
+// the most bytes a match is made of
+const MAX_MATCH: uint = 258;
+// the bits of the hash of the first bytes of a match
+const HASH_BITS: uint = 15;
+// the most earlier positions a match is looked for at
+const MAX_CHAIN: uint = 64;
+
+/// A `Writer` that compresses what is written to it with the `gzip`
+/// content-coding as it goes, such as to send a request body chunked.
+///
+/// Repeats are found within a window as large as deflate allows, and sent
+/// in one block with the fixed codes. `finish` must be called to write the
+/// end of the stream.
+pub struct GzipEncoder<W> {
+    inner: W,
+    // what has been written, from a window back from what is left to
+    // compress
+    data: Vec<u8>,
+    // the position in the stream of the first of `data`
+    offset: uint,
+    // the position in the stream of the first byte left to compress
+    done: uint,
+    // the latest position of each hash, plus one, or zero for none
+    head: Vec<uint>,
+    // the position before each in the window with the same hash, plus one
+    prev: Vec<uint>,
+    bits: u64,
+    nbits: uint,
+    out: Vec<u8>,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Writer> GzipEncoder<W> {
+    /// Create a new GzipEncoder, writing the compressed stream to `inner`.
+    pub fn new(inner: W) -> GzipEncoder<W> {
+        let mut encoder = GzipEncoder {
+            inner: inner,
+            data: Vec::new(),
+            offset: 0,
+            done: 0,
+            head: Vec::from_elem(1 << HASH_BITS, 0u),
+            prev: Vec::from_elem(WINDOW_SIZE, 0u),
+            bits: 0,
+            nbits: 0,
+            // no mtime, flags, or name, and an unknown OS
+            out: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff],
+            crc: !0u32,
+            size: 0,
+        };
+        // the only block, so the last, with the fixed codes
+        encoder.put_bits(1, 1);
+        encoder.put_bits(1, 2);
+        encoder
+    }
+
+    /// Write the end of the stream, returning the inner Writer.
+    pub fn finish(mut self) -> IoResult<W> {
+        self.compress();
+        self.put_symbol(256);
+        if self.nbits > 0 {
+            self.put_bits(0, 8 - self.nbits);
+        }
+        let (crc, size) = (!self.crc, self.size);
+        for word in [crc, size].iter() {
+            self.out.push_all([*word as u8, (*word >> 8) as u8, (*word >> 16) as u8,
+                               (*word >> 24) as u8][]);
+        }
+        try!(self.inner.write(self.out[]));
+        try!(self.inner.flush());
+        Ok(self.inner)
+    }
+
+    fn put_bits(&mut self, value: uint, n: uint) {
+        self.bits |= (value as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Put a literal or length symbol, with its fixed code.
+    fn put_symbol(&mut self, symbol: uint) {
+        let (code, len) = match symbol {
+            0...143 => (0x30 + symbol, 8),
+            144...255 => (0x190 + symbol - 144, 9),
+            256...279 => (symbol - 256, 7),
+            _ => (0xc0 + symbol - 280, 8)
+        };
+        // codes are sent from their highest bit
+        self.put_bits(reverse(code, len), len);
+    }
+
+    fn put_match(&mut self, len: uint, dist: uint) {
+        let i = LENGTH_BASE.iter().rposition(|&base| base as uint <= len).unwrap();
+        self.put_symbol(257 + i);
+        self.put_bits(len - LENGTH_BASE[i] as uint, LENGTH_EXTRA[i] as uint);
+        let i = DIST_BASE.iter().rposition(|&base| base as uint <= dist).unwrap();
+        self.put_bits(reverse(i, 5), 5);
+        self.put_bits(dist - DIST_BASE[i] as uint, DIST_EXTRA[i] as uint);
+    }
+
+    fn hash(&self, at: uint) -> uint {
+        let bytes = self.data[at - self.offset..at - self.offset + MIN_MATCH];
+        ((bytes[0] as uint << 10) ^ (bytes[1] as uint << 5) ^ bytes[2] as uint) & ((1 << HASH_BITS) - 1)
+    }
+
+    /// Remember the position `at`, for later matches to find.
+    fn insert(&mut self, at: uint) {
+        if at + MIN_MATCH > self.offset + self.data.len() {
+            return;
+        }
+        let hash = self.hash(at);
+        self.prev[at % WINDOW_SIZE] = self.head[hash];
+        self.head[hash] = at + 1;
+    }
+
+    /// The longest earlier match of the bytes at `at`, as its length and
+    /// distance.
+    fn longest_match(&self, at: uint) -> (uint, uint) {
+        let end = self.offset + self.data.len();
+        if at + MIN_MATCH > end {
+            return (0, 0);
+        }
+        let max = cmp::min(MAX_MATCH, end - at);
+        let here = self.data[at - self.offset..at - self.offset + max];
+        let (mut best, mut best_dist) = (0, 0);
+        let mut candidate = self.head[self.hash(at)];
+        let mut chain = 0;
+        // positions only go back, as an older one may have been overwritten
+        while candidate > 0 && candidate - 1 < at && chain < MAX_CHAIN {
+            let from = candidate - 1;
+            if at - from > WINDOW_SIZE || from < self.offset {
+                break;
+            }
+            let there = self.data[from - self.offset..];
+            let len = here.iter().zip(there.iter()).take_while(|&(a, b)| a == b).count();
+            if len > best {
+                best = len;
+                best_dist = at - from;
+                if len == max {
+                    break;
+                }
+            }
+            let next = self.prev[from % WINDOW_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain += 1;
+        }
+        (best, best_dist)
+    }
+
+    /// Compress all that has been written, keeping a window of it for later
+    /// matches.
+    fn compress(&mut self) {
+        let end = self.offset + self.data.len();
+        while self.done < end {
+            let at = self.done;
+            let (len, dist) = self.longest_match(at);
+            if len >= MIN_MATCH {
+                self.put_match(len, dist);
+                for pos in range(at, at + len) {
+                    self.insert(pos);
+                }
+                self.done += len;
+            } else {
+                let byte = self.data[at - self.offset];
+                self.put_symbol(byte as uint);
+                self.insert(at);
+                self.done += 1;
+            }
+        }
+        if self.data.len() > 2 * WINDOW_SIZE {
+            let passed = self.data.len() - WINDOW_SIZE;
+            let window = self.data[passed..].to_vec();
+            self.data = window;
+            self.offset += passed;
+        }
+    }
+}
+
+impl<W: Writer> Writer for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.crc = crc32_update(self.crc, buf);
+        self.size += buf.len() as u32;
+        self.data.push_all(buf);
+        self.compress();
+        let out = mem::replace(&mut self.out, Vec::new());
+        self.inner.write(out[])
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// The lowest `n` bits of `code`, in reverse order.
+fn reverse(code: uint, n: uint) -> uint {
+    let mut reversed = 0;
+    for i in range(0, n) {
+        reversed |= ((code >> i) & 1) << (n - 1 - i);
+    }
+    reversed
+}
+
+#[cfg(test)]
+mod tests {
+    use header::common::transfer_encoding::Encoding::{Gzip, Deflate, EncodingExt};
+    use mock::MockReverse;
+    use std::io::MemWriter;
+
+    use super::{crc32, decode_all, gunzip, gzip, inflate, Decoders, GzipEncoder};
+
+    #[test]
+    fn test_decoders_register() {
+        let mut decoders = Decoders::new();
+        assert!(decoders.get(&EncodingExt("br".into_string())).is_none());
+        decoders.register(EncodingExt("br".into_string()), MockReverse);
+        assert_eq!(decoders.encodings(), vec![Gzip, Deflate, EncodingExt("br".into_string())]);
+        let br = decoders.get(&EncodingExt("br".into_string())).unwrap();
+        assert_eq!(decode_all(br.decoder(), b"olleh"), Some(b"hello".to_vec()));
+
+        decoders.register(Gzip, MockReverse);
+        assert_eq!(decoders.encodings().len(), 3);
+        assert_eq!(decode_all(decoders.get(&Gzip).unwrap().decoder(), b"ab"), Some(b"ba".to_vec()));
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xf3\x48\xcd\xc9\xc9\xd7\x51\x08\
+                     \xcf\x2f\xca\x49\x51\x04\x00\xd0\xc3\x4a\xec\x0d\x00\x00\x00";
+        assert_eq!(gunzip(data), Some(b"Hello, World!".to_vec()));
+    }
+
+    #[test]
+    fn test_gzip() {
+        assert_eq!(crc32(b"Hello, World!"), 0xec4ac3d0);
+        assert_eq!(gunzip(gzip(b"Hello, World!").unwrap()[]), Some(b"Hello, World!".to_vec()));
+        // longer than the window, so matches reach back across it
+        let long = Vec::from_fn(100_000, |i| (i % 251) as u8);
+        assert_eq!(gunzip(gzip(long[]).unwrap()[]), Some(long));
+    }
+
+    #[test]
+    fn test_gzip_encoder() {
+        let mut encoder = GzipEncoder::new(MemWriter::new());
+        let mut sent = Vec::new();
+        for i in range(0u, 50) {
+            let piece = format!("piece {} of a body written a piece at a time, ", i % 7);
+            encoder.write(piece.as_bytes()).unwrap();
+            sent.push_all(piece.as_bytes());
+        }
+        let encoded = encoder.finish().unwrap().unwrap();
+        assert!(encoded.len() < sent.len() / 4);
+        assert_eq!(gunzip(encoded[]), Some(sent));
+        assert_eq!(gunzip(gzip(b"").unwrap()[]), Some(vec![]));
+    }
+
+    #[test]
+    fn test_gunzip_invalid() {
+        assert_eq!(gunzip(b"Hello, World!"), None);
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xf3\x48\xcd\xc9\xc9\xd7\x51\x08";
+        assert_eq!(gunzip(data), None);
+    }
+
+    #[test]
+    fn test_inflate_zlib() {
+        let data = b"\x78\x9c\xf3\x48\xcd\xc9\xc9\xd7\x51\x08\xcf\x2f\xca\x49\x51\x04\x00\
+                     \x1f\x9e\x04\x6a";
+        assert_eq!(inflate(data), Some(b"Hello, World!".to_vec()));
+    }
+
+    /// The body the streams below were compressed from by zlib.
+    fn lines(n: uint) -> Vec<u8> {
+        let mut lines = Vec::new();
+        for i in range(0, n) {
+            lines.push_all(format!("line {}: the quick brown fox jumps over the lazy dog\n", i)
+                           .as_bytes());
+        }
+        lines
+    }
+
+    #[test]
+    fn test_inflate_stored() {
+        let data = b"\x78\x01\x01\x0d\x00\xf2\xff\x48\x65\x6c\x6c\x6f\x2c\x20\x57\x6f\
+                     \x72\x6c\x64\x21\x1f\x9e\x04\x6a";
+        assert_eq!(inflate(data), Some(b"Hello, World!".to_vec()));
+    }
+
+    #[test]
+    fn test_inflate_dynamic() {
+        let data = b"\x78\xda\x9d\xd6\x4d\x12\xc1\x40\x10\x40\xe1\xbd\x53\xf4\x11\xf4\
+                     \x0f\xc2\x6d\x88\x41\x92\x91\x21\x44\x70\x7a\xc5\x0d\xbc\xf5\xd4\
+                     \x5b\xf5\x57\xdd\x93\x9b\x3e\xc9\x7c\x23\xf7\x53\x92\xeb\xd8\xd4\
+                     \x9d\xec\x86\x32\xf5\x72\x28\x4f\x69\xc7\xf3\xe5\x26\xe5\x91\x86\
+                     \xdf\x73\xde\xbe\x5f\xb2\x2f\xc7\x59\xfe\x36\x0a\x1a\x03\x8d\x83\
+                     \x26\x40\xb3\x00\xcd\x12\x34\x2b\xd0\x54\xa0\x59\x93\x99\x22\x08\
+                     \x44\x82\x12\x0a\x4a\x2c\x28\xc1\xa0\x44\x83\x12\x0e\x4a\x3c\x28\
+                     \x01\xa1\x44\x84\x11\x11\x86\x76\x03\x11\x61\x44\x84\x11\x11\x46\
+                     \x44\x18\x11\x61\x44\x84\x11\x11\x46\x44\x38\x11\xe1\x44\x84\xa3\
+                     \x73\x41\x44\x38\x11\xe1\x44\x84\x13\x11\x4e\x44\x38\x11\xe1\x44\
+                     \x44\x10\x11\x41\x44\x04\x11\x11\xe8\x07\x41\x44\x04\x11\x11\x44\
+                     \x44\x10\x11\x41\x44\xc4\x9f\x22\x3e\x18\x34\x9d\x8d";
+        assert_eq!(inflate(data), Some(lines(50)));
+    }
+
+    #[test]
+    fn test_gunzip_blocks() {
+        // flushed after the first lines, with a sync flush, and after the
+        // greeting, with a full flush
+        let data = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x9c\xd2\x5d\x16\x42\x50\
+                     \x18\x85\xe1\x7b\xa3\xf8\x86\x60\x4b\x3f\x9a\x8d\x38\x4a\x0e\x27\
+                     \x0a\x65\xf4\x96\x66\xe0\xbd\xde\xeb\xbd\xda\x8f\xaf\x3b\x67\xf1\
+                     \xd5\x3e\x0f\x67\xfd\x58\x17\x8d\xdd\x86\x30\x77\x56\x85\xaf\x3d\
+                     \xc7\xf6\xf5\xb6\x30\xb9\xe1\x3f\xfb\x7c\xf9\x59\x19\xee\x91\xdf\
+                     \x1a\x81\x26\x01\xcd\x01\x34\x29\x68\x8e\xa0\x39\x81\xe6\x0c\x9a\
+                     \x0b\x68\x32\xf2\x29\x82\x40\x24\x88\x50\x10\xb1\x20\x82\x41\x44\
+                     \x83\x08\x07\x11\x0f\x22\x20\xb4\x53\xc4\x0a\x00\x00\xff\xff\xf2\
+                     \x48\xcd\xc9\xc9\xd7\x51\x08\xcf\x2f\xca\x49\x51\x04\x00\x00\x00\
+                     \xff\xff\x9d\xd2\x5d\x16\x42\x50\x18\x85\xe1\x7b\xa3\xf8\x86\x60\
+                     \x4b\x3f\x9a\x8d\x38\x4a\x0e\x27\x0a\x65\xf4\x96\x66\xe0\xbd\xde\
+                     \xeb\xbd\xda\x8f\xaf\x3b\x67\xf1\xd5\x3e\x0f\x67\xfd\x58\x17\x8d\
+                     \xdd\x86\x30\x77\x56\x85\xaf\x3d\xc7\xf6\xf5\xb6\x30\xb9\xe1\x3f\
+                     \xfb\x7c\xf9\x59\x19\xee\x91\xdf\x1a\x81\x26\x01\xcd\x01\x34\x29\
+                     \x68\x8e\xa0\x39\x81\xe6\x0c\x9a\x0b\x68\x32\xf2\x29\x82\x40\x24\
+                     \x88\x50\x10\xb1\x20\x82\x41\x44\x83\x08\x07\x11\x0f\x22\x20\xb4\
+                     \x53\xc4\x0a\xe2\xd2\x96\xc7\x41\x08\x00\x00";
+        let mut body = lines(20);
+        body.push_all(b"Hello, World!");
+        body.push_all(lines(20)[]);
+        assert_eq!(gunzip(data), Some(body));
+    }
+
+    #[test]
+    fn test_inflate_raw() {
+        let data = b"\xf3\x48\xcd\xc9\xc9\xd7\x51\x08\xcf\x2f\xca\x49\x51\x04\x00";
+        assert_eq!(inflate(data), Some(b"Hello, World!".to_vec()));
+    }
+}
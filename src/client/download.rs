@@ -0,0 +1,188 @@
+//! Resumable Downloads
+use std::io::{File, FileAccess, FileMode};
+use std::io::util::copy;
+
+use header::Headers;
+use header::common::{AcceptEncoding, ContentRange, Etag, Range};
+use header::common::range::ByteRange::AllFrom;
+use header::common::transfer_encoding::Encoding::EncodingExt;
+use net::{NetworkConnector, NetworkStream};
+use status::StatusClass::Success;
+use status::StatusCode::{PartialContent, RequestedRangeNotSatisfiable};
+use client::{Client, IntoUrl, Response};
+use HttpResult;
+use HttpError::HttpStatusError;
+
+use self::Step::{Append, Replace, Complete, Restart};
+
+/// The outcome of a `Client::download`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Download {
+    /// The length of the downloaded file.
+    pub len: u64,
+    /// Whether a partial file was resumed, instead of being downloaded
+    /// again from the start.
+    pub resumed: bool,
+    /// The `Etag` of the file, to pass to a later download resuming it.
+    pub etag: Option<Etag>,
+}
+
+/// What to do with the file, given a response.
+enum Step {
+    /// The response is the rest of the file.
+    Append,
+    /// The response is the whole file.
+    Replace,
+    /// The file was already whole.
+    Complete,
+    /// The file cannot be resumed, and must be asked for again.
+    Restart,
+}
+
+impl<C: NetworkConnector<S>, S: NetworkStream> Client<C> {
+    /// Download `url` to the file at `path`, resuming the file if part of
+    /// it is already there.
+    ///
+    /// When the file is not empty, only the rest of it is asked for, with a
+    /// `Range` header. A partial response is appended to the file if its
+    /// `Content-Range` starts where the file ends, and, when an `etag` is
+    /// given, its `Etag` strongly matches it. Otherwise, the file is
+    /// downloaded again from the start.
+    pub fn download<U: IntoUrl>(&mut self, url: U, path: &Path, etag: Option<&Etag>) -> HttpResult<Download> {
+        let url = try!(self.resolve_url(url));
+        let mut file = try!(File::open_mode(path, FileMode::Append, FileAccess::Write));
+        let mut offset = try!(file.stat()).size;
+        loop {
+            let mut headers = Headers::new();
+            // ranges count the bytes as sent, so they must not be encoded
+            headers.set(AcceptEncoding(vec![EncodingExt("identity".into_string())]));
+            if offset > 0 {
+                headers.set(Range(vec![AllFrom(offset)]));
+            }
+            let mut res = try!(self.get(url.clone()).headers(headers).send());
+
+            let step = match res.status {
+                PartialContent if offset > 0 => match res.headers.get::<ContentRange>() {
+                    Some(&ContentRange { range: Some((first, _)), .. })
+                        if first == offset && strong_match(etag, &res) => Append,
+                    _ => Restart
+                },
+                RequestedRangeNotSatisfiable if offset > 0 => match res.headers.get::<ContentRange>() {
+                    Some(&ContentRange { instance_length: Some(len), .. })
+                        if len == offset => Complete,
+                    _ => Restart
+                },
+                status if status.class() == Success => Replace,
+                status => {
+                    debug!("download of {} failed with {}", url, status);
+                    return Err(HttpStatusError);
+                }
+            };
+            debug!("download of {} from {}: {}", url, offset, res.status);
+
+            let etag = match step {
+                Complete => etag.map(|etag| etag.clone()),
+                _ => res.headers.get::<Etag>().map(|etag| etag.clone())
+            };
+            match step {
+                Append => try!(copy(&mut res, &mut file)),
+                Replace => {
+                    try!(file.truncate(0));
+                    try!(copy(&mut res, &mut file));
+                },
+                Complete => (),
+                Restart => {
+                    try!(file.truncate(0));
+                    offset = 0;
+                    continue;
+                }
+            }
+            return Ok(Download {
+                len: try!(file.stat()).size,
+                resumed: match step { Append | Complete => true, _ => false },
+                etag: etag,
+            });
+        }
+    }
+}
+
+/// Whether the response is of the representation with the `etag` given,
+/// if one was given, using the strong comparison ranges require.
+fn strong_match(etag: Option<&Etag>, res: &Response) -> bool {
+    match etag {
+        Some(etag) => match res.headers.get::<Etag>() {
+            Some(other) => !etag.weak && !other.weak && etag.tag == other.tag,
+            None => false
+        },
+        None => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{File, TempDir};
+
+    use header::common::Etag;
+    use client::Client;
+
+    mock_connector!(MockDownload {
+        "http://127.0.0.1" =>       "HTTP/1.1 206 Partial Content\r\n\
+                                     Content-Range: bytes 5-10/11\r\n\
+                                     Content-Length: 6\r\n\
+                                     Etag: \"abc\"\r\n\
+                                     \r\n\
+                                     \x20world"
+        "http://127.0.0.2" =>       "HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 11\r\n\
+                                     \r\n\
+                                     hello world"
+        "http://127.0.0.3" =>       "HTTP/1.1 416 Requested Range Not Satisfiable\r\n\
+                                     Content-Range: bytes */5\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    fn partial(dir: &TempDir, contents: &[u8]) -> Path {
+        let path = dir.path().join("partial");
+        File::create(&path).unwrap().write(contents).unwrap();
+        path
+    }
+
+    fn etag() -> Etag {
+        Etag { weak: false, tag: "abc".into_string() }
+    }
+
+    #[test]
+    fn test_download_resumes() {
+        let dir = TempDir::new("hyper-download").unwrap();
+        let path = partial(&dir, b"hello");
+        let mut client = Client::with_connector(MockDownload);
+        let download = client.download("http://127.0.0.1", &path, Some(&etag())).unwrap();
+        assert!(download.resumed);
+        assert_eq!(download.len, 11);
+        assert_eq!(download.etag, Some(etag()));
+        assert_eq!(File::open(&path).unwrap().read_to_end().unwrap(), b"hello world".to_vec());
+    }
+
+    #[test]
+    fn test_download_replaces_whole_response() {
+        let dir = TempDir::new("hyper-download").unwrap();
+        let path = partial(&dir, b"stale contents");
+        let mut client = Client::with_connector(MockDownload);
+        let download = client.download("http://127.0.0.2", &path, None).unwrap();
+        assert!(!download.resumed);
+        assert_eq!(File::open(&path).unwrap().read_to_end().unwrap(), b"hello world".to_vec());
+    }
+
+    #[test]
+    fn test_download_already_complete() {
+        let dir = TempDir::new("hyper-download").unwrap();
+        let path = partial(&dir, b"hello");
+        let mut client = Client::with_connector(MockDownload);
+        let download = client.download("http://127.0.0.3", &path, Some(&etag())).unwrap();
+        assert!(download.resumed);
+        assert_eq!(download.len, 5);
+        assert_eq!(File::open(&path).unwrap().read_to_end().unwrap(), b"hello".to_vec());
+    }
+}
@@ -0,0 +1,167 @@
+//! Client Strict Transport Security
+use std::ascii::OwnedAsciiExt;
+use std::collections::HashMap;
+use std::io::net::ip::IpAddr;
+
+use time::{Timespec, get_time};
+use url::Url;
+
+use header::common::StrictTransportSecurity;
+use super::expires_after;
+
+/// A store of hosts that have asked a `Client` to only reach them over TLS.
+///
+/// Hosts are stored following
+/// [RFC6797](http://tools.ietf.org/html/rfc6797#section-8.1): only from
+/// `Strict-Transport-Security` headers received over `https`, and only for
+/// host names, not IP addresses. Until its policy expires, `http` urls for
+/// a stored host, and for its subdomains if the policy includes them, are
+/// upgraded to `https`.
+#[deriving(Clone, Default)]
+pub struct HstsStore {
+    hosts: HashMap<String, HstsPolicy>,
+}
+
+#[deriving(Clone)]
+struct HstsPolicy {
+    include_subdomains: bool,
+    expires: Timespec,
+}
+
+impl HstsStore {
+    /// Create a new, empty HstsStore.
+    pub fn new() -> HstsStore {
+        HstsStore { hosts: HashMap::new() }
+    }
+
+    /// Store the policy of a `Strict-Transport-Security` header, received
+    /// from `url`.
+    ///
+    /// A `max_age` of zero removes any policy stored for the host.
+    pub fn store(&mut self, url: &Url, sts: &StrictTransportSecurity) {
+        if url.scheme[] != "https" {
+            debug!("ignoring Strict-Transport-Security received without TLS");
+            return;
+        }
+        let host = match url.serialize_host() {
+            Some(ref host) if from_str::<IpAddr>(host[]).is_none() && !host[].starts_with("[") => {
+                host.clone().into_ascii_lower()
+            },
+            _ => return
+        };
+        if sts.max_age == 0 {
+            debug!("removing HSTS policy of {}", host);
+            self.hosts.remove(&host);
+        } else {
+            debug!("storing HSTS policy of {} for {}s", host, sts.max_age);
+            self.hosts.insert(host, HstsPolicy {
+                include_subdomains: sts.include_subdomains,
+                expires: expires_after(get_time(), sts.max_age),
+            });
+        }
+    }
+
+    /// Whether `host` must only be reached over TLS.
+    pub fn is_secure_host(&self, host: &str) -> bool {
+        let host = host.into_string().into_ascii_lower();
+        let now = get_time();
+        self.hosts.iter().any(|(stored, policy)| {
+            policy.expires > now && (host == *stored || (policy.include_subdomains &&
+                host[].ends_with(stored[]) &&
+                host.as_bytes()[host.len() - stored.len() - 1] == b'.'))
+        })
+    }
+
+    /// Upgrade `url` to `https`, if it is an `http` url for a host that must
+    /// only be reached over TLS. Returns whether it was upgraded.
+    ///
+    /// A port of 80 becomes 443, while any other port is kept.
+    pub fn upgrade(&self, url: &mut Url) -> bool {
+        let secure = url.scheme[] == "http" && url.serialize_host().map_or(false, |host| {
+            self.is_secure_host(host[])
+        });
+        if !secure {
+            return false;
+        }
+        debug!("upgrading {} to https", url);
+        url.scheme = "https".into_string();
+        match url.relative_scheme_data_mut() {
+            Some(data) => {
+                if data.port == Some(80) {
+                    data.port = None;
+                }
+                data.default_port = Some(443);
+            },
+            None => ()
+        }
+        true
+    }
+
+    /// Remove all policies from this store.
+    pub fn clear(&mut self) {
+        self.hosts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::u64;
+
+    use url::Url;
+
+    use header::common::StrictTransportSecurity;
+    use super::HstsStore;
+
+    fn sts(max_age: u64, include_subdomains: bool) -> StrictTransportSecurity {
+        StrictTransportSecurity { max_age: max_age, include_subdomains: include_subdomains }
+    }
+
+    fn upgraded(store: &HstsStore, url: &str) -> String {
+        let mut url = Url::parse(url).unwrap();
+        store.upgrade(&mut url);
+        url.serialize()
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut store = HstsStore::new();
+        store.store(&Url::parse("https://example.dom/").unwrap(), &sts(600, false));
+        assert_eq!(upgraded(&store, "http://example.dom/a?b"), "https://example.dom/a?b".into_string());
+        assert_eq!(upgraded(&store, "http://EXAMPLE.dom:80/"), "https://example.dom/".into_string());
+        assert_eq!(upgraded(&store, "http://example.dom:8080/"), "https://example.dom:8080/".into_string());
+        assert_eq!(upgraded(&store, "http://www.example.dom/"), "http://www.example.dom/".into_string());
+    }
+
+    #[test]
+    fn test_huge_max_age() {
+        let mut store = HstsStore::new();
+        store.store(&Url::parse("https://example.dom/").unwrap(), &sts(u64::MAX, false));
+        assert!(store.is_secure_host("example.dom"));
+    }
+
+    #[test]
+    fn test_include_subdomains() {
+        let mut store = HstsStore::new();
+        store.store(&Url::parse("https://example.dom/").unwrap(), &sts(600, true));
+        assert!(store.is_secure_host("api.example.dom"));
+        assert!(!store.is_secure_host("badexample.dom"));
+    }
+
+    #[test]
+    fn test_ignored_without_tls() {
+        let mut store = HstsStore::new();
+        store.store(&Url::parse("http://example.dom/").unwrap(), &sts(600, false));
+        store.store(&Url::parse("https://127.0.0.1/").unwrap(), &sts(600, false));
+        assert!(!store.is_secure_host("example.dom"));
+        assert!(!store.is_secure_host("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_max_age_zero_removes() {
+        let mut store = HstsStore::new();
+        let url = Url::parse("https://example.dom/").unwrap();
+        store.store(&url, &sts(600, false));
+        store.store(&url, &sts(0, false));
+        assert!(!store.is_secure_host("example.dom"));
+    }
+}
@@ -0,0 +1,410 @@
+//! Client Middleware
+use std::ascii::AsciiExt;
+
+use url::Url;
+
+use header::Headers;
+use header::common::{Authorization, Link};
+use header::common::authorization::Bearer;
+use header::common::link::LinkValue;
+use http::RawStatus;
+use net::Fresh;
+use status::StatusCode::Unauthorized;
+use client::{InformationalHandler, Request, Response};
+use super::same_host;
+
+/// Hooks run by a `Client` around each request it sends, such as to log
+/// requests, add credentials to them, or sign them.
+///
+/// The middleware of a Client is a stack: `before_request` is called on
+/// each middleware in the order they were added, and `after_response` in
+/// the reverse order. All hooks do nothing by default.
+pub trait ClientMiddleware: Send {
+    /// Called with each request, including each redirect and retry, once
+    /// all of its headers have been set and just before it is sent.
+    fn before_request(&mut self, _req: &mut Request<Fresh>) {}
+
+    /// Whether `sign_request` should be called with each request.
+    ///
+    /// If any middleware of a Client wants them, the bodies of its requests
+    /// are read into memory before being sent, and so are sent with a
+    /// Content-Length instead of chunked. Defaults to `false`.
+    fn wants_body(&self) -> bool { false }
+
+    /// Called with each request and its whole body, after `before_request`
+    /// has been called on every middleware, so that nothing about the
+    /// request changes after this, and before anything is written.
+    ///
+    /// This allows a request to be signed, such as with AWS Signature
+    /// Version 4 or an HMAC, by adding headers. It is only called if
+    /// `wants_body` returns `true`. A request without a body gets an empty
+    /// one.
+    fn sign_request(&mut self, _req: &mut Request<Fresh>, _body: &[u8]) {}
+
+    /// Called with each response received, before any redirect it asks for
+    /// is followed.
+    ///
+    /// This is not called for responses served from a `CacheStore`.
+    fn after_response(&mut self, _res: &mut Response) {}
+
+    /// Called with each response, after `after_response` has been called on
+    /// every middleware, to ask whether its request should be sent again,
+    /// such as with new credentials after they were refused.
+    ///
+    /// It is called on every middleware, even once one has returned `true`.
+    /// A request is only sent again once this way, and only if its body can
+    /// be sent again. Defaults to `false`.
+    fn should_resend(&mut self, _res: &Response) -> bool { false }
+
+    /// Called with each interim `1xx` response received before the final
+    /// response to a request, such as `102 Processing`.
+    fn informational(&mut self, _status: &RawStatus, _headers: &Headers) {}
+
+    /// Called with the links of each `103 Early Hints` response received,
+    /// after `informational`, so that the resources they link to can be
+    /// fetched before the final response arrives.
+    fn on_early_hints(&mut self, _links: &[LinkValue]) {}
+}
+
+impl InformationalHandler for Vec<Box<ClientMiddleware + Send>> {
+    fn informational(&mut self, status: &RawStatus, headers: &Headers) {
+        let links = if status.0 == 103 { headers.get::<Link>() } else { None };
+        for middleware in self.iter_mut() {
+            middleware.informational(status, headers);
+            match links {
+                Some(links) => middleware.on_early_hints(links.0[]),
+                None => ()
+            }
+        }
+    }
+}
+
+/// A source of new tokens for a `BearerAuth`.
+pub trait TokenRefresher: Send {
+    /// Get a new access token, after `token` was refused by a server.
+    ///
+    /// Returning `None` gives up, and the `401 Unauthorized` response is
+    /// returned instead.
+    fn refresh(&mut self, token: &str) -> Option<String>;
+}
+
+/// A middleware that sends an OAuth 2.0 access token with each request to
+/// one origin, in an `Authorization: Bearer` header.
+///
+/// When a request is refused with a `401 Unauthorized`, a new token is got
+/// from its `TokenRefresher`, and the request is sent once more with it.
+/// Requests that already have an `Authorization` header are left alone, and
+/// so are requests to other origins, such as those redirected to another
+/// host, so the token isn't given away to them.
+pub struct BearerAuth<R> {
+    origin: Url,
+    token: String,
+    refresher: R,
+    sent: bool,
+}
+
+impl<R: TokenRefresher> BearerAuth<R> {
+    /// Create a new BearerAuth, sending `token` until it is refused, with
+    /// the requests to the origin of `origin`: its scheme, host and port.
+    pub fn new(origin: Url, token: String, refresher: R) -> BearerAuth<R> {
+        BearerAuth {
+            origin: origin,
+            token: token,
+            refresher: refresher,
+            sent: false,
+        }
+    }
+
+    /// Get the token that is currently sent.
+    pub fn token(&self) -> &str {
+        self.token[]
+    }
+}
+
+impl<R: TokenRefresher> ClientMiddleware for BearerAuth<R> {
+    fn before_request(&mut self, req: &mut Request<Fresh>) {
+        self.sent = req.url.scheme == self.origin.scheme && same_host(&req.url, &self.origin) &&
+            !req.headers().has::<Authorization<Bearer>>();
+        if self.sent {
+            req.headers_mut().set(Authorization(Bearer { token: self.token.clone() }));
+        }
+    }
+
+    fn should_resend(&mut self, res: &Response) -> bool {
+        if !self.sent || res.status != Unauthorized {
+            return false;
+        }
+        debug!("bearer token refused, refreshing it");
+        match self.refresher.refresh(self.token[]) {
+            Some(token) => {
+                self.token = token;
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// A sink for the requests and responses logged by a `WireLogger`.
+pub trait WireLog: Send {
+    /// Called with the head of each request, just before it is sent, and
+    /// its body if the logger is set to log bodies.
+    fn log_request(&mut self, head: &str, body: Option<&[u8]>);
+
+    /// Called with the head of each response received.
+    fn log_response(&mut self, head: &str);
+}
+
+/// The headers whose values are not logged by a `WireLogger`.
+static REDACTED_HEADERS: &'static [&'static str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+];
+
+/// A middleware that logs the request line and headers of each request
+/// and response of a `Client` to a `WireLog`, and optionally the body of
+/// each request.
+///
+/// The values of headers that carry credentials, such as `Authorization`
+/// and `Cookie`, are logged as `[redacted]`. Add it to a Client after any
+/// middleware that sets headers, so that it logs them too.
+pub struct WireLogger<L> {
+    log: L,
+    log_bodies: bool,
+}
+
+impl<L: WireLog> WireLogger<L> {
+    /// Create a new WireLogger, logging to `log`.
+    pub fn new(log: L) -> WireLogger<L> {
+        WireLogger {
+            log: log,
+            log_bodies: false,
+        }
+    }
+
+    /// Set whether the body of each request is logged.
+    ///
+    /// Bodies can only be logged by reading them into memory before they
+    /// are sent, as with any middleware that `wants_body`. Defaults to false.
+    pub fn set_log_bodies(&mut self, log_bodies: bool) {
+        self.log_bodies = log_bodies;
+    }
+
+    /// Get the `WireLog` of this WireLogger.
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    /// Get a mutable reference to the `WireLog` of this WireLogger.
+    pub fn log_mut(&mut self) -> &mut L {
+        &mut self.log
+    }
+}
+
+impl<L: WireLog> ClientMiddleware for WireLogger<L> {
+    fn before_request(&mut self, req: &mut Request<Fresh>) {
+        // with bodies, the request is logged along with its body instead
+        if !self.log_bodies {
+            self.log.log_request(request_head(req)[], None);
+        }
+    }
+
+    fn wants_body(&self) -> bool {
+        self.log_bodies
+    }
+
+    fn sign_request(&mut self, req: &mut Request<Fresh>, body: &[u8]) {
+        self.log.log_request(request_head(req)[], Some(body));
+    }
+
+    fn after_response(&mut self, res: &mut Response) {
+        let RawStatus(code, ref reason) = *res.status_raw();
+        let mut head = format!("{} {} {}\r\n", res.version, code, reason);
+        push_headers(&mut head, &res.headers);
+        self.log.log_response(head[]);
+    }
+}
+
+fn request_head(req: &Request<Fresh>) -> String {
+    let mut head = format!("{} {} {}\r\n", req.method(), req.url, req.version);
+    push_headers(&mut head, req.headers());
+    head
+}
+
+fn push_headers(head: &mut String, headers: &Headers) {
+    for header in headers.iter() {
+        if REDACTED_HEADERS.iter().any(|name| name.eq_ignore_ascii_case(header.name())) {
+            head.push_str(format!("{}: [redacted]\r\n", header.name())[]);
+        } else {
+            head.push_str(format!("{}\r\n", header)[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::IoResult;
+    use std::sync::{Arc, Mutex};
+
+    use url::Url;
+
+    use client::{Client, ClientMiddleware, Request};
+    use header::Headers;
+    use header::common::{Authorization, ContentType};
+    use header::common::authorization::Bearer;
+    use header::common::link::LinkValue;
+    use mock::MockStream;
+    use net::{Fresh, NetworkConnector};
+    use status::StatusCode;
+    use Port;
+    use super::{BearerAuth, TokenRefresher, WireLog, WireLogger};
+
+    mock_connector!(MockLogged {
+        "http://127.0.0.1" =>       "HTTP/1.1 200 OK\r\n\
+                                     Set-Cookie: session=abc\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    struct MockLog(Arc<Mutex<Vec<String>>>);
+
+    impl WireLog for MockLog {
+        fn log_request(&mut self, head: &str, body: Option<&[u8]>) {
+            let body = body.map_or(String::new(), |body| String::from_utf8_lossy(body).into_string());
+            self.0.lock().push(format!("{}{}", head, body));
+        }
+
+        fn log_response(&mut self, head: &str) {
+            self.0.lock().push(head.into_string());
+        }
+    }
+
+    #[test]
+    fn test_wire_logger_redacts() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = WireLogger::new(MockLog(log.clone()));
+        logger.set_log_bodies(true);
+        let mut client = Client::with_connector(MockLogged);
+        client.add_middleware(logger);
+
+        let mut headers = Headers::new();
+        headers.set_raw("Cookie", vec![b"session=abc".to_vec()]);
+        headers.set(ContentType(from_str("text/plain").unwrap()));
+        client.post("http://127.0.0.1/login").headers(headers).body("user=sean").send().unwrap();
+        let log = log.lock();
+        assert_eq!(log.len(), 2);
+        assert!(log[0][].starts_with("POST http://127.0.0.1/login HTTP/1.1\r\n"));
+        assert!(log[0][].contains("Cookie: [redacted]\r\n"));
+        assert!(log[0][].contains("Content-Type: text/plain\r\n"));
+        assert!(log[0][].ends_with("user=sean"));
+        assert!(log[1][].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(log[1][].contains("Set-Cookie: [redacted]\r\n"));
+        assert!(!log.iter().any(|entry| entry[].contains("abc")));
+    }
+
+    mock_connector!(MockEarlyHints {
+        "http://127.0.0.1" =>       "HTTP/1.1 103 Early Hints\r\n\
+                                     Link: </style.css>; rel=preload; as=style\r\n\
+                                     \r\n\
+                                     HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    struct MockHintRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl ClientMiddleware for MockHintRecorder {
+        fn on_early_hints(&mut self, links: &[LinkValue]) {
+            self.0.lock().extend(links.iter().map(|link| link.uri.clone()));
+        }
+    }
+
+    #[test]
+    fn test_on_early_hints() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(MockEarlyHints);
+        client.add_middleware(MockHintRecorder(log.clone()));
+        let res = client.get("http://127.0.0.1").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(*log.lock(), vec!["/style.css".into_string()]);
+    }
+
+    /// Refuses the first request, and answers the rest.
+    struct MockExpired(uint);
+
+    impl NetworkConnector<MockStream> for MockExpired {
+        fn connect(&mut self, _host: &str, _port: Port, _scheme: &str) -> IoResult<MockStream> {
+            self.0 += 1;
+            Ok(MockStream::with_input(if self.0 == 1 {
+                "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".as_bytes()
+            } else {
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes()
+            }))
+        }
+    }
+
+    struct MockRefresher(Arc<Mutex<Vec<String>>>);
+
+    impl TokenRefresher for MockRefresher {
+        fn refresh(&mut self, token: &str) -> Option<String> {
+            self.0.lock().push(token.into_string());
+            Some("fresh".into_string())
+        }
+    }
+
+    struct MockTokenRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl ClientMiddleware for MockTokenRecorder {
+        fn before_request(&mut self, req: &mut Request<Fresh>) {
+            let token = req.headers().get::<Authorization<Bearer>>().map(|auth| auth.token.clone());
+            self.0.lock().push(token.unwrap_or("none".into_string()));
+        }
+    }
+
+    #[test]
+    fn test_bearer_auth_refreshes() {
+        let refreshed = Arc::new(Mutex::new(Vec::new()));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(MockExpired(0));
+        client.add_middleware(BearerAuth::new(Url::parse("http://127.0.0.1/").unwrap(),
+                                              "stale".into_string(),
+                                              MockRefresher(refreshed.clone())));
+        client.add_middleware(MockTokenRecorder(sent.clone()));
+
+        let res = client.get("http://127.0.0.1").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(*refreshed.lock(), vec!["stale".into_string()]);
+        assert_eq!(*sent.lock(), vec!["stale".into_string(), "fresh".into_string()]);
+    }
+
+    mock_connector!(MockRedirectElsewhere {
+        "http://127.0.0.1" =>       "HTTP/1.1 302 Found\r\n\
+                                     Location: http://evil.dom/\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+        "http://evil.dom" =>        "HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    #[test]
+    fn test_bearer_auth_other_origin() {
+        let refreshed = Arc::new(Mutex::new(Vec::new()));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(MockRedirectElsewhere);
+        client.add_middleware(BearerAuth::new(Url::parse("http://127.0.0.1/").unwrap(),
+                                              "secret".into_string(),
+                                              MockRefresher(refreshed)));
+        client.add_middleware(MockTokenRecorder(sent.clone()));
+
+        let res = client.get("http://127.0.0.1").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(*sent.lock(), vec!["secret".into_string(), "none".into_string()]);
+    }
+}
@@ -0,0 +1,200 @@
+//! Client multipart/form-data Bodies
+use std::io::{IoResult, MemReader, EndOfFile, standard_error};
+use std::rand::{task_rng, Rng};
+
+use mime::Mime;
+
+use client::{Body, IntoBody};
+use http::LINE_ENDING;
+
+/// A builder for a `multipart/form-data` request body.
+///
+/// Fields are streamed in the order they were added, so file readers are not
+/// read until the request is sent.
+///
+/// ```no_run
+/// # use hyper::Client;
+/// # use hyper::client::multipart::Multipart;
+/// # use std::io::File;
+/// let mut file = File::open(&Path::new("avatar.png")).unwrap();
+/// let mut form = Multipart::new();
+/// form.add_text("user", "sean");
+/// form.add_file("avatar", "avatar.png", from_str("image/png").unwrap(), &mut file);
+///
+/// let mut client = Client::new();
+/// client.post("http://example.domain/upload").body(&mut form).send().unwrap();
+/// ```
+pub struct Multipart<'a> {
+    boundary: String,
+    parts: Parts<'a>,
+}
+
+struct Parts<'a> {
+    pieces: Vec<Piece<'a>>,
+    index: uint,
+    closing: Option<Vec<u8>>,
+}
+
+enum Piece<'a> {
+    Buf(MemReader),
+    Stream(&'a mut (Reader + 'a)),
+}
+
+impl<'a> Multipart<'a> {
+    /// Create a new, empty Multipart body, with a random boundary.
+    pub fn new() -> Multipart<'a> {
+        let boundary = task_rng().gen_ascii_chars().take(32).collect::<String>();
+        Multipart::with_boundary(boundary[])
+    }
+
+    /// Create a new, empty Multipart body, using the given boundary.
+    ///
+    /// The boundary must not appear in any of the fields added.
+    pub fn with_boundary(boundary: &str) -> Multipart<'a> {
+        Multipart {
+            boundary: boundary.into_string(),
+            parts: Parts {
+                pieces: Vec::new(),
+                index: 0,
+                closing: Some(format!("--{}--\r\n", boundary).into_bytes()),
+            }
+        }
+    }
+
+    /// The boundary separating each field.
+    pub fn boundary(&self) -> &str {
+        self.boundary[]
+    }
+
+    /// Add a text field.
+    pub fn add_text(&mut self, name: &str, value: &str) {
+        self.push_head(name, None, None);
+        self.parts.pieces.push(Piece::Buf(MemReader::new(value.as_bytes().to_vec())));
+        self.push_tail();
+    }
+
+    /// Add a field with the bytes of a file.
+    pub fn add_bytes(&mut self, name: &str, filename: &str, mime: Mime, bytes: Vec<u8>) {
+        self.push_head(name, Some(filename), Some(mime));
+        self.parts.pieces.push(Piece::Buf(MemReader::new(bytes)));
+        self.push_tail();
+    }
+
+    /// Add a field with the contents of a file, which is read to the end
+    /// as the body is sent.
+    pub fn add_file(&mut self, name: &str, filename: &str, mime: Mime, file: &'a mut (Reader + 'a)) {
+        self.push_head(name, Some(filename), Some(mime));
+        self.parts.pieces.push(Piece::Stream(file));
+        self.push_tail();
+    }
+
+    fn push_head(&mut self, name: &str, filename: Option<&str>, mime: Option<Mime>) {
+        let mut head = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                               self.boundary, quote(name));
+        match filename {
+            Some(filename) => head.push_str(format!("; filename=\"{}\"", quote(filename))[]),
+            None => ()
+        }
+        head.push_str("\r\n");
+        match mime {
+            Some(mime) => head.push_str(format!("Content-Type: {}\r\n", mime)[]),
+            None => ()
+        }
+        head.push_str("\r\n");
+        self.parts.pieces.push(Piece::Buf(MemReader::new(head.into_bytes())));
+    }
+
+    fn push_tail(&mut self) {
+        self.parts.pieces.push(Piece::Buf(MemReader::new(LINE_ENDING.to_vec())));
+    }
+}
+
+impl<'a, 'b> IntoBody<'a> for &'a mut Multipart<'b> {
+    #[inline]
+    fn into_body(self) -> Body<'a> {
+        Body::ChunkedBody(&mut self.parts)
+    }
+
+    fn content_type(&self) -> Option<Mime> {
+        from_str(format!("multipart/form-data; boundary={}", self.boundary)[])
+    }
+}
+
+impl<'a> Reader for Parts<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        loop {
+            if self.index == self.pieces.len() {
+                match self.closing.take() {
+                    Some(closing) => self.pieces.push(Piece::Buf(MemReader::new(closing))),
+                    None => return Err(standard_error(EndOfFile))
+                }
+            }
+            let result = match self.pieces[self.index] {
+                Piece::Buf(ref mut r) => r.read(buf),
+                Piece::Stream(ref mut r) => r.read(buf),
+            };
+            match result {
+                Ok(0) => (),
+                Err(ref e) if e.kind == EndOfFile => (),
+                result => return result
+            }
+            self.index += 1;
+        }
+    }
+}
+
+/// Escape a name so it can be put in a quoted-string, the way browsers do.
+fn quote(s: &str) -> String {
+    s.replace("\"", "%22").replace("\r", "%0D").replace("\n", "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemReader;
+    use std::str::from_utf8;
+
+    use client::IntoBody;
+    use super::Multipart;
+
+    #[test]
+    fn test_multipart_body() {
+        let mut file = MemReader::new(b"file contents".to_vec());
+        let mut form = Multipart::with_boundary("boundary");
+        form.add_text("name", "value");
+        form.add_bytes("bytes", "a.bin", from_str("application/octet-stream").unwrap(), vec![1, 2]);
+        form.add_file("file", "b \"quoted\".txt", from_str("text/plain").unwrap(), &mut file);
+
+        let body = (&mut form).into_body().read_to_end().unwrap();
+        assert_eq!(from_utf8(body[]).unwrap(), "\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"name\"\r\n\
+            \r\n\
+            value\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"bytes\"; filename=\"a.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n\
+            \x01\x02\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"b %22quoted%22.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            file contents\r\n\
+            --boundary--\r\n");
+    }
+
+    #[test]
+    fn test_multipart_content_type() {
+        let mut form = Multipart::with_boundary("boundary");
+        let mime = (&mut form).content_type().unwrap();
+        assert_eq!(mime.to_string()[], "multipart/form-data; boundary=boundary");
+    }
+
+    #[test]
+    fn test_random_boundary() {
+        let a = Multipart::new();
+        let b = Multipart::new();
+        assert_eq!(a.boundary().len(), 32);
+        assert!(a.boundary() != b.boundary());
+    }
+}
@@ -0,0 +1,539 @@
+//! Client Connection Pooling
+use std::collections::HashMap;
+use std::collections::hash_map::{Occupied, Vacant};
+use std::default::Default;
+use std::io::{IoError, IoResult, ResourceUnavailable};
+use std::io::net::ip::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use time::precise_time_ns;
+
+use header::common::authorization::Basic;
+use net::{ConnectTimings, NetworkConnector, NetworkStream, TlsInfo};
+use {HttpResult, Port};
+use HttpError::HttpIoError;
+
+/// A connector that keeps idle keep-alive connections around, so that
+/// subsequent requests to the same host can reuse them.
+///
+/// Connections are keyed by host, port, and scheme.
+pub struct Pool<C> {
+    connector: C,
+    inner: Arc<Mutex<PoolImpl>>,
+    timings: Option<ConnectTimings>,
+    reused: bool,
+    reuse_idle: bool,
+}
+
+type Key = (String, Port, String);
+
+/// Limits on the connections a `Pool` keeps open.
+#[deriving(Clone, PartialEq, Show)]
+pub struct PoolConfig {
+    /// The most idle connections kept for each host. Connections beyond it
+    /// are closed instead of being kept.
+    pub max_idle_per_host: Option<uint>,
+    /// The most connections open at once, idle or in use.
+    ///
+    /// When it is reached, the connection idle the longest is closed to make
+    /// room for a new one. If none is idle, connecting is an error.
+    pub max_total: Option<uint>,
+    /// How long a connection may stay idle before it is closed.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    /// No limits at all.
+    fn default() -> PoolConfig {
+        PoolConfig {
+            max_idle_per_host: None,
+            max_total: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+struct PoolImpl {
+    conns: HashMap<Key, Vec<IdleConn>>,
+    config: PoolConfig,
+    /// The number of connections open, idle or in use.
+    open: uint,
+}
+
+struct IdleConn {
+    conn: Box<NetworkStream + Send>,
+    /// When it became idle, from `precise_time_ns`.
+    since: u64,
+    /// How long the server keeps it open while idle, in nanoseconds, if the
+    /// server said.
+    timeout: Option<u64>,
+}
+
+impl PoolImpl {
+    fn reuse(&mut self, key: Key, conn: Box<NetworkStream + Send>, timeout: Option<Duration>) {
+        debug!("reuse {}", key);
+        self.evict_expired();
+        let max = self.config.max_idle_per_host;
+        let idle = match self.conns.entry(key) {
+            Vacant(entry) => entry.set(vec![]),
+            Occupied(entry) => entry.into_mut()
+        };
+        if max.map_or(false, |max| idle.len() >= max) {
+            debug!("too many idle connections, closing");
+            self.open -= 1;
+            return;
+        }
+        idle.push(IdleConn {
+            conn: conn,
+            since: precise_time_ns(),
+            timeout: timeout.map(nanoseconds),
+        });
+    }
+
+    fn take(&mut self, key: &Key) -> Option<Box<NetworkStream + Send>> {
+        self.evict_expired();
+        match self.conns.get_mut(key) {
+            Some(conns) => conns.pop().map(|idle| idle.conn),
+            None => None
+        }
+    }
+
+    /// Closes the connections that have been idle longer than the idle
+    /// timeout, or than their server keeps them open for.
+    fn evict_expired(&mut self) {
+        let timeout = self.config.idle_timeout.map(nanoseconds);
+        let now = precise_time_ns();
+        let mut evicted = 0;
+        for conns in self.conns.values_mut() {
+            let before = conns.len();
+            conns.retain(|idle| !is_expired(idle, timeout, now));
+            evicted += before - conns.len();
+        }
+        if evicted > 0 {
+            debug!("closed {} expired idle connections", evicted);
+        }
+        self.open -= evicted;
+    }
+
+    /// Closes the connection idle the longest, returning whether there was one.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self.conns.iter()
+            .filter_map(|(key, conns)| conns.first().map(|idle| (key.clone(), idle.since)))
+            .min_by(|&(_, since)| since);
+        match oldest {
+            Some((key, _)) => {
+                debug!("closing idle connection to {} to make room", key);
+                self.conns.get_mut(&key).map(|conns| conns.remove(0));
+                self.open -= 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn idle(&self) -> uint {
+        self.conns.values().fold(0, |count, conns| count + conns.len())
+    }
+}
+
+fn is_expired(idle: &IdleConn, timeout: Option<u64>, now: u64) -> bool {
+    [timeout, idle.timeout].iter().any(|timeout| match *timeout {
+        Some(timeout) => now - idle.since >= timeout,
+        None => false
+    })
+}
+
+fn nanoseconds(duration: Duration) -> u64 {
+    duration.num_milliseconds() as u64 * 1_000_000
+}
+
+impl<C> Pool<C> {
+    /// Creates a new `Pool`, which will make new connections with `connector`.
+    pub fn new(connector: C) -> Pool<C> {
+        Pool::with_config(connector, Default::default())
+    }
+
+    /// Creates a new `Pool` with limits on the connections it keeps open.
+    pub fn with_config(connector: C, config: PoolConfig) -> Pool<C> {
+        Pool {
+            connector: connector,
+            inner: Arc::new(Mutex::new(PoolImpl {
+                conns: HashMap::new(),
+                config: config,
+                open: 0,
+            })),
+            timings: None,
+            reused: false,
+            reuse_idle: true,
+        }
+    }
+
+    /// Get a mutable reference to the connector new connections are made with.
+    pub fn connector_mut(&mut self) -> &mut C {
+        &mut self.connector
+    }
+
+    /// Set the limits on the connections this pool keeps open.
+    ///
+    /// They apply to connections as they are next used or returned.
+    pub fn set_config(&mut self, config: PoolConfig) {
+        self.inner.lock().config = config;
+    }
+
+    /// Closes all idle connections held by this pool.
+    pub fn clear(&mut self) {
+        let mut inner = self.inner.lock();
+        let idle = inner.idle();
+        inner.open -= idle;
+        inner.conns.clear();
+    }
+}
+
+impl<C: NetworkConnector<S>, S: NetworkStream> Pool<C> {
+    /// Makes sure an idle connection to `host:port` is waiting in the pool,
+    /// connecting, and for `https` completing the TLS handshake, if none is.
+    pub fn preconnect(&mut self, host: &str, port: Port, scheme: &str) -> IoResult<()> {
+        let mut stream = try!(self.connect(host, port, scheme));
+        stream.mark_reusable();
+        Ok(())
+    }
+}
+
+impl<C: NetworkConnector<S>, S: NetworkStream> NetworkConnector<PooledStream> for Pool<C> {
+    fn connect(&mut self, host: &str, port: Port, scheme: &str) -> IoResult<PooledStream> {
+        self.connect_http(host, port, scheme).map_err(|e| e.into_io_error())
+    }
+
+    fn connect_http(&mut self, host: &str, port: Port, scheme: &str) -> HttpResult<PooledStream> {
+        let key = (host.into_string(), port, scheme.into_string());
+        let idle = if self.reuse_idle { self.inner.lock().take(&key) } else { None };
+        self.timings = None;
+        self.reused = idle.is_some();
+        let conn = match idle {
+            Some(conn) => {
+                debug!("reusing connection to {}:{}", host, port);
+                conn
+            },
+            None => {
+                {
+                    let mut inner = self.inner.lock();
+                    let full = inner.config.max_total.map_or(false, |max| inner.open >= max);
+                    if full && !inner.evict_oldest() {
+                        return Err(HttpIoError(IoError {
+                            kind: ResourceUnavailable,
+                            desc: "Pool has reached its maximum number of connections",
+                            detail: None
+                        }));
+                    }
+                    inner.open += 1;
+                }
+                let conn = match self.connector.connect_http(host, port, scheme) {
+                    Ok(conn) => box conn as Box<NetworkStream + Send>,
+                    Err(e) => {
+                        self.inner.lock().open -= 1;
+                        return Err(e);
+                    }
+                };
+                self.timings = self.connector.connect_timings();
+                conn
+            }
+        };
+        Ok(PooledStream {
+            inner: Some(conn),
+            key: key,
+            pool: Some(self.inner.clone()),
+            reusable: false,
+            idle_timeout: None,
+        })
+    }
+
+    fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+        self.connector.set_connect_timeout(timeout)
+    }
+
+    fn is_proxied(&self, host: &str, port: Port, scheme: &str) -> bool {
+        self.connector.is_proxied(host, port, scheme)
+    }
+
+    /// A reused connection was not made, so has no timings.
+    fn connect_timings(&self) -> Option<ConnectTimings> {
+        self.timings.clone()
+    }
+
+    fn proxy_credentials(&self) -> Option<Basic> {
+        self.connector.proxy_credentials()
+    }
+
+    fn connection_reused(&self) -> bool {
+        self.reused
+    }
+
+    fn set_reuse_idle(&mut self, reuse: bool) {
+        self.reuse_idle = reuse;
+    }
+}
+
+/// A stream handed out by a `Pool`.
+///
+/// Once the response on this stream has been read to completion, and the
+/// server has agreed to keep the connection alive, the stream is marked
+/// reusable and will be returned to the pool when dropped. Otherwise, it is
+/// simply closed.
+pub struct PooledStream {
+    inner: Option<Box<NetworkStream + Send>>,
+    key: Key,
+    pool: Option<Arc<Mutex<PoolImpl>>>,
+    reusable: bool,
+    idle_timeout: Option<Duration>,
+}
+
+impl PooledStream {
+    /// Marks this stream as safe to be returned to the pool once dropped.
+    #[inline]
+    pub fn mark_reusable(&mut self) {
+        self.reusable = true;
+    }
+
+    /// Limits how long this stream is kept idle in the pool, such as to the
+    /// timeout a server gave in a `Keep-Alive` header.
+    #[inline]
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    #[inline]
+    fn stream(&mut self) -> &mut Box<NetworkStream + Send> {
+        self.inner.as_mut().expect("PooledStream used after drop")
+    }
+}
+
+impl Clone for PooledStream {
+    fn clone(&self) -> PooledStream {
+        // Only the original stream may find its way back into the pool.
+        PooledStream {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            pool: None,
+            reusable: false,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl Reader for PooledStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.stream().read(buf)
+    }
+}
+
+impl Writer for PooledStream {
+    #[inline]
+    fn write(&mut self, msg: &[u8]) -> IoResult<()> {
+        self.stream().write(msg)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> IoResult<()> {
+        self.stream().flush()
+    }
+}
+
+impl NetworkStream for PooledStream {
+    #[inline]
+    fn peer_name(&mut self) -> IoResult<SocketAddr> {
+        self.stream().peer_name()
+    }
+
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.stream().set_read_timeout(timeout)
+    }
+
+    #[inline]
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.stream().set_write_timeout(timeout)
+    }
+
+    #[inline]
+    fn shutdown(&mut self) -> IoResult<()> {
+        self.stream().shutdown()
+    }
+
+    #[inline]
+    fn tls_info(&self) -> Option<TlsInfo> {
+        self.inner.as_ref().and_then(|stream| stream.tls_info())
+    }
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        match (self.inner.take(), self.pool.take()) {
+            (Some(conn), Some(pool)) => if self.reusable {
+                pool.lock().reuse(self.key.clone(), conn, self.idle_timeout)
+            } else {
+                pool.lock().open -= 1
+            },
+            _ => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use client::Response;
+    use mock::MockConnector;
+    use net::{NetworkConnector, NetworkStream};
+    use super::{Pool, PoolConfig};
+
+    mock_connector!(MockKeepAlive {
+        "http://127.0.0.1" =>       "HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 5\r\n\
+                                     \r\n\
+                                     hello"
+        "http://127.0.0.2" =>       "HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 1000000\r\n\
+                                     \r\n\
+                                     hello"
+        "http://127.0.0.3" =>       "HTTP/1.0 200 OK\r\n\
+                                     Connection: keep-alive\r\n\
+                                     Keep-Alive: timeout=0, max=100\r\n\
+                                     Content-Length: 5\r\n\
+                                     \r\n\
+                                     hello"
+        "http://127.0.0.4" =>       "HTTP/1.0 200 OK\r\n\
+                                     Content-Length: 5\r\n\
+                                     \r\n\
+                                     hello"
+    })
+
+    #[test]
+    fn test_reusable_stream_returns_to_pool() {
+        let mut pool = Pool::new(MockConnector);
+        let key = ("127.0.0.1".into_string(), 3000, "http".into_string());
+        {
+            let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            stream.mark_reusable();
+        }
+        assert_eq!(pool.inner.lock().conns.get(&key).map(|c| c.len()), Some(1));
+
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        assert_eq!(pool.inner.lock().conns.get(&key).map(|c| c.len()), Some(0));
+        drop(stream);
+        assert_eq!(pool.inner.lock().conns.get(&key).map(|c| c.len()), Some(0));
+    }
+
+    #[test]
+    fn test_dropped_response_is_drained() {
+        let mut pool = Pool::new(MockKeepAlive);
+        let stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        drop(Response::new(box stream as Box<NetworkStream + Send>).unwrap());
+        assert_eq!(pool.inner.lock().idle(), 1);
+    }
+
+    #[test]
+    fn test_dropped_large_response_is_closed() {
+        let mut pool = Pool::new(MockKeepAlive);
+        let stream = pool.connect("127.0.0.2", 3000, "http").unwrap();
+        drop(Response::new(box stream as Box<NetworkStream + Send>).unwrap());
+        assert_eq!(pool.inner.lock().idle(), 0);
+        assert_eq!(pool.inner.lock().open, 0);
+    }
+
+    #[test]
+    fn test_http10_keep_alive() {
+        let mut pool = Pool::new(MockKeepAlive);
+        let stream = pool.connect("127.0.0.4", 3000, "http").unwrap();
+        drop(Response::new(box stream as Box<NetworkStream + Send>).unwrap());
+        assert_eq!(pool.inner.lock().idle(), 0);
+
+        // kept, but only as long as the server said it keeps it open
+        let stream = pool.connect("127.0.0.3", 3000, "http").unwrap();
+        drop(Response::new(box stream as Box<NetworkStream + Send>).unwrap());
+        assert_eq!(pool.inner.lock().idle(), 1);
+        pool.connect("127.0.0.3", 3000, "http").unwrap();
+        assert!(!pool.connection_reused());
+    }
+
+    #[test]
+    fn test_pool_keys_by_scheme() {
+        let mut pool = Pool::new(MockConnector);
+        {
+            let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            stream.mark_reusable();
+        }
+        let https = ("127.0.0.1".into_string(), 3000, "https".into_string());
+        assert!(pool.inner.lock().conns.get(&https).is_none());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut pool = Pool::new(MockConnector);
+        {
+            let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            stream.mark_reusable();
+        }
+        pool.clear();
+        assert!(pool.inner.lock().conns.is_empty());
+    }
+
+    #[test]
+    fn test_preconnect() {
+        let mut pool = Pool::new(MockConnector);
+        let key = ("127.0.0.1".into_string(), 3000, "http".into_string());
+        pool.preconnect("127.0.0.1", 3000, "http").unwrap();
+        pool.preconnect("127.0.0.1", 3000, "http").unwrap();
+        assert_eq!(pool.inner.lock().conns.get(&key).map(|c| c.len()), Some(1));
+    }
+
+    fn config(max_idle_per_host: Option<uint>, max_total: Option<uint>,
+              idle_timeout: Option<Duration>) -> PoolConfig {
+        PoolConfig {
+            max_idle_per_host: max_idle_per_host,
+            max_total: max_total,
+            idle_timeout: idle_timeout,
+        }
+    }
+
+    #[test]
+    fn test_max_idle_per_host() {
+        let mut pool = Pool::with_config(MockConnector, config(Some(1), None, None));
+        let key = ("127.0.0.1".into_string(), 3000, "http".into_string());
+        {
+            let mut first = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            let mut second = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            first.mark_reusable();
+            second.mark_reusable();
+        }
+        assert_eq!(pool.inner.lock().conns.get(&key).map(|c| c.len()), Some(1));
+        assert_eq!(pool.inner.lock().open, 1);
+    }
+
+    #[test]
+    fn test_max_total() {
+        let mut pool = Pool::with_config(MockConnector, config(None, Some(1), None));
+        {
+            let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            assert!(pool.connect("127.0.0.2", 3000, "http").is_err());
+            stream.mark_reusable();
+        }
+        // the idle connection is closed to make room
+        let _stream = pool.connect("127.0.0.2", 3000, "http").unwrap();
+        assert_eq!(pool.inner.lock().idle(), 0);
+        assert_eq!(pool.inner.lock().open, 1);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let mut pool = Pool::with_config(MockConnector, config(None, None, Some(Duration::zero())));
+        {
+            let mut stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+            stream.mark_reusable();
+        }
+        let _stream = pool.connect("127.0.0.1", 3000, "http").unwrap();
+        assert_eq!(pool.inner.lock().idle(), 0);
+        assert_eq!(pool.inner.lock().open, 1);
+    }
+}
@@ -1,21 +1,51 @@
 //! Client Requests
+use std::default::Default;
 use std::io::{BufferedWriter, IoResult};
+use std::time::Duration;
 
 use url::Url;
+use url::percent_encoding::lossy_utf8_percent_decode;
 
 use method;
 use method::Method::{Get, Post, Delete, Put, Patch, Head, Options};
 use header::Headers;
-use header::common::{mod, Host};
+use header::common::{mod, Expect, Host};
 use net::{NetworkStream, NetworkConnector, HttpConnector, Fresh, Streaming};
-use HttpError::HttpUriError;
-use http::{HttpWriter, LINE_ENDING};
+use HttpError::{HttpUriError, HttpTimeoutError};
+use http::{HeaderLimits, HttpWriter, LINE_ENDING, RawStatus, StatusLine, is_interim,
+           read_status_line};
 use http::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter, EmptyWriter};
 use version;
 use HttpResult;
-use client::Response;
+use client::{CancelHandle, InformationalHandler, Response};
 
 
+/// The method, url, version, and headers of a request, without a connection
+/// to send it on, for `Client::execute`.
+#[deriving(Clone)]
+pub struct RequestHead {
+    /// The method of the request.
+    pub method: method::Method,
+    /// The target URI of the request.
+    pub url: Url,
+    /// The HTTP version of the request.
+    pub version: version::HttpVersion,
+    /// The headers of the request.
+    pub headers: Headers,
+}
+
+impl RequestHead {
+    /// A HTTP/1.1 request with no headers.
+    pub fn new(method: method::Method, url: Url) -> RequestHead {
+        RequestHead {
+            method: method,
+            url: url,
+            version: version::HttpVersion::Http11,
+            headers: Headers::new(),
+        }
+    }
+}
+
 /// A client request to a remote server.
 pub struct Request<W> {
     /// The target URI for this request.
@@ -27,6 +57,28 @@ pub struct Request<W> {
     body: HttpWriter<BufferedWriter<Box<NetworkStream + Send>>>,
     headers: Headers,
     method: method::Method,
+    proxied: bool,
+    read_timeout: Option<Duration>,
+    first_byte_timeout: Option<Duration>,
+    continue_timeout: Duration,
+    rejected: Option<StatusLine>,
+    interim: Vec<(RawStatus, Headers)>,
+    header_limits: HeaderLimits,
+    cancel: Option<CancelWatch>,
+}
+
+/// A cancel handle, and the generation of the stream of a Request it
+/// watches, which it stops watching once the Request is dropped, having
+/// failed or handed the handle on to its `Response`.
+struct CancelWatch {
+    handle: CancelHandle,
+    generation: uint,
+}
+
+impl Drop for CancelWatch {
+    fn drop(&mut self) {
+        self.handle.unwatch(self.generation);
+    }
 }
 
 impl<W> Request<W> {
@@ -37,45 +89,108 @@ impl<W> Request<W> {
     /// Read the Request method.
     #[inline]
     pub fn method(&self) -> method::Method { self.method.clone() }
+
+    /// Set the read timeout of the underlying stream.
+    ///
+    /// This also applies when reading the `Response` to this Request.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.body.get_mut().get_mut().set_read_timeout(timeout)
+    }
+
+    /// Set the write timeout of the underlying stream.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.body.get_mut().get_mut().set_write_timeout(timeout)
+    }
+
+    /// Set a handle that cancels this Request, and reading its `Response`,
+    /// from another task.
+    pub fn set_cancel_handle(&mut self, handle: CancelHandle) {
+        let generation = handle.watch(self.body.get_ref().get_ref().clone());
+        self.cancel = Some(CancelWatch { handle: handle, generation: generation });
+    }
+
+    /// Set the limits on the headers of the `Response` to this Request.
+    ///
+    /// Headers beyond them are an `HttpHeadersTooLargeError`. Defaults to
+    /// `HeaderLimits::default()`.
+    pub fn set_header_limits(&mut self, limits: HeaderLimits) {
+        self.header_limits = limits;
+    }
 }
 
 impl Request<Fresh> {
     /// Create a new client request.
     pub fn new(method: method::Method, url: Url) -> HttpResult<Request<Fresh>> {
-        let mut conn = HttpConnector;
+        let mut conn = HttpConnector::new();
         Request::with_connector(method, url, &mut conn)
     }
 
     /// Create a new client request with a specific underlying NetworkStream.
     pub fn with_connector<C: NetworkConnector<S>, S: NetworkStream>(method: method::Method, url: Url, connector: &mut C) -> HttpResult<Request<Fresh>> {
         debug!("{} {}", method, url);
-        let host = match url.serialize_host() {
-            Some(host) => host,
-            None => return Err(HttpUriError)
+        let (host, port, host_header) = match unix_socket_parts(&url) {
+            Some((path, _)) => {
+                debug!("unix socket={}", path);
+                // the socket stands in for the host, which must still be sent
+                (path, 0, Host { hostname: "localhost".into_string(), port: None })
+            },
+            None => {
+                let host = match url.serialize_host() {
+                    Some(host) => host,
+                    None => return Err(HttpUriError)
+                };
+                debug!("host={}", host);
+                let port = match url.port_or_default() {
+                    Some(port) => port,
+                    None => return Err(HttpUriError)
+                };
+                debug!("port={}", port);
+                (host.clone(), port, Host { hostname: host, port: Some(port) })
+            }
         };
-        debug!("host={}", host);
-        let port = match url.port_or_default() {
-            Some(port) => port,
-            None => return Err(HttpUriError)
+
+        let proxied = connector.is_proxied(host[], port, &*url.scheme);
+        let stream: S = try!(connector.connect_http(host[], port, &*url.scheme));
+        Ok(Request::from_stream(method, url, box stream as Box<NetworkStream + Send>, host_header, proxied))
+    }
+
+    /// Create a new client request on a connection that is already open,
+    /// such as one that earlier requests were written to.
+    ///
+    /// If `proxied`, the connection is to a proxy, and the whole url is
+    /// sent for it to forward the request to.
+    pub fn with_stream(method: method::Method, url: Url, stream: Box<NetworkStream + Send>,
+                       proxied: bool) -> HttpResult<Request<Fresh>> {
+        let host_header = match (url.serialize_host(), url.port_or_default()) {
+            (Some(host), Some(port)) => Host { hostname: host, port: Some(port) },
+            _ => return Err(HttpUriError)
         };
-        debug!("port={}", port);
+        Ok(Request::from_stream(method, url, stream, host_header, proxied))
+    }
 
-        let stream: S = try!(connector.connect((host[], port), &*url.scheme));
-        let stream = ThroughWriter(BufferedWriter::new(box stream as Box<NetworkStream + Send>));
+    fn from_stream(method: method::Method, url: Url, stream: Box<NetworkStream + Send>,
+                   host_header: Host, proxied: bool) -> Request<Fresh> {
+        let stream = ThroughWriter(BufferedWriter::new(stream));
 
         let mut headers = Headers::new();
-        headers.set(Host {
-            hostname: host,
-            port: Some(port),
-        });
+        headers.set(host_header);
 
-        Ok(Request {
+        Request {
             method: method,
             headers: headers,
             url: url,
             version: version::HttpVersion::Http11,
-            body: stream
-        })
+            body: stream,
+            proxied: proxied,
+            read_timeout: None,
+            first_byte_timeout: None,
+            continue_timeout: Duration::seconds(1),
+            rejected: None,
+            interim: vec![],
+            header_limits: Default::default(),
+            cancel: None,
+        }
     }
 
     /// Create a new GET request.
@@ -106,14 +221,53 @@ impl Request<Fresh> {
     #[inline]
     pub fn options(url: Url) -> HttpResult<Request<Fresh>> { Request::new(Options, url) }
 
+    /// Set how long to wait for a `100 Continue` before sending the body
+    /// anyway, when the request has an `Expect: 100-continue` header.
+    ///
+    /// Defaults to 1 second.
+    pub fn set_continue_timeout(&mut self, timeout: Duration) {
+        self.continue_timeout = timeout;
+    }
+
+    /// Set how long to wait for the head of the `Response`, once the request
+    /// has been sent, in place of the read timeout.
+    ///
+    /// The read timeout applies again when reading the body, so it can be
+    /// long for streaming bodies while a server that never answers is still
+    /// noticed quickly. Defaults to `None`, using the read timeout.
+    pub fn set_first_byte_timeout(&mut self, timeout: Option<Duration>) {
+        self.first_byte_timeout = timeout;
+    }
+
     /// Consume a Fresh Request, writing the headers and method,
     /// returning a Streaming Request.
+    ///
+    /// If the request has an `Expect: 100-continue` header, this waits for
+    /// the server to accept the body before returning. If the server refuses
+    /// it with a final response instead, the Streaming Request discards
+    /// anything written to it, and `send()` returns that response.
     pub fn start(mut self) -> HttpResult<Request<Streaming>> {
-        let mut uri = self.url.serialize_path().unwrap();
-        //TODO: this needs a test
-        if let Some(ref q) = self.url.query {
-            uri.push('?');
-            uri.push_str(q[]);
+        let uri = if self.proxied {
+            // the proxy needs the whole URI to know where to forward to
+            self.url.serialize_no_fragment()
+        } else {
+            let mut uri = match unix_socket_parts(&self.url) {
+                Some((_, path)) => path,
+                None => self.url.serialize_path().unwrap()
+            };
+            //TODO: this needs a test
+            if let Some(ref q) = self.url.query {
+                uri.push('?');
+                uri.push_str(q[]);
+            }
+            uri
+        };
+
+        // an HTTP/1.0 server closes the connection after responding, unless
+        // asked to keep it open
+        if self.version == version::HttpVersion::Http10 &&
+                !self.headers.has::<common::Connection>() {
+            self.headers.set(common::Connection(vec![common::connection::KeepAlive]));
         }
 
         debug!("writing head: {} {} {}", self.method, uri, self.version);
@@ -161,6 +315,10 @@ impl Request<Fresh> {
                 try!(write!(&mut self.body, "{}", self.headers));
                 try!(self.body.write(LINE_ENDING));
 
+                if self.headers.get::<Expect>() == Some(&Expect::Continue) {
+                    self.rejected = try!(self.wait_for_continue());
+                }
+
                 if chunked {
                     ChunkedWriter(self.body.unwrap())
                 } else {
@@ -174,28 +332,149 @@ impl Request<Fresh> {
             headers: self.headers,
             url: self.url,
             version: self.version,
-            body: stream
+            body: stream,
+            proxied: self.proxied,
+            read_timeout: self.read_timeout,
+            first_byte_timeout: self.first_byte_timeout,
+            continue_timeout: self.continue_timeout,
+            rejected: self.rejected,
+            interim: self.interim,
+            header_limits: self.header_limits,
+            cancel: self.cancel,
         })
     }
 
+    /// Waits for the server to accept the body, returning the status line of
+    /// the final response it sent instead, if it refused it.
+    fn wait_for_continue(&mut self) -> HttpResult<Option<StatusLine>> {
+        try!(self.body.flush());
+        let stream = self.body.get_mut().get_mut();
+        stream.set_read_timeout(Some(self.continue_timeout));
+        let result = read_continue_status_line(stream, &self.header_limits, &mut self.interim);
+        stream.set_read_timeout(self.read_timeout);
+        match result {
+            Ok((_, RawStatus(100, _))) => {
+                try!(Headers::from_raw_with_limits(stream, &self.header_limits));
+                Ok(None)
+            },
+            Ok(status_line) => {
+                debug!("body refused with {}", status_line.1);
+                Ok(Some(status_line))
+            },
+            Err(HttpTimeoutError(_)) => {
+                debug!("no 100 Continue in time, sending body");
+                Ok(None)
+            },
+            Err(e) => Err(e)
+        }
+    }
+
     /// Get a mutable reference to the Request headers.
     #[inline]
     pub fn headers_mut(&mut self) -> &mut Headers { &mut self.headers }
 }
 
+/// Reads status lines until a `100 Continue` or a final status, keeping the
+/// other interim responses read before it.
+fn read_continue_status_line<R: Reader>(stream: &mut R, limits: &HeaderLimits,
+                                        interim: &mut Vec<(RawStatus, Headers)>)
+        -> HttpResult<StatusLine> {
+    loop {
+        let (version, status) = try!(read_status_line(stream));
+        if status.0 == 100 || !is_interim(&status) {
+            return Ok((version, status));
+        }
+        debug!("interim response {} {}", status.0, status.1);
+        let headers = try!(Headers::from_raw_with_limits(stream, limits));
+        interim.push((status, headers));
+    }
+}
+
+/// Splits a url of the `http+unix` scheme, such as
+/// `http+unix://%2Fvar%2Frun%2Fdocker.sock/v1/containers`, into the path of
+/// the socket and the path requested on it.
+fn unix_socket_parts(url: &Url) -> Option<(String, String)> {
+    if url.scheme[] != "http+unix" {
+        return None;
+    }
+    let data = match url.non_relative_scheme_data() {
+        Some(data) if data.starts_with("//") => data[2..],
+        _ => return None
+    };
+    let (socket, path) = match data.find('/') {
+        Some(i) => (data[..i], data[i..]),
+        None => (data, "/")
+    };
+    if socket.is_empty() {
+        return None;
+    }
+    Some((lossy_utf8_percent_decode(socket.as_bytes()), path.into_string()))
+}
+
 impl Request<Streaming> {
+    /// Whether the server refused the body of this request, in answer to
+    /// an `Expect: 100-continue` header.
+    ///
+    /// If so, anything written to the request is discarded.
+    #[inline]
+    pub fn is_rejected(&self) -> bool {
+        self.rejected.is_some()
+    }
+
     /// Completes writing the request, and returns a response to read from.
     ///
     /// Consumes the Request.
     pub fn send(self) -> HttpResult<Response> {
-        let raw = try!(self.body.end()).into_inner();
-        Response::new(raw)
+        self.send_with_handler(&mut ())
+    }
+
+    /// Completes writing the request, and returns a response to read from,
+    /// passing any interim `1xx` responses sent before it to `handler`.
+    ///
+    /// Consumes the Request.
+    pub fn send_with_handler(self, handler: &mut InformationalHandler) -> HttpResult<Response> {
+        // those read while waiting for a 100 Continue
+        for &(ref status, ref headers) in self.interim.iter() {
+            handler.informational(status, headers);
+        }
+        let mut res = try!(match self.rejected {
+            Some(status_line) => {
+                Response::rejected(self.body.unwrap().into_inner(), status_line, self.header_limits)
+            },
+            None => {
+                let mut raw = try!(self.body.end()).into_inner();
+                if self.first_byte_timeout.is_some() {
+                    raw.set_read_timeout(self.first_byte_timeout);
+                }
+                Response::with_informational_handler(raw, self.header_limits, handler)
+            }
+        });
+        if self.first_byte_timeout.is_some() {
+            res.set_read_timeout(self.read_timeout);
+        }
+        match self.cancel {
+            Some(ref watch) => res.set_cancel_handle(watch.handle.clone()),
+            None => ()
+        }
+        Ok(res)
+    }
+
+    /// Completes writing the request, without reading a response, and
+    /// returns the connection it was written to.
+    ///
+    /// More requests can then be written to the connection with
+    /// `Request::with_stream` before the responses are read, pipelining them.
+    pub fn end(self) -> HttpResult<Box<NetworkStream + Send>> {
+        Ok(try!(self.body.end()).into_inner())
     }
 }
 
 impl Writer for Request<Streaming> {
     #[inline]
     fn write(&mut self, msg: &[u8]) -> IoResult<()> {
+        if self.rejected.is_some() {
+            return Ok(());
+        }
         self.body.write(msg)
     }
 
@@ -208,11 +487,30 @@ impl Writer for Request<Streaming> {
 #[cfg(test)]
 mod tests {
     use std::boxed::BoxAny;
+    use std::io::IoResult;
+    use std::io::net::ip::Port;
     use std::str::from_utf8;
     use url::Url;
-    use method::Method::{Get, Head};
+    use header::common::Expect;
+    use method::Method::{Get, Head, Post};
+    use status::StatusCode;
+    use version::HttpVersion;
     use mock::{MockStream, MockConnector};
-    use super::Request;
+    use net::NetworkConnector;
+    use client::CancelHandle;
+    use super::{Request, unix_socket_parts};
+
+    struct MockProxyConnector;
+
+    impl NetworkConnector<MockStream> for MockProxyConnector {
+        fn connect(&mut self, _host: &str, _port: Port, _scheme: &str) -> IoResult<MockStream> {
+            Ok(MockStream::new())
+        }
+
+        fn is_proxied(&self, _host: &str, _port: Port, scheme: &str) -> bool {
+            scheme == "http"
+        }
+    }
 
     #[test]
     fn test_get_empty_body() {
@@ -227,6 +525,20 @@ mod tests {
         assert!(!s.contains("Transfer-Encoding:"));
     }
 
+    #[test]
+    fn test_http10_keep_alive() {
+        let mut req = Request::with_connector(
+            Get, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.version = HttpVersion::Http10;
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap().into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.starts_with("GET / HTTP/1.0\r\n"));
+        assert!(s.contains("Connection: keep-alive\r\n"));
+    }
+
     #[test]
     fn test_head_empty_body() {
         let req = Request::with_connector(
@@ -239,4 +551,130 @@ mod tests {
         assert!(!s.contains("Content-Length:"));
         assert!(!s.contains("Transfer-Encoding:"));
     }
+
+    #[test]
+    fn test_origin_form_uri() {
+        let req = Request::with_connector(
+            Get, Url::parse("http://example.dom/foo?q=1#frag").unwrap(), &mut MockConnector
+        ).unwrap();
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap().into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.starts_with("GET /foo?q=1 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_proxied_absolute_uri() {
+        let req = Request::with_connector(
+            Get, Url::parse("http://example.dom/foo?q=1#frag").unwrap(), &mut MockProxyConnector
+        ).unwrap();
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap().into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.starts_with("GET http://example.dom/foo?q=1 HTTP/1.1\r\n"));
+        assert!(s.contains("Host: example.dom"));
+    }
+
+    #[test]
+    fn test_tunneled_origin_form_uri() {
+        let req = Request::with_connector(
+            Get, Url::parse("https://example.dom/foo").unwrap(), &mut MockProxyConnector
+        ).unwrap();
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap().into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.starts_with("GET /foo HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_unix_socket_uri() {
+        let url = Url::parse("http+unix://%2Fvar%2Frun%2Fdocker.sock/v1/containers?all=1").unwrap();
+        assert_eq!(unix_socket_parts(&url), Some(("/var/run/docker.sock".into_string(),
+                                                  "/v1/containers".into_string())));
+        let req = Request::with_connector(Get, url, &mut MockConnector).unwrap();
+        let req = req.start().unwrap();
+        let stream = *req.body.end().unwrap().into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.starts_with("GET /v1/containers?all=1 HTTP/1.1\r\n"));
+        assert!(s.contains("Host: localhost\r\n"));
+    }
+
+    #[test]
+    fn test_unix_socket_parts_invalid() {
+        assert_eq!(unix_socket_parts(&Url::parse("http://example.dom/").unwrap()), None);
+        assert_eq!(unix_socket_parts(&Url::parse("http+unix:/v1").unwrap()), None);
+    }
+
+    mock_connector!(MockContinue {
+        "http://127.0.0.1" =>       "HTTP/1.1 100 Continue\r\n\
+                                     \r\n\
+                                     HTTP/1.1 200 OK\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+        "http://127.0.0.2" =>       "HTTP/1.1 417 Expectation Failed\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    #[test]
+    fn test_expect_continue() {
+        let mut req = Request::with_connector(
+            Post, Url::parse("http://127.0.0.1").unwrap(), &mut MockContinue
+        ).unwrap();
+        req.headers_mut().set(Expect::Continue);
+        let mut req = req.start().unwrap();
+        assert!(!req.is_rejected());
+        req.write(b"foo").unwrap();
+        let res = req.send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let stream = *res.into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(s.contains("Expect: 100-continue\r\n"));
+        assert!(s.contains("foo"));
+    }
+
+    #[test]
+    fn test_expect_continue_rejected() {
+        let mut req = Request::with_connector(
+            Post, Url::parse("http://127.0.0.2").unwrap(), &mut MockContinue
+        ).unwrap();
+        req.headers_mut().set(Expect::Continue);
+        let mut req = req.start().unwrap();
+        assert!(req.is_rejected());
+        req.write(b"foo").unwrap();
+        let res = req.send().unwrap();
+        assert_eq!(res.status, StatusCode::ExpectationFailed);
+        let stream = *res.into_inner().downcast::<MockStream>().unwrap();
+        let bytes = stream.write.into_inner();
+        let s = from_utf8(bytes[]).unwrap();
+        assert!(!s.contains("foo"));
+    }
+
+    #[test]
+    fn test_cancel_handle_unwatched() {
+        let handle = CancelHandle::new();
+        let mut req = Request::with_connector(
+            Get, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.set_cancel_handle(handle.clone());
+        assert!(handle.is_watching());
+        drop(req);
+        assert!(!handle.is_watching());
+
+        // the mock sends no response, so sending fails
+        let mut req = Request::with_connector(
+            Get, Url::parse("http://example.dom").unwrap(), &mut MockConnector
+        ).unwrap();
+        req.set_cancel_handle(handle.clone());
+        assert!(req.start().unwrap().send().is_err());
+        assert!(!handle.is_watching());
+        assert!(!handle.is_cancelled());
+    }
 }
@@ -1,17 +1,46 @@
 //! Client Responses
+use std::any::AnyMutRefExt;
+use std::ascii::AsciiExt;
+use std::borrow::Cow::Borrowed;
+use std::cmp;
+use std::default::Default;
+use std::error::FromError;
+use std::mem;
 use std::num::FromPrimitive;
-use std::io::{BufferedReader, IoResult};
+use std::str::from_utf8;
+use std::io::{BufferedReader, IoError, IoResult, ConnectionAborted, EndOfFile, InvalidInput, NotConnected,
+              OtherIoError, MemReader};
+use std::io::net::ip::SocketAddr;
+use std::time::Duration;
 
 use header;
-use header::common::{ContentLength, TransferEncoding};
+use header::common::{Connection, ContentEncoding, ContentLength, ContentMd5, ContentType, TransferEncoding};
+use header::common::connection::{KeepAlive, Close};
 use header::common::transfer_encoding::Encoding::Chunked;
-use net::{NetworkStream, HttpStream};
-use http::{read_status_line, HttpReader, RawStatus};
-use http::HttpReader::{SizedReader, ChunkedReader, EofReader};
+use net::{ConnectTimings, NetworkStream, HttpStream, RateLimit, TlsInfo};
+use http::{is_interim, read_status_line, HeaderLimits, HttpReader, RawStatus, StatusLine};
+use http::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader};
+use mime::Mime;
+use openssl::crypto::hash::{HashType, Hasher};
+#[cfg(feature = "json")]
+use mime::TopLevel::Application;
+#[cfg(feature = "json")]
+use mime::SubLevel::{mod, Json};
+#[cfg(feature = "json")]
+use serialize::Decodable;
+#[cfg(feature = "json")]
+use serialize::json;
 use status;
+use status::StatusCode::{NoContent, NotModified};
+use status::StatusClass::Informational;
+use time::precise_time_ns;
 use version;
-use HttpResult;
-use HttpError::HttpStatusError;
+use version::HttpVersion::{Http10, Http11};
+use {HttpError, HttpResult};
+use HttpError::{HttpStatusError, HttpIoError, HttpIntegrityError};
+use client::{CancelHandle, deadline_exceeded};
+use client::{Decoder, Decoders};
+use client::pool::PooledStream;
 
 /// A response for a client request to a remote server.
 pub struct Response<S = HttpStream> {
@@ -21,26 +50,211 @@ pub struct Response<S = HttpStream> {
     pub headers: header::Headers,
     /// The HTTP version of this response from the server.
     pub version: version::HttpVersion,
+    /// How long the request for this response took, if it was timed.
+    pub timings: Option<Timings>,
     status_raw: RawStatus,
     body: HttpReader<BufferedReader<Box<NetworkStream + Send>>>,
+    keep_alive: bool,
+    decoder: Option<Box<Decoder + Send>>,
+    max_body_size: Option<u64>,
+    body_read: u64,
+    decoded_read: u64,
+    cancel: Option<CancelHandle>,
+    // the generation of the stream the cancel handle watches
+    cancel_watch: uint,
+    rate_limit: Option<RateLimit>,
+    read_timeout: Option<Duration>,
+    deadline: Option<u64>,
+    tls_info: Option<TlsInfo>,
+    digest: Option<(BodyDigest, Hasher)>,
+    digest_mismatch: bool,
+    trailers: Option<header::Headers>,
+    header_limits: HeaderLimits,
+}
+
+/// How long each step of a request took.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Timings {
+    /// Making the connection, unless an idle one was reused.
+    pub connect: Option<ConnectTimings>,
+    /// From the request being sent until its response head was read.
+    pub first_byte: Duration,
+    /// From the start of the request until its response head was read.
+    pub total: Duration,
+}
+
+/// A digest that the body of a Response is checked against as it is read.
+#[deriving(Clone, PartialEq, Show)]
+pub enum BodyDigest {
+    /// An MD5 digest, such as one sent in a `Content-MD5` header.
+    Md5(Vec<u8>),
+    /// A SHA-1 digest.
+    Sha1(Vec<u8>),
+    /// A SHA-256 digest.
+    Sha256(Vec<u8>),
+}
+
+impl BodyDigest {
+    fn hasher(&self) -> Hasher {
+        Hasher::new(match *self {
+            BodyDigest::Md5(_) => HashType::MD5,
+            BodyDigest::Sha1(_) => HashType::SHA1,
+            BodyDigest::Sha256(_) => HashType::SHA256,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            BodyDigest::Md5(ref bytes) | BodyDigest::Sha1(ref bytes) | BodyDigest::Sha256(ref bytes) => bytes[]
+        }
+    }
+}
+
+/// The `desc` of the error reading fails with when the body does not match
+/// its expected digest, which methods returning an `HttpResult` fail with
+/// as an `HttpIntegrityError`.
+pub static DIGEST_MISMATCH: &'static str = "Response body does not match its digest";
+
+/// Receives the interim `1xx` responses sent before a final response, such
+/// as `102 Processing` or `103 Early Hints`.
+pub trait InformationalHandler {
+    /// Called with the status and headers of each interim response.
+    fn informational(&mut self, status: &RawStatus, headers: &header::Headers);
+}
+
+/// Ignores every interim response.
+impl InformationalHandler for () {
+    fn informational(&mut self, _status: &RawStatus, _headers: &header::Headers) {}
 }
 
 impl Response {
 
     /// Creates a new response from a server.
     pub fn new(stream: Box<NetworkStream + Send>) -> HttpResult<Response> {
-        let mut stream = BufferedReader::new(stream);
-        let (version, raw_status) = try!(read_status_line(&mut stream));
+        Response::with_header_limits(stream, Default::default())
+    }
+
+    /// Creates a new response from a server, whose headers must be within
+    /// `limits`.
+    pub fn with_header_limits(stream: Box<NetworkStream + Send>, limits: HeaderLimits) -> HttpResult<Response> {
+        Response::from_buffered(BufferedReader::new(stream), &limits, &mut ())
+    }
+
+    /// Creates a new response from a server, whose headers must be within
+    /// `limits`, passing any interim responses sent before it to `handler`.
+    pub fn with_informational_handler(stream: Box<NetworkStream + Send>, limits: HeaderLimits,
+                                      handler: &mut InformationalHandler) -> HttpResult<Response> {
+        Response::from_buffered(BufferedReader::new(stream), &limits, handler)
+    }
+
+    /// Reads a whole response from `stream`, one of several pipelined on a
+    /// connection, returning it held in memory along with the stream to read
+    /// the next response from.
+    ///
+    /// The response to a HEAD request has no body, whatever its headers say.
+    /// Only the `last` response allows the connection back into its pool.
+    #[doc(hidden)]
+    pub fn read_pipelined(stream: BufferedReader<Box<NetworkStream + Send>>, limits: &HeaderLimits,
+                          head: bool, last: bool)
+            -> HttpResult<(Response, BufferedReader<Box<NetworkStream + Send>>)> {
+        let mut res = try!(Response::from_buffered(stream, limits, &mut ()));
+        let reusable = if head {
+            res.body = EmptyReader(res.take_reader());
+            keep_alive(res.version, &res.headers)
+        } else {
+            res.keep_alive
+        };
+        if !reusable && !last {
+            debug!("server closes the connection before answering every pipelined request");
+            return Err(HttpIoError(IoError {
+                kind: ConnectionAborted,
+                desc: "Server closes the connection before answering every pipelined request",
+                detail: None
+            }));
+        }
+        res.keep_alive = reusable && last;
+        let body = try!(res.read_to_end());
+        let stream = res.take_reader();
+        let headers = mem::replace(&mut res.headers, header::Headers::new());
+        Ok((Response::from_parts(res.status, res.version, headers, body), stream))
+    }
+
+    fn from_buffered(mut stream: BufferedReader<Box<NetworkStream + Send>>, limits: &HeaderLimits,
+                     handler: &mut InformationalHandler) -> HttpResult<Response> {
+        loop {
+            let (version, status) = try!(read_status_line(&mut stream));
+            if !is_interim(&status) {
+                return Response::with_status_line(stream, (version, status), limits);
+            }
+            // such as a 100 Continue sent after the request stopped waiting
+            // for it, or 103 Early Hints
+            debug!("interim response {} {}", status.0, status.1);
+            let headers = try!(header::Headers::from_raw_with_limits(&mut stream, limits));
+            handler.informational(&status, &headers);
+        }
+    }
+
+    /// Creates a response held in memory, such as one served from a cache.
+    ///
+    /// There is no connection underneath it, so the stream returned by
+    /// `into_inner` can only be read from.
+    pub fn from_parts(status: status::StatusCode, version: version::HttpVersion,
+                      headers: header::Headers, body: Vec<u8>) -> Response {
+        let len = body.len();
+        let stream = box MemoryStream(MemReader::new(body)) as Box<NetworkStream + Send>;
+        Response {
+            status: status,
+            version: version,
+            headers: headers,
+            timings: None,
+            body: SizedReader(BufferedReader::new(stream), len),
+            status_raw: RawStatus(status as u16, Borrowed(status.canonical_reason().unwrap_or(""))),
+            keep_alive: false,
+            decoder: None,
+            max_body_size: None,
+            body_read: 0,
+            decoded_read: 0,
+            cancel: None,
+            cancel_watch: 0,
+            rate_limit: None,
+            read_timeout: None,
+            deadline: None,
+            tls_info: None,
+            digest: None,
+            digest_mismatch: false,
+            trailers: None,
+            header_limits: Default::default(),
+        }
+    }
+
+    /// Creates a response to a request whose body was refused, from a stream
+    /// its status line was already read from.
+    ///
+    /// The connection is never reused, since the server may still expect
+    /// the body that was not sent.
+    #[doc(hidden)]
+    pub fn rejected(stream: Box<NetworkStream + Send>, status_line: StatusLine,
+                    limits: HeaderLimits) -> HttpResult<Response> {
+        let mut res = try!(Response::with_status_line(BufferedReader::new(stream), status_line, &limits));
+        res.keep_alive = false;
+        Ok(res)
+    }
+
+    fn with_status_line(mut stream: BufferedReader<Box<NetworkStream + Send>>,
+                        (version, raw_status): StatusLine, limits: &HeaderLimits) -> HttpResult<Response> {
         let status = match FromPrimitive::from_u16(raw_status.0) {
             Some(status) => status,
             None => return Err(HttpStatusError)
         };
         debug!("{} {}", version, status);
+        let tls_info = stream.get_ref().tls_info();
 
-        let headers = try!(header::Headers::from_raw(&mut stream));
+        let headers = try!(header::Headers::from_raw_with_limits(&mut stream, limits));
         debug!("{}", headers);
 
-        let body = if headers.has::<TransferEncoding>() {
+        let body = if status.class() == Informational || status == NoContent || status == NotModified {
+            EmptyReader(stream)
+        } else if headers.has::<TransferEncoding>() {
             match headers.get::<TransferEncoding>() {
                 Some(&TransferEncoding(ref codings)) => {
                     if codings.len() > 1 {
@@ -66,12 +280,35 @@ impl Response {
             EofReader(stream)
         };
 
+        // A body delimited by the connection closing can never be reused.
+        let keep_alive = match body {
+            EofReader(..) => false,
+            _ => keep_alive(version, &headers)
+        };
+        debug!("keep_alive = {}", keep_alive);
+
         Ok(Response {
             status: status,
             version: version,
             headers: headers,
+            timings: None,
             body: body,
             status_raw: raw_status,
+            keep_alive: keep_alive,
+            decoder: None,
+            max_body_size: None,
+            body_read: 0,
+            decoded_read: 0,
+            cancel: None,
+            cancel_watch: 0,
+            rate_limit: None,
+            read_timeout: None,
+            deadline: None,
+            tls_info: tls_info,
+            digest: None,
+            digest_mismatch: false,
+            trailers: None,
+            header_limits: limits.clone(),
         })
     }
 
@@ -80,35 +317,569 @@ impl Response {
         &self.status_raw
     }
 
+    /// The trailers sent after the body of a chunked response, such as
+    /// those announced by a `Trailer` header.
+    ///
+    /// They are only read once the body has been read to the end, so until
+    /// then, and for a response that is not chunked, this is `None`.
+    pub fn trailers(&self) -> Option<&header::Headers> {
+        self.trailers.as_ref()
+    }
+
+    /// What was negotiated in the TLS handshake of the connection this
+    /// Response was read from, if it was made over TLS.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
+
     /// Consumes the Request to return the NetworkStream underneath.
-    pub fn into_inner(self) -> Box<NetworkStream + Send> {
-        self.body.unwrap().into_inner()
+    pub fn into_inner(mut self) -> Box<NetworkStream + Send> {
+        self.take_reader().into_inner()
+    }
+
+    /// Reads and discards the rest of the body, so that the connection can
+    /// be reused for another request.
+    ///
+    /// Dropping a Response does this by itself for up to 64KB of body, and
+    /// closes the connection instead if more is left.
+    pub fn drain(&mut self) -> HttpResult<()> {
+        let mut buf = [0u8, ..4096];
+        loop {
+            match self.read(&mut buf) {
+                Ok(_) => (),
+                Err(ref e) if e.kind == EndOfFile => return Ok(()),
+                Err(e) => return Err(self.read_error(e))
+            }
+        }
+    }
+
+    /// The `HttpError` for an error reading the body, which is an
+    /// `HttpIntegrityError` if the body did not match its digest.
+    fn read_error(&self, err: IoError) -> HttpError {
+        if self.digest_mismatch {
+            HttpIntegrityError(err)
+        } else {
+            FromError::from_error(err)
+        }
+    }
+
+    /// Reads the rest of the body into `w`, returning how many bytes were
+    /// written.
+    ///
+    /// The body is streamed through a buffer, rather than read into memory
+    /// first as `read_to_end` does.
+    pub fn copy_to(&mut self, w: &mut Writer) -> IoResult<u64> {
+        let mut buf = [0u8, ..8192];
+        let mut copied = 0u64;
+        loop {
+            let n = match self.read(&mut buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind == EndOfFile => return Ok(copied),
+                Err(e) => return Err(e)
+            };
+            try!(w.write(buf[..n]));
+            copied += n as u64;
+        }
+    }
+
+    /// Set a handle that cancels reading this Response from another task.
+    ///
+    /// Once cancelled, reading returns an error.
+    pub fn set_cancel_handle(&mut self, handle: CancelHandle) {
+        self.cancel_watch = handle.watch(self.body.get_mut().get_ref().clone());
+        self.cancel = Some(handle);
+    }
+
+    /// Set the read timeout of the underlying stream.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.body.get_mut().get_mut().set_read_timeout(timeout)
+    }
+
+    /// Set how long from now the body of this Response may be read for.
+    ///
+    /// Each read is given no longer than the time left, and once it runs out
+    /// reading fails with a `TimedOut` error. Pass `None` for no deadline,
+    /// which is the default.
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline.map(|deadline| {
+            precise_time_ns() + cmp::max(deadline.num_nanoseconds().unwrap_or(0), 0) as u64
+        });
+    }
+
+    /// Set the digest the body of this Response is expected to have.
+    ///
+    /// The body is hashed as it is read, and reading its end fails with an
+    /// `InvalidInput` error if the digest does not match. It must be set
+    /// before any of the body is read. Pass `None` to not check the body,
+    /// which is the default.
+    pub fn set_expected_digest(&mut self, digest: Option<BodyDigest>) {
+        self.digest = digest.map(|digest| {
+            let hasher = digest.hasher();
+            (digest, hasher)
+        });
+    }
+
+    /// Check the body of this Response against its `Content-MD5` header, if
+    /// it has one, as with `set_expected_digest`.
+    pub fn verify_content_md5(&mut self) {
+        let digest = match self.headers.get::<ContentMd5>() {
+            Some(&ContentMd5(ref md5)) => BodyDigest::Md5(md5.clone()),
+            None => return
+        };
+        self.set_expected_digest(Some(digest));
+    }
+
+    /// Set the most bytes of body that may be read from this Response.
+    ///
+    /// Reading past the limit returns an error instead, so that a huge or
+    /// endless body cannot use up all memory. A decompressed body is held to
+    /// the limit both as it was sent and as it is decoded, so that a small
+    /// compressed body cannot decode to a huge one. This errors at once if the
+    /// Content-Length header declares a larger body. Pass `None` to read
+    /// bodies of any size, which is the default.
+    pub fn set_max_body_size(&mut self, max: Option<u64>) -> IoResult<()> {
+        self.max_body_size = max;
+        match (max, self.headers.get::<ContentLength>()) {
+            (Some(max), Some(&ContentLength(len))) if len as u64 > max => Err(body_too_large(max)),
+            _ => Ok(())
+        }
+    }
+
+    /// Set the most bytes a second the body of this Response is read at.
+    ///
+    /// Reads sleep as needed to keep to the limit. A limit of zero is no
+    /// limit. Pass `None` to read as fast as the body arrives, which is the
+    /// default.
+    pub fn set_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.rate_limit = bytes_per_sec.map(RateLimit::new);
+    }
+
+    /// Reads the whole body, decoding it to a `String` with the charset
+    /// given by the Content-Type header.
+    ///
+    /// Bodies in `utf-8`, `iso-8859-1`, and `us-ascii` are decoded. Bodies
+    /// in any other charset, or without one, are decoded as UTF-8 if they
+    /// are valid UTF-8, and as Latin-1 otherwise.
+    pub fn read_string(&mut self) -> HttpResult<String> {
+        let body = match self.read_to_end() {
+            Ok(body) => body,
+            Err(e) => return Err(self.read_error(e))
+        };
+        let charset = match self.headers.get::<ContentType>() {
+            Some(&ContentType(ref mime)) => charset(mime),
+            None => None
+        };
+        debug!("decoding body with charset {}", charset);
+        match charset.as_ref().map(|charset| charset[]) {
+            Some("utf-8") | Some("utf8") => match String::from_utf8(body) {
+                Ok(text) => Ok(text),
+                Err(_) => Err(HttpIoError(IoError {
+                    kind: InvalidInput,
+                    desc: "Response body is not valid UTF-8",
+                    detail: None
+                }))
+            },
+            Some("iso-8859-1") | Some("latin1") | Some("us-ascii") => Ok(latin1(body[])),
+            _ => match String::from_utf8(body) {
+                Ok(text) => Ok(text),
+                Err(body) => Ok(latin1(body[]))
+            }
+        }
+    }
+
+    /// Reads the whole body, decoding it from JSON.
+    ///
+    /// The Content-Type of the response must be `application/json`, or
+    /// another JSON type such as `application/vnd.api+json`.
+    #[cfg(feature = "json")]
+    pub fn decode_json<T: Decodable<json::Decoder, json::DecoderError>>(&mut self) -> HttpResult<T> {
+        let is_json = match self.headers.get::<ContentType>() {
+            Some(&ContentType(Mime(Application, Json, _))) => true,
+            Some(&ContentType(Mime(Application, SubLevel::Ext(ref sub), _))) => sub[].ends_with("+json"),
+            _ => false
+        };
+        if !is_json {
+            return Err(HttpIoError(IoError {
+                kind: InvalidInput,
+                desc: "Response is not JSON",
+                detail: self.headers.get::<ContentType>().map(|mime| mime.to_string())
+            }));
+        }
+        let body = try!(self.read_string());
+        json::decode(body[]).map_err(|e| HttpIoError(IoError {
+            kind: InvalidInput,
+            desc: "Invalid JSON body",
+            detail: Some(e.to_string())
+        }))
+    }
+
+    /// Decodes a body compressed with `gzip` or `deflate`, as indicated by
+    /// the Content-Encoding header, so that reading returns plain bytes.
+    ///
+    /// The body is decoded as it is read, and a body that isn't validly
+    /// encoded is an error of the read that finds it so. The Content-Encoding
+    /// and Content-Length headers are removed. A body with any other encoding
+    /// is left as it is.
+    pub fn decompress(&mut self) -> HttpResult<()> {
+        self.decompress_with(&Decoders::new())
+    }
+
+    /// Decodes a compressed body like `decompress`, with the decoders of
+    /// `decoders` rather than only the built in ones.
+    pub fn decompress_with(&mut self, decoders: &Decoders) -> HttpResult<()> {
+        let decoder = match self.headers.get::<ContentEncoding>() {
+            Some(&ContentEncoding(ref encodings)) if encodings.len() == 1 => match decoders.get(&encodings[0]) {
+                Some(decoder) => decoder,
+                None => return Ok(())
+            },
+            _ => return Ok(())
+        };
+        // an empty body has nothing to decode, whatever its encoding
+        match self.body {
+            EmptyReader(_) | SizedReader(_, 0) => (),
+            _ => self.decoder = Some(decoder.decoder())
+        }
+        self.headers.remove::<ContentEncoding>();
+        self.headers.remove::<ContentLength>();
+        Ok(())
+    }
+
+}
+
+/// The most body a Response drains when dropped before being read to the end.
+const DRAIN_ON_DROP_LIMIT: uint = 64 * 1024;
+
+impl<S> Response<S> {
+    /// Takes the stream the body is read from, leaving an empty body. The
+    /// stream is taken over as it is, so it is not drained on drop.
+    fn take_reader(&mut self) -> BufferedReader<Box<NetworkStream + Send>> {
+        self.keep_alive = false;
+        let empty = box MemoryStream(MemReader::new(vec![])) as Box<NetworkStream + Send>;
+        mem::replace(&mut self.body, EmptyReader(BufferedReader::new(empty))).unwrap()
+    }
+
+    /// Called once the body has been read to the end. If the connection
+    /// can be kept alive, a pooled stream is allowed back into its pool.
+    fn release(&mut self) {
+        self.unwatch();
+        let timeout = keep_alive_timeout(&self.headers);
+        let stream: &mut NetworkStream = &mut **self.body.get_mut().get_mut();
+        match stream.downcast_mut::<PooledStream>() {
+            Some(pooled) => {
+                pooled.mark_reusable();
+                timeout.map(|timeout| pooled.set_idle_timeout(timeout));
+            },
+            None => ()
+        }
+    }
+
+    /// Stops the cancel handle watching the stream, which this is done with.
+    fn unwatch(&self) {
+        match self.cancel {
+            Some(ref handle) => handle.unwatch(self.cancel_watch),
+            None => ()
+        }
+    }
+
+    /// Reads the trailers after the last chunk of a chunked body, so that
+    /// the connection is left at the end of this response.
+    fn read_trailers(&mut self) -> IoResult<()> {
+        match self.body {
+            ChunkedReader(_, Some(0)) if self.trailers.is_none() => (),
+            _ => return Ok(())
+        }
+        match header::Headers::from_raw_with_limits(self.body.get_mut(), &self.header_limits) {
+            Ok(trailers) => {
+                debug!("trailers [\n{}]", trailers);
+                self.trailers = Some(trailers);
+                Ok(())
+            },
+            Err(HttpIoError(e)) => Err(e),
+            Err(e) => Err(IoError {
+                kind: InvalidInput,
+                desc: "Invalid trailers",
+                detail: Some(e.to_string())
+            })
+        }
+    }
+
+    /// Reads what is left of the body, so the connection can be kept alive,
+    /// unless that is more than `DRAIN_ON_DROP_LIMIT`. An undrained
+    /// connection is closed when its stream is dropped.
+    fn drain_on_drop(&mut self) {
+        if !self.keep_alive ||
+                self.cancel.as_ref().map_or(false, |handle| handle.is_cancelled()) {
+            return;
+        }
+        match self.body {
+            SizedReader(_, remaining) if remaining > DRAIN_ON_DROP_LIMIT => {
+                debug!("dropped with {} bytes of body unread, closing", remaining);
+                return;
+            },
+            _ => ()
+        }
+        let mut buf = [0u8, ..4096];
+        let mut drained = 0;
+        while drained <= DRAIN_ON_DROP_LIMIT {
+            match self.body.read(&mut buf) {
+                Ok(count) => drained += count,
+                Err(ref e) if e.kind == EndOfFile => {
+                    debug!("drained {} bytes of body on drop", drained);
+                    if self.read_trailers().is_ok() {
+                        self.release();
+                    }
+                    return;
+                },
+                Err(_) => break
+            }
+        }
+        debug!("could not drain body on drop, closing");
+    }
+}
+
+#[unsafe_destructor]
+impl<S> Drop for Response<S> {
+    fn drop(&mut self) {
+        self.drain_on_drop();
+        self.unwatch();
     }
 }
 
 impl Reader for Response {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
-        self.body.read(buf)
+        match self.decoder.take() {
+            Some(mut decoder) => {
+                let result = decoder.read(&mut RawBody(self), buf);
+                self.decoder = Some(decoder);
+                let count = try!(result);
+                self.decoded_read += count as u64;
+                match self.max_body_size {
+                    Some(max) if self.decoded_read > max => Err(body_too_large(max)),
+                    _ => Ok(count)
+                }
+            },
+            None => self.read_raw(buf)
+        }
+    }
+}
+
+/// The body of a Response as it was sent, which its decoder reads.
+struct RawBody<'a>(&'a mut Response);
+
+impl<'a> Reader for RawBody<'a> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.0.read_raw(buf)
+    }
+}
+
+impl Response {
+    /// Read the body as it was sent, before any decoding.
+    fn read_raw(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        match self.deadline {
+            Some(deadline) => {
+                let now = precise_time_ns();
+                if now >= deadline {
+                    return Err(deadline_exceeded());
+                }
+                let remaining = Duration::nanoseconds((deadline - now) as i64);
+                let timeout = match self.read_timeout {
+                    Some(timeout) if timeout < remaining => timeout,
+                    _ => remaining
+                };
+                self.body.get_mut().get_mut().set_read_timeout(Some(timeout));
+            },
+            None => ()
+        }
+        let result = match self.rate_limit {
+            Some(ref mut limit) => {
+                let len = limit.chunk(buf.len());
+                self.body.read(buf[mut ..len])
+            },
+            None => self.body.read(buf)
+        };
+        match result {
+            Ok(count) => {
+                match self.rate_limit {
+                    Some(ref mut limit) => limit.transferred(count),
+                    None => ()
+                }
+                match self.digest {
+                    Some((_, ref mut hasher)) => hasher.update(buf[..count]),
+                    None => ()
+                }
+                self.body_read += count as u64;
+                match self.max_body_size {
+                    Some(max) if self.body_read > max => return Err(body_too_large(max)),
+                    _ => ()
+                }
+            },
+            Err(_) if self.cancel.as_ref().map_or(false, |handle| handle.is_cancelled()) => {
+                return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "Request was cancelled",
+                    detail: None
+                });
+            },
+            Err(ref e) if e.kind == EndOfFile => {
+                match self.read_trailers() {
+                    Ok(()) => (),
+                    Err(e) => {
+                        self.keep_alive = false;
+                        return Err(e);
+                    }
+                }
+                if self.keep_alive {
+                    self.release();
+                }
+                match self.digest.take() {
+                    Some((expected, mut hasher)) => if hasher.finalize()[] != expected.bytes() {
+                        debug!("body does not match {}", expected);
+                        self.digest_mismatch = true;
+                        return Err(IoError {
+                            kind: InvalidInput,
+                            desc: DIGEST_MISMATCH,
+                            detail: None
+                        });
+                    },
+                    None => ()
+                }
+            },
+            _ => ()
+        }
+        result
+    }
+}
+
+/// The charset parameter of a media type, in lowercase.
+fn charset(mime: &Mime) -> Option<String> {
+    let Mime(_, _, ref params) = *mime;
+    params.iter()
+        .find(|&&(ref attr, _)| attr.to_string()[].eq_ignore_ascii_case("charset"))
+        .map(|&(_, ref value)| value.to_string().into_ascii_lower())
+}
+
+/// Each byte of Latin-1 is the code point of the same value.
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn body_too_large(max: u64) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: "Response body is larger than the maximum size",
+        detail: Some(format!("more than {} bytes", max))
+    }
+}
+
+/// The stream underneath a Response that is held in memory.
+struct MemoryStream(MemReader);
+
+impl Clone for MemoryStream {
+    fn clone(&self) -> MemoryStream {
+        MemoryStream(MemReader::new(self.0.get_ref().to_vec()))
+    }
+}
+
+impl Reader for MemoryStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.0.read(buf)
+    }
+}
+
+impl Writer for MemoryStream {
+    fn write(&mut self, _msg: &[u8]) -> IoResult<()> {
+        Err(not_connected())
+    }
+}
+
+impl NetworkStream for MemoryStream {
+    fn peer_name(&mut self) -> IoResult<SocketAddr> {
+        Err(not_connected())
     }
 }
 
+fn not_connected() -> IoError {
+    IoError {
+        kind: NotConnected,
+        desc: "Response is held in memory, and has no connection",
+        detail: None
+    }
+}
+
+fn keep_alive(version: version::HttpVersion, headers: &header::Headers) -> bool {
+    match (version, headers.get::<Connection>()) {
+        (Http10, Some(conn)) if conn.contains(&KeepAlive) => true,
+        (Http11, Some(conn)) if conn.contains(&Close) => false,
+        (Http11, _) => true,
+        _ => false
+    }
+}
+
+/// How long the server keeps the connection open while idle, from a
+/// `Keep-Alive` header such as `Keep-Alive: timeout=5, max=100`.
+///
+/// A timeout that is negative, or too long for a `u32` of seconds, is
+/// ignored.
+fn keep_alive_timeout(headers: &header::Headers) -> Option<Duration> {
+    let raw = match headers.get_raw("Keep-Alive") {
+        Some(raw) => raw,
+        None => return None
+    };
+    for line in raw.iter() {
+        let line = match from_utf8(line[]) {
+            Some(line) => line,
+            None => continue
+        };
+        for param in line.split(',') {
+            let mut parts = param.splitn(1, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("timeout") => {
+                    return from_str::<u32>(value.trim()).map(|secs| Duration::seconds(secs as i64));
+                },
+                _ => ()
+            }
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow::Borrowed;
     use std::boxed::BoxAny;
-    use std::io::BufferedReader;
+    use std::default::Default;
+    use std::io::{BufferedReader, MemWriter, TimedOut};
+    use std::time::Duration;
 
+    use client::decode::gzip;
     use header::Headers;
+    use header::common::{ContentEncoding, ContentLength, ContentMd5, ContentType};
     use http::HttpReader::EofReader;
-    use http::RawStatus;
+    use http::{HeaderLimits, RawStatus};
     use mock::MockStream;
     use net::NetworkStream;
     use status;
     use version;
+    use HttpErrorKind;
+    use HttpError::HttpHeadersTooLargeError;
+
+    use super::{BodyDigest, DIGEST_MISMATCH, InformationalHandler, Response, keep_alive_timeout};
 
-    use super::Response;
 
+    #[test]
+    fn test_keep_alive_timeout() {
+        let mut headers = Headers::new();
+        headers.set_raw("Keep-Alive", vec![b"timeout=5, max=100".to_vec()]);
+        assert_eq!(keep_alive_timeout(&headers), Some(Duration::seconds(5)));
+        headers.set_raw("Keep-Alive", vec![b"timeout=99999999999999999".to_vec()]);
+        assert_eq!(keep_alive_timeout(&headers), None);
+        headers.set_raw("Keep-Alive", vec![b"timeout=-5".to_vec()]);
+        assert_eq!(keep_alive_timeout(&headers), None);
+    }
 
     #[test]
     fn test_unwrap() {
@@ -116,12 +887,351 @@ mod tests {
             status: status::StatusCode::Ok,
             headers: Headers::new(),
             version: version::HttpVersion::Http11,
+            timings: None,
             body: EofReader(BufferedReader::new(box MockStream::new() as Box<NetworkStream + Send>)),
-            status_raw: RawStatus(200, Borrowed("OK"))
+            status_raw: RawStatus(200, Borrowed("OK")),
+            keep_alive: false,
+            decoder: None,
+            max_body_size: None,
+            body_read: 0,
+            decoded_read: 0,
+            cancel: None,
+            cancel_watch: 0,
+            rate_limit: None,
+            read_timeout: None,
+            deadline: None,
+            tls_info: None,
+            digest: None,
+            digest_mismatch: false,
+            trailers: None,
+            header_limits: Default::default(),
         };
 
         let b = res.into_inner().downcast::<MockStream>().unwrap();
         assert_eq!(b, box MockStream::new());
 
     }
+
+    #[test]
+    fn test_keep_alive() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 0\r\n\
+            \r\n\
+        ") as Box<NetworkStream + Send>;
+        assert!(Response::new(stream).unwrap().keep_alive);
+
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Connection: close\r\n\
+            Content-Length: 0\r\n\
+            \r\n\
+        ") as Box<NetworkStream + Send>;
+        assert!(!Response::new(stream).unwrap().keep_alive);
+    }
+
+    #[test]
+    fn test_no_keep_alive_without_length() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            \r\n\
+            read until close\
+        ") as Box<NetworkStream + Send>;
+        assert!(!Response::new(stream).unwrap().keep_alive);
+    }
+
+    #[test]
+    fn test_drain() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            0\r\n\r\n\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.drain().unwrap();
+        assert_eq!(res.read_to_end().unwrap(), vec![]);
+    }
+
+    struct MockInformational(Vec<u16>);
+
+    impl InformationalHandler for MockInformational {
+        fn informational(&mut self, status: &RawStatus, _headers: &Headers) {
+            self.0.push(status.0);
+        }
+    }
+
+    #[test]
+    fn test_skips_informational() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 102 Processing\r\n\
+            \r\n\
+            HTTP/1.1 103 Early Hints\r\n\
+            Link: </style.css>; rel=preload\r\n\
+            \r\n\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ") as Box<NetworkStream + Send>;
+        let mut handler = MockInformational(vec![]);
+        let mut res = Response::with_informational_handler(stream, HeaderLimits::default(),
+                                                           &mut handler).unwrap();
+        assert_eq!(res.status, status::StatusCode::Ok);
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+        assert_eq!(handler.0, vec![102, 103]);
+    }
+
+    #[test]
+    fn test_trailers() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            Trailer: Content-MD5\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            0\r\n\
+            Content-MD5: XUFAKrxLKna5cZ2REBfFkg==\r\n\
+            \r\n\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        assert!(res.trailers().is_none());
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+        let md5 = res.trailers().unwrap().get::<ContentMd5>().unwrap();
+        assert_eq!(md5.len(), 16);
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            6\r\n world\r\n\
+            0\r\n\r\n\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        let mut w = MemWriter::new();
+        assert_eq!(res.copy_to(&mut w).unwrap(), 11);
+        assert_eq!(w.get_ref(), b"hello world");
+    }
+
+    #[test]
+    fn test_expected_digest() {
+        let body = b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ";
+        let sha256 = vec![0x2c, 0xf2, 0x4d, 0xba, 0x5f, 0xb0, 0xa3, 0x0e, 0x26, 0xe8, 0x3b, 0x2a,
+                          0xc5, 0xb9, 0xe2, 0x9e, 0x1b, 0x16, 0x1e, 0x5c, 0x1f, 0xa7, 0x42, 0x5e,
+                          0x73, 0x04, 0x33, 0x62, 0x93, 0x8b, 0x98, 0x24];
+        let stream = box MockStream::with_input(body) as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.set_expected_digest(Some(BodyDigest::Sha256(sha256)));
+        assert_eq!(res.read_to_end().unwrap(), b"hello".to_vec());
+
+        let stream = box MockStream::with_input(body) as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.set_expected_digest(Some(BodyDigest::Md5(vec![0, ..16])));
+        assert_eq!(res.read_to_end().unwrap_err().desc, DIGEST_MISMATCH);
+
+        let stream = box MockStream::with_input(body) as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.set_expected_digest(Some(BodyDigest::Md5(vec![0, ..16])));
+        assert_eq!(res.read_string().unwrap_err().kind(), HttpErrorKind::Integrity);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Encoding: gzip\r\n\
+            Content-Length: 33\r\n\
+            \r\n\
+            \x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xf3\x48\xcd\xc9\xc9\xd7\x51\x08\
+            \xcf\x2f\xca\x49\x51\x04\x00\xd0\xc3\x4a\xec\x0d\x00\x00\x00\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.decompress().unwrap();
+        assert!(!res.headers.has::<ContentEncoding>());
+        assert!(!res.headers.has::<ContentLength>());
+        assert_eq!(res.read_to_string().unwrap()[], "Hello, World!");
+    }
+
+    #[test]
+    fn test_max_body_size_decompressed() {
+        let mut input = b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Encoding: gzip\r\n\
+            \r\n\
+        ".to_vec();
+        input.push_all(gzip(&[0u8, ..1000]).unwrap()[]);
+        let stream = box MockStream::with_input(input[]) as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.decompress().unwrap();
+        // the compressed body is well under the limit, but not once decoded
+        res.set_max_body_size(Some(100)).unwrap();
+        assert_eq!(res.read_to_end().err().unwrap().desc,
+                   "Response body is larger than the maximum size");
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 20\r\n\
+            \r\n\
+            twenty bytes of body\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        // the pacing itself is tested with RateLimit
+        res.set_rate_limit(Some(0));
+        assert_eq!(res.read_to_string().unwrap()[], "twenty bytes of body");
+    }
+
+    #[test]
+    fn test_decompress_identity() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            plain\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.decompress().unwrap();
+        assert_eq!(res.read_to_string().unwrap()[], "plain");
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Encoding: gzip\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            plain\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.decompress().unwrap();
+        assert!(res.read_to_end().is_err());
+    }
+
+    #[test]
+    fn test_max_body_size_declared() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        assert!(res.set_max_body_size(Some(4)).is_err());
+        assert!(res.set_max_body_size(Some(5)).is_ok());
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+    }
+
+    #[test]
+    fn test_max_body_size_chunked() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            3\r\nhel\r\n\
+            2\r\nlo\r\n\
+            0\r\n\r\n\
+        ") as Box<NetworkStream + Send>;
+        let mut res = Response::new(stream).unwrap();
+        res.set_max_body_size(Some(4)).unwrap();
+        assert_eq!(res.read_to_end().err().unwrap().desc,
+                   "Response body is larger than the maximum size");
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut res = with_body(None, b"hello");
+        res.set_deadline(Some(Duration::minutes(1)));
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+
+        let mut res = with_body(None, b"hello");
+        res.set_deadline(Some(Duration::zero()));
+        assert_eq!(res.read_to_end().err().unwrap().kind, TimedOut);
+    }
+
+    fn with_body(content_type: Option<&str>, body: &[u8]) -> Response {
+        let mut headers = Headers::new();
+        content_type.map(|mime| headers.set(ContentType(from_str(mime).unwrap())));
+        Response::from_parts(status::StatusCode::Ok, version::HttpVersion::Http11,
+                             headers, body.to_vec())
+    }
+
+    #[test]
+    fn test_tls_info_without_tls() {
+        let stream = box MockStream::with_input(b"HTTP/1.1 204 No Content\r\n\r\n")
+            as Box<NetworkStream + Send>;
+        let res = Response::new(stream).unwrap();
+        assert_eq!(res.tls_info(), None);
+    }
+
+    #[test]
+    fn test_read_string() {
+        let mut res = with_body(Some("text/plain; charset=utf-8"), "caf\u00e9".as_bytes());
+        assert_eq!(res.read_string().unwrap()[], "caf\u00e9");
+        let mut res = with_body(Some("text/plain; charset=ISO-8859-1"), b"caf\xe9");
+        assert_eq!(res.read_string().unwrap()[], "caf\u00e9");
+        let mut res = with_body(Some("text/plain; charset=utf-8"), b"caf\xe9");
+        assert!(res.read_string().is_err());
+    }
+
+    #[test]
+    fn test_read_string_without_charset() {
+        let mut res = with_body(None, "caf\u00e9".as_bytes());
+        assert_eq!(res.read_string().unwrap()[], "caf\u00e9");
+        let mut res = with_body(Some("text/plain"), b"caf\xe9");
+        assert_eq!(res.read_string().unwrap()[], "caf\u00e9");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_decode_json_requires_json() {
+        let mut res = with_body(Some("text/plain"), b"[1,2,3]");
+        assert!(res.decode_json::<Vec<uint>>().is_err());
+        let mut res = with_body(Some("application/vnd.api+json"), b"[1,2,3]");
+        assert_eq!(res.decode_json::<Vec<uint>>().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 200 OK\r\n\
+            Server: mock\r\n\
+            Content-Length: 0\r\n\
+            \r\n\
+        ") as Box<NetworkStream + Send>;
+        let limits = HeaderLimits { max_count: 1, ..Default::default() };
+        assert_eq!(Response::with_header_limits(stream, limits).err(), Some(HttpHeadersTooLargeError));
+    }
+
+    #[test]
+    fn test_skips_100_continue() {
+        let stream = box MockStream::with_input(b"\
+            HTTP/1.1 100 Continue\r\n\
+            \r\n\
+            HTTP/1.1 200 OK\r\n\
+            Content-Length: 0\r\n\
+            \r\n\
+        ") as Box<NetworkStream + Send>;
+        assert_eq!(Response::new(stream).unwrap().status, status::StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let mut res = Response::from_parts(status::StatusCode::Ok, version::HttpVersion::Http11,
+                                           Headers::new(), b"hello".to_vec());
+        assert_eq!(res.status_raw(), &RawStatus(200, Borrowed("OK")));
+        assert_eq!(res.read_to_string().unwrap()[], "hello");
+    }
 }
@@ -0,0 +1,179 @@
+//! Client Sessions
+use header::Headers;
+use header::common::Authorization;
+use header::common::authorization::Basic;
+use method::Method;
+use net::{HttpConnector, NetworkConnector, NetworkStream};
+
+use super::{Client, CookieStore, IntoUrl, Pool, RequestBuilder};
+
+/// A Client that carries state from one request to the next.
+///
+/// Cookies set by responses are sent with later requests, and the headers
+/// and credentials of a Session are sent with every request. Requests are
+/// built just as with a `Client`.
+pub struct Session<C> {
+    client: Client<C>,
+}
+
+impl Session<Pool<HttpConnector>> {
+    /// Create a new Session, around a new `Client`.
+    pub fn new() -> Session<Pool<HttpConnector>> {
+        Session::with_client(Client::new())
+    }
+}
+
+impl<C: NetworkConnector<S>, S: NetworkStream> Session<C> {
+    /// Create a new Session around `client`, keeping its settings.
+    ///
+    /// If the Client has no `CookieStore`, it is given an empty one.
+    pub fn with_client(mut client: Client<C>) -> Session<C> {
+        if client.cookie_store().is_none() {
+            client.set_cookie_store(Some(CookieStore::new()));
+        }
+        Session { client: client }
+    }
+
+    /// Get the Client of this Session.
+    pub fn client(&self) -> &Client<C> {
+        &self.client
+    }
+
+    /// Get a mutable reference to the Client of this Session, to change
+    /// its settings.
+    pub fn client_mut(&mut self) -> &mut Client<C> {
+        &mut self.client
+    }
+
+    /// Consume the Session, returning its Client.
+    pub fn into_client(self) -> Client<C> {
+        self.client
+    }
+
+    /// Get the cookies kept by this Session.
+    ///
+    /// This is `None` only if the `CookieStore` of the Client was removed.
+    pub fn cookies(&self) -> Option<&CookieStore> {
+        self.client.cookie_store()
+    }
+
+    /// Get a mutable reference to the cookies kept by this Session.
+    pub fn cookies_mut(&mut self) -> Option<&mut CookieStore> {
+        self.client.cookie_store_mut()
+    }
+
+    /// Get the headers sent with every request of this Session.
+    pub fn headers(&self) -> &Headers {
+        self.client.default_headers()
+    }
+
+    /// Get a mutable reference to the headers sent with every request of
+    /// this Session.
+    ///
+    /// Headers set on an individual request override these.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        self.client.default_headers_mut()
+    }
+
+    /// Set the credentials sent in an `Authorization` header with every
+    /// request of this Session.
+    ///
+    /// As with other headers, they are not sent to another host when
+    /// following a redirect, unless the Client is set to keep credentials.
+    /// Pass `None` to stop sending them, which is the default.
+    pub fn set_credentials(&mut self, credentials: Option<Basic>) {
+        let headers = self.client.default_headers_mut();
+        match credentials {
+            Some(credentials) => headers.set(Authorization(credentials)),
+            None => { headers.remove::<Authorization<Basic>>(); }
+        }
+    }
+
+    /// Execute a Get request.
+    pub fn get<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.get(url)
+    }
+
+    /// Execute a Head request.
+    pub fn head<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.head(url)
+    }
+
+    /// Execute a Post request.
+    pub fn post<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.post(url)
+    }
+
+    /// Execute a Put request.
+    pub fn put<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.put(url)
+    }
+
+    /// Execute a Delete request.
+    pub fn delete<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.delete(url)
+    }
+
+    /// Execute a Patch request.
+    pub fn patch<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.patch(url)
+    }
+
+    /// Execute an Options request.
+    pub fn options<U: IntoUrl>(&mut self, url: U) -> RequestBuilder<U, C, S> {
+        self.client.options(url)
+    }
+
+    /// Build a new request using this Session.
+    pub fn request<U: IntoUrl>(&mut self, method: Method, url: U) -> RequestBuilder<U, C, S> {
+        self.client.request(method, url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use client::{Client, ClientMiddleware, Request};
+    use header::common::{Authorization, Cookies, UserAgent};
+    use header::common::authorization::Basic;
+    use net::Fresh;
+    use super::Session;
+
+    mock_connector!(MockLogin {
+        "http://127.0.0.1" =>       "HTTP/1.1 200 OK\r\n\
+                                     Set-Cookie: session=abc\r\n\
+                                     Content-Length: 0\r\n\
+                                     \r\n\
+                                    "
+    })
+
+    struct MockHeaderRecorder(Arc<Mutex<Vec<(bool, bool, bool)>>>);
+
+    impl ClientMiddleware for MockHeaderRecorder {
+        fn before_request(&mut self, req: &mut Request<Fresh>) {
+            let headers = req.headers();
+            self.0.lock().push((headers.has::<Cookies>(), headers.has::<Authorization<Basic>>(),
+                                headers.has::<UserAgent>()));
+        }
+    }
+
+    #[test]
+    fn test_session_carries_state() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut client = Client::with_connector(MockLogin);
+        client.add_middleware(MockHeaderRecorder(log.clone()));
+        let mut session = Session::with_client(client);
+        session.headers_mut().set(UserAgent("session".into_string()));
+        session.set_credentials(Some(Basic {
+            username: "Aladdin".into_string(),
+            password: Some("open sesame".into_string())
+        }));
+
+        session.get("http://127.0.0.1").send().unwrap();
+        session.set_credentials(None);
+        session.post("http://127.0.0.1").send().unwrap();
+        assert_eq!(*log.lock(), vec![(false, true, true), (true, false, true)]);
+        assert!(session.cookies().is_some());
+    }
+}
@@ -0,0 +1,35 @@
+use header::{Header, HeaderFormat};
+use std::fmt;
+use header::common::transfer_encoding::Encoding;
+use super::util::{from_comma_delimited, fmt_comma_delimited};
+
+/// The `Accept-Encoding` header.
+///
+/// The `Accept-Encoding` header is used to tell a server which content-codings
+/// the client is able to decode in a response.
+///
+/// ```notrust
+/// Accept-Encoding: gzip, deflate
+/// ```
+#[deriving(Clone, PartialEq, Show)]
+pub struct AcceptEncoding(pub Vec<Encoding>);
+
+deref!(AcceptEncoding -> Vec<Encoding>)
+
+impl Header for AcceptEncoding {
+    fn header_name(_: Option<AcceptEncoding>) -> &'static str {
+        "Accept-Encoding"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<AcceptEncoding> {
+        from_comma_delimited(raw).map(AcceptEncoding)
+    }
+}
+
+impl HeaderFormat for AcceptEncoding {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt_comma_delimited(fmt, self[])
+    }
+}
+
+bench_header!(normal, AcceptEncoding, { vec![b"gzip, deflate".to_vec()] })
@@ -103,6 +103,35 @@ impl Scheme for Basic {
     }
 }
 
+/// Token holder for Bearer Authentication, as used by OAuth 2.0, of
+/// [RFC6750](http://tools.ietf.org/html/rfc6750#section-2.1).
+#[deriving(Clone, PartialEq, Show)]
+pub struct Bearer {
+    /// The access token.
+    pub token: String
+}
+
+impl Scheme for Bearer {
+    fn scheme(_: Option<Bearer>) -> Option<&'static str> {
+        Some("Bearer")
+    }
+
+    fn fmt_scheme(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.token.fmt(f)
+    }
+}
+
+impl FromStr for Bearer {
+    fn from_str(s: &str) -> Option<Bearer> {
+        let token = s.trim();
+        if token.is_empty() {
+            None
+        } else {
+            Some(Bearer { token: token.into_string() })
+        }
+    }
+}
+
 impl FromStr for Basic {
     fn from_str(s: &str) -> Option<Basic> {
         match s.from_base64() {
@@ -138,7 +167,7 @@ impl FromStr for Basic {
 #[cfg(test)]
 mod tests {
     use std::io::MemReader;
-    use super::{Authorization, Basic};
+    use super::{Authorization, Basic, Bearer};
     use super::super::super::{Headers};
 
     fn mem(s: &str) -> MemReader {
@@ -188,7 +217,22 @@ mod tests {
         assert_eq!(auth.0.password, Some("".to_string()));
     }
 
+    #[test]
+    fn test_bearer_auth() {
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer { token: "mF_9.B5f-4.1JqM".into_string() }));
+        assert_eq!(headers.to_string(), "Authorization: Bearer mF_9.B5f-4.1JqM\r\n".into_string());
+    }
+
+    #[test]
+    fn test_bearer_auth_parse() {
+        let headers = Headers::from_raw(&mut mem("Authorization: Bearer mF_9.B5f-4.1JqM\r\n\r\n")).unwrap();
+        let auth = headers.get::<Authorization<Bearer>>().unwrap();
+        assert_eq!(auth.0.token[], "mF_9.B5f-4.1JqM");
+    }
+
 }
 
 bench_header!(raw, Authorization<String>, { vec![b"foo bar baz".to_vec()] })
 bench_header!(basic, Authorization<Basic>, { vec![b"Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_vec()] })
+bench_header!(bearer, Authorization<Bearer>, { vec![b"Bearer mF_9.B5f-4.1JqM".to_vec()] })
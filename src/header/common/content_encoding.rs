@@ -0,0 +1,35 @@
+use header::{Header, HeaderFormat};
+use std::fmt;
+use header::common::transfer_encoding::Encoding;
+use super::util::{from_comma_delimited, fmt_comma_delimited};
+
+/// The `Content-Encoding` header.
+///
+/// This header describes the codings that have been applied to the
+/// representation, in the order they were applied.
+///
+/// ```notrust
+/// Content-Encoding: gzip
+/// ```
+#[deriving(Clone, PartialEq, Show)]
+pub struct ContentEncoding(pub Vec<Encoding>);
+
+deref!(ContentEncoding -> Vec<Encoding>)
+
+impl Header for ContentEncoding {
+    fn header_name(_: Option<ContentEncoding>) -> &'static str {
+        "Content-Encoding"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<ContentEncoding> {
+        from_comma_delimited(raw).map(ContentEncoding)
+    }
+}
+
+impl HeaderFormat for ContentEncoding {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt_comma_delimited(fmt, self[])
+    }
+}
+
+bench_header!(normal, ContentEncoding, { vec![b"gzip".to_vec()] })
@@ -0,0 +1,63 @@
+use std::fmt::{mod, Show};
+use serialize::base64::{ToBase64, FromBase64, Standard, Config};
+
+use header::{Header, HeaderFormat};
+use super::util::from_one_raw_str;
+
+/// The `Content-MD5` header, of
+/// [RFC1864](http://tools.ietf.org/html/rfc1864).
+///
+/// The MD5 digest of the body, which is base64 encoded in the header.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ContentMd5(pub Vec<u8>);
+
+deref!(ContentMd5 -> Vec<u8>)
+
+impl Header for ContentMd5 {
+    fn header_name(_: Option<ContentMd5>) -> &'static str {
+        "Content-MD5"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<ContentMd5> {
+        from_one_raw_str(raw).and_then(|s: String| {
+            match s[].trim().from_base64() {
+                Ok(digest) if digest.len() == 16 => Some(ContentMd5(digest)),
+                _ => None
+            }
+        })
+    }
+}
+
+impl HeaderFormat for ContentMd5 {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0[].to_base64(Config {
+            char_set: Standard,
+            pad: true,
+            line_length: None
+        }).fmt(fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{Header, HeaderFormatter};
+    use super::ContentMd5;
+
+    #[test]
+    fn test_parse() {
+        let md5: Option<ContentMd5> = Header::parse_header([b"XUFAKrxLKna5cZ2REBfFkg==".to_vec()][]);
+        assert_eq!(md5, Some(ContentMd5(vec![0x5d, 0x41, 0x40, 0x2a, 0xbc, 0x4b, 0x2a, 0x76,
+                                             0xb9, 0x71, 0x9d, 0x91, 0x10, 0x17, 0xc5, 0x92])));
+        let short: Option<ContentMd5> = Header::parse_header([b"aGVsbG8=".to_vec()][]);
+        assert_eq!(short, None);
+    }
+
+    #[test]
+    fn test_format() {
+        let md5 = ContentMd5(vec![0x5d, 0x41, 0x40, 0x2a, 0xbc, 0x4b, 0x2a, 0x76,
+                                  0xb9, 0x71, 0x9d, 0x91, 0x10, 0x17, 0xc5, 0x92]);
+        assert_eq!(format!("{}", HeaderFormatter(&md5))[], "XUFAKrxLKna5cZ2REBfFkg==");
+    }
+}
+
+bench_header!(bench, ContentMd5, { vec![b"XUFAKrxLKna5cZ2REBfFkg==".to_vec()] })
@@ -0,0 +1,107 @@
+use header::{Header, HeaderFormat};
+use std::fmt;
+use super::util::from_one_raw_str;
+
+/// The `Content-Range` header.
+///
+/// Says which bytes of a representation a partial response contains, as
+/// described in [RFC7233](http://tools.ietf.org/html/rfc7233#section-4.2).
+/// Only the `bytes` unit is supported.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ContentRange {
+    /// The first and last bytes sent, inclusive. This is `None` in a
+    /// `416 Requested Range Not Satisfiable` response, written `*`.
+    pub range: Option<(u64, u64)>,
+    /// The length of the whole representation, if known.
+    pub instance_length: Option<u64>,
+}
+
+impl Header for ContentRange {
+    fn header_name(_: Option<ContentRange>) -> &'static str {
+        "Content-Range"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<ContentRange> {
+        from_one_raw_str(raw).and_then(|s: String| {
+            let s = s[].trim();
+            if !s.starts_with("bytes ") {
+                return None;
+            }
+            let s = s["bytes ".len()..];
+            let slash = match s.find('/') {
+                Some(i) => i,
+                None => return None
+            };
+            let range = match s[..slash] {
+                "*" => None,
+                range => match range.find('-') {
+                    Some(i) => match (from_str(range[..i]), from_str(range[i + 1..])) {
+                        (Some(first), Some(last)) if first <= last => Some((first, last)),
+                        _ => return None
+                    },
+                    None => return None
+                }
+            };
+            let instance_length = match s[slash + 1..] {
+                "*" => None,
+                len => match from_str(len) {
+                    Some(len) => Some(len),
+                    None => return None
+                }
+            };
+            match (range, instance_length) {
+                (None, None) => None,
+                (Some((_, last)), Some(len)) if last >= len => None,
+                _ => Some(ContentRange { range: range, instance_length: instance_length })
+            }
+        })
+    }
+}
+
+impl HeaderFormat for ContentRange {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(fmt.write(b"bytes "));
+        match self.range {
+            Some((first, last)) => try!(write!(fmt, "{}-{}", first, last)),
+            None => try!(fmt.write(b"*"))
+        }
+        match self.instance_length {
+            Some(len) => write!(fmt, "/{}", len),
+            None => fmt.write(b"/*")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use header::HeaderFormatter;
+    use super::ContentRange;
+
+    #[test]
+    fn test_parse_content_range() {
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes 0-499/1234".to_vec()][]);
+        assert_eq!(cr, Some(ContentRange { range: Some((0, 499)), instance_length: Some(1234) }));
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes 0-499/*".to_vec()][]);
+        assert_eq!(cr, Some(ContentRange { range: Some((0, 499)), instance_length: None }));
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes */1234".to_vec()][]);
+        assert_eq!(cr, Some(ContentRange { range: None, instance_length: Some(1234) }));
+
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes */*".to_vec()][]);
+        assert_eq!(cr, None);
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes 0-1234/1234".to_vec()][]);
+        assert_eq!(cr, None);
+        let cr: Option<ContentRange> = Header::parse_header([b"bytes 0-499".to_vec()][]);
+        assert_eq!(cr, None);
+    }
+
+    #[test]
+    fn test_format_content_range() {
+        let cr = ContentRange { range: Some((500, 999)), instance_length: None };
+        assert_eq!(format!("{}", HeaderFormatter(&cr))[], "bytes 500-999/*");
+        let cr = ContentRange { range: None, instance_length: Some(1000) };
+        assert_eq!(format!("{}", HeaderFormatter(&cr))[], "bytes */1000");
+    }
+}
+
+bench_header!(bench, ContentRange, { vec![b"bytes 0-499/1234".to_vec()] })
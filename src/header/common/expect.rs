@@ -0,0 +1,58 @@
+use header::{Header, HeaderFormat};
+use std::ascii::AsciiExt;
+use std::fmt::{mod, Show};
+use std::str::from_utf8;
+
+use self::Expect::Continue;
+
+/// The `Expect` header.
+///
+/// > The "Expect" header field in a request indicates a certain set of
+/// > behaviors (expectations) that need to be supported by the server in
+/// > order to properly handle this request.  The only such expectation
+/// > defined by this specification is 100-continue.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Expect {
+    /// The value `100-continue`.
+    Continue
+}
+
+impl Header for Expect {
+    fn header_name(_: Option<Expect>) -> &'static str {
+        "Expect"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<Expect> {
+        if raw.len() != 1 {
+            return None;
+        }
+        match from_utf8(raw[0][]) {
+            Some(s) if s.trim().eq_ignore_ascii_case("100-continue") => Some(Continue),
+            _ => None
+        }
+    }
+}
+
+impl HeaderFormat for Expect {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Continue => "100-continue".fmt(fmt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use super::Expect;
+
+    #[test]
+    fn test_expect() {
+        assert_eq!(Header::parse_header([b"100-continue".to_vec()][]), Some(Expect::Continue));
+        assert_eq!(Header::parse_header([b"100-Continue".to_vec()][]), Some(Expect::Continue));
+        let other: Option<Expect> = Header::parse_header([b"200-ok".to_vec()][]);
+        assert_eq!(other, None);
+    }
+}
+
+bench_header!(bench, Expect, { vec![b"100-continue".to_vec()] })
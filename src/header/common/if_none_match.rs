@@ -0,0 +1,115 @@
+use header::{Header, HeaderFormat};
+use std::fmt::{mod, Show};
+use std::str::from_utf8;
+use super::Etag;
+
+/// The `If-None-Match` header field.
+///
+/// > The "If-None-Match" header field makes the request method conditional
+/// > on a recipient cache or origin server either not having any current
+/// > representation of the target resource, when the field-value is "*",
+/// > or having a selected representation with an entity-tag that does not
+/// > match any of those listed in the field-value.
+#[deriving(Clone, PartialEq, Show)]
+pub enum IfNoneMatch {
+    /// `*`, matching any current representation.
+    Any,
+    /// A list of entity-tags.
+    EntityTags(Vec<Etag>)
+}
+
+impl IfNoneMatch {
+    /// Whether this header matches `etag`, using the weak comparison
+    /// that If-None-Match calls for.
+    pub fn matches(&self, etag: &Etag) -> bool {
+        match *self {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::EntityTags(ref tags) => tags.iter().any(|tag| tag.tag == etag.tag)
+        }
+    }
+}
+
+impl Header for IfNoneMatch {
+    fn header_name(_: Option<IfNoneMatch>) -> &'static str {
+        "If-None-Match"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<IfNoneMatch> {
+        let mut tags = Vec::new();
+        for line in raw.iter() {
+            let line = match from_utf8(line[]) {
+                Some(line) => line,
+                None => return None
+            };
+            if line.trim() == "*" {
+                return Some(IfNoneMatch::Any);
+            }
+            for tag in line.split(',') {
+                let tag = tag.trim();
+                if tag.is_empty() {
+                    continue;
+                }
+                match Header::parse_header([tag.as_bytes().to_vec()][]) {
+                    Some(etag) => tags.push(etag),
+                    None => return None
+                }
+            }
+        }
+        if tags.is_empty() {
+            None
+        } else {
+            Some(IfNoneMatch::EntityTags(tags))
+        }
+    }
+}
+
+impl HeaderFormat for IfNoneMatch {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IfNoneMatch::Any => "*".fmt(fmt),
+            IfNoneMatch::EntityTags(ref tags) => {
+                for (i, tag) in tags.iter().enumerate() {
+                    if i != 0 {
+                        try!(", ".fmt(fmt));
+                    }
+                    try!(tag.fmt_header(fmt));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use header::common::Etag;
+    use super::IfNoneMatch;
+
+    #[test]
+    fn test_if_none_match() {
+        let mut inm: Option<IfNoneMatch>;
+
+        inm = Header::parse_header([b"*".to_vec()][]);
+        assert_eq!(inm, Some(IfNoneMatch::Any));
+
+        inm = Header::parse_header([b"\"foo\", W/\"bar\"".to_vec()][]);
+        assert_eq!(inm, Some(IfNoneMatch::EntityTags(vec![
+            Etag { weak: false, tag: "foo".into_string() },
+            Etag { weak: true, tag: "bar".into_string() }
+        ])));
+
+        inm = Header::parse_header([b"foo".to_vec()][]);
+        assert_eq!(inm, None);
+    }
+
+    #[test]
+    fn test_if_none_match_matches() {
+        let inm = IfNoneMatch::EntityTags(vec![Etag { weak: true, tag: "foo".into_string() }]);
+        assert!(inm.matches(&Etag { weak: false, tag: "foo".into_string() }));
+        assert!(!inm.matches(&Etag { weak: false, tag: "bar".into_string() }));
+        assert!(IfNoneMatch::Any.matches(&Etag { weak: false, tag: "bar".into_string() }));
+    }
+}
+
+bench_header!(bench, IfNoneMatch, { vec![b"\"foo\", W/\"bar\"".to_vec()] })
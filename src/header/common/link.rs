@@ -0,0 +1,175 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::str::from_utf8;
+use header::{Header, HeaderFormat};
+
+/// The `Link` header field, of
+/// [RFC5988](http://tools.ietf.org/html/rfc5988#section-5).
+///
+/// It links to other resources, such as those a `103 Early Hints` response
+/// suggests preloading.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Link(pub Vec<LinkValue>);
+
+deref!(Link -> Vec<LinkValue>)
+
+/// A single link of a `Link` header, such as
+/// `</style.css>; rel=preload; as=style`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct LinkValue {
+    /// The URI linked to, which may be relative.
+    pub uri: String,
+    /// The parameters of the link, with their names in lowercase. A
+    /// parameter without a value has an empty one.
+    pub params: Vec<(String, String)>,
+}
+
+impl LinkValue {
+    /// The value of the parameter named `name`, if the link has one.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|&&(ref param, _)| param[].eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value[])
+    }
+
+    /// The relation of the link, such as `preload`.
+    pub fn rel(&self) -> Option<&str> {
+        self.param("rel")
+    }
+}
+
+impl Header for Link {
+    fn header_name(_: Option<Link>) -> &'static str {
+        "Link"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<Link> {
+        let mut links = vec![];
+        for line in raw.iter() {
+            match from_utf8(line[]).and_then(parse_links) {
+                Some(parsed) => links.extend(parsed.into_iter()),
+                None => return None
+            }
+        }
+        if links.is_empty() {
+            None
+        } else {
+            Some(Link(links))
+        }
+    }
+}
+
+impl HeaderFormat for Link {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (i, link) in self.0.iter().enumerate() {
+            if i != 0 {
+                try!(write!(fmt, ", "));
+            }
+            try!(write!(fmt, "<{}>", link.uri));
+            for &(ref name, ref value) in link.params.iter() {
+                if value.is_empty() {
+                    try!(write!(fmt, "; {}", name));
+                } else if value[].contains_char(' ') || value[].contains_char(',') ||
+                        value[].contains_char(';') {
+                    try!(write!(fmt, "; {}=\"{}\"", name, value));
+                } else {
+                    try!(write!(fmt, "; {}={}", name, value));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_links(s: &str) -> Option<Vec<LinkValue>> {
+    let mut links = vec![];
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        if !rest.starts_with("<") {
+            return None;
+        }
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => return None
+        };
+        let uri = rest[1..end].into_string();
+        rest = rest[end + 1..].trim_left();
+        let mut params = vec![];
+        loop {
+            if rest.is_empty() {
+                break;
+            } else if rest.starts_with(",") {
+                rest = rest[1..].trim_left();
+                break;
+            } else if !rest.starts_with(";") {
+                return None;
+            }
+            rest = rest[1..].trim_left();
+            let name_end = rest.find(|c: char| c == '=' || c == ';' || c == ',').unwrap_or(rest.len());
+            let name = rest[..name_end].trim().to_ascii_lower();
+            rest = rest[name_end..];
+            let value = if rest.starts_with("=") {
+                rest = rest[1..].trim_left();
+                if rest.starts_with("\"") {
+                    let close = match rest[1..].find('"') {
+                        Some(close) => close + 1,
+                        None => return None
+                    };
+                    let value = rest[1..close].into_string();
+                    rest = rest[close + 1..].trim_left();
+                    value
+                } else {
+                    let end = rest.find(|c: char| c == ';' || c == ',').unwrap_or(rest.len());
+                    let value = rest[..end].trim().into_string();
+                    rest = rest[end..];
+                    value
+                }
+            } else {
+                String::new()
+            };
+            params.push((name, value));
+        }
+        links.push(LinkValue { uri: uri, params: params });
+    }
+    Some(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{Header, HeaderFormatter};
+    use super::{Link, LinkValue};
+
+    fn parse(s: &str) -> Option<Link> {
+        Header::parse_header([s.as_bytes().to_vec()][])
+    }
+
+    #[test]
+    fn test_parse() {
+        let link = parse("</style.css>; rel=preload; as=style, \
+                          <https://example.dom/a,b>; rel=\"next; page\"").unwrap();
+        assert_eq!(link.len(), 2);
+        assert_eq!(link[0].uri[], "/style.css");
+        assert_eq!(link[0].rel(), Some("preload"));
+        assert_eq!(link[0].param("AS"), Some("style"));
+        assert_eq!(link[1].uri[], "https://example.dom/a,b");
+        assert_eq!(link[1].rel(), Some("next; page"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse("/style.css; rel=preload"), None);
+        assert_eq!(parse("</style.css>; rel=\"preload"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let link = Link(vec![LinkValue {
+            uri: "/style.css".into_string(),
+            params: vec![("rel".into_string(), "preload".into_string()),
+                         ("crossorigin".into_string(), "".into_string())]
+        }]);
+        assert_eq!(format!("{}", HeaderFormatter(&link))[], "</style.css>; rel=preload; crossorigin");
+    }
+}
+
+bench_header!(bench, Link, { vec![b"</style.css>; rel=preload; as=style".to_vec()] })
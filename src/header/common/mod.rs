@@ -7,25 +7,35 @@
 //! is used, such as `ContentType(pub Mime)`.
 
 pub use self::accept::Accept;
+pub use self::accept_encoding::AcceptEncoding;
 pub use self::allow::Allow;
 pub use self::authorization::Authorization;
 pub use self::cache_control::CacheControl;
 pub use self::cookie::Cookies;
 pub use self::connection::Connection;
+pub use self::content_encoding::ContentEncoding;
 pub use self::content_length::ContentLength;
+pub use self::content_md5::ContentMd5;
+pub use self::content_range::ContentRange;
 pub use self::content_type::ContentType;
 pub use self::date::Date;
 pub use self::etag::Etag;
+pub use self::expect::Expect;
 pub use self::expires::Expires;
 pub use self::host::Host;
 pub use self::last_modified::LastModified;
+pub use self::link::Link;
 pub use self::if_modified_since::IfModifiedSince;
+pub use self::if_none_match::IfNoneMatch;
 pub use self::location::Location;
+pub use self::proxy_authorization::ProxyAuthorization;
+pub use self::range::Range;
 pub use self::transfer_encoding::TransferEncoding;
 pub use self::upgrade::Upgrade;
 pub use self::user_agent::UserAgent;
 pub use self::server::Server;
 pub use self::set_cookie::SetCookie;
+pub use self::strict_transport_security::StrictTransportSecurity;
 
 macro_rules! bench_header(
     ($name:ident, $ty:ty, $value:expr) => {
@@ -75,6 +85,9 @@ macro_rules! deref(
 /// Exposes the Accept header.
 pub mod accept;
 
+/// Exposes the AcceptEncoding header.
+pub mod accept_encoding;
+
 /// Exposes the Allow header.
 pub mod allow;
 
@@ -90,9 +103,18 @@ pub mod cookie;
 /// Exposes the Connection header.
 pub mod connection;
 
+/// Exposes the ContentEncoding header.
+pub mod content_encoding;
+
 /// Exposes the ContentLength header.
 pub mod content_length;
 
+/// Exposes the Content-MD5 header.
+pub mod content_md5;
+
+/// Exposes the ContentRange header.
+pub mod content_range;
+
 /// Exposes the ContentType header.
 pub mod content_type;
 
@@ -102,6 +124,9 @@ pub mod date;
 /// Exposes the Etag header.
 pub mod etag;
 
+/// Exposes the Expect header.
+pub mod expect;
+
 /// Exposes the Expires header.
 pub mod expires;
 
@@ -111,18 +136,33 @@ pub mod host;
 /// Exposes the LastModified header.
 pub mod last_modified;
 
+/// Exposes the Link header.
+pub mod link;
+
 /// Exposes the If-Modified-Since header.
 pub mod if_modified_since;
 
+/// Exposes the If-None-Match header.
+pub mod if_none_match;
+
 /// Exposes the Location header.
 pub mod location;
 
+/// Exposes the Proxy-Authorization header.
+pub mod proxy_authorization;
+
+/// Exposes the Range header.
+pub mod range;
+
 /// Exposes the Server header.
 pub mod server;
 
 /// Exposes the Set-Cookie header.
 pub mod set_cookie;
 
+/// Exposes the Strict-Transport-Security header.
+pub mod strict_transport_security;
+
 /// Exposes the TransferEncoding header.
 pub mod transfer_encoding;
 
@@ -0,0 +1,108 @@
+use header::{Header, HeaderFormat};
+use std::fmt::{mod, Show};
+use std::str::FromStr;
+use super::util::{from_one_raw_str, fmt_comma_delimited};
+
+use self::ByteRange::{FromTo, AllFrom, Last};
+
+/// The `Range` header.
+///
+/// Asks for only some of the bytes of a representation, as described in
+/// [RFC7233](http://tools.ietf.org/html/rfc7233#section-3.1). Only the
+/// `bytes` unit is supported.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Range(pub Vec<ByteRange>);
+
+deref!(Range -> Vec<ByteRange>)
+
+/// A range of bytes asked for in a `Range` header.
+#[deriving(Clone, PartialEq)]
+pub enum ByteRange {
+    /// The bytes from the first offset to the last, inclusive: `first-last`.
+    FromTo(u64, u64),
+    /// The bytes from an offset to the end: `first-`.
+    AllFrom(u64),
+    /// The last bytes, this many of them: `-length`.
+    Last(u64),
+}
+
+impl FromStr for ByteRange {
+    fn from_str(s: &str) -> Option<ByteRange> {
+        let s = s.trim();
+        match s.find('-') {
+            Some(0) => from_str(s[1..]).map(Last),
+            Some(i) if i == s.len() - 1 => from_str(s[..i]).map(AllFrom),
+            Some(i) => match (from_str(s[..i]), from_str(s[i + 1..])) {
+                (Some(first), Some(last)) if first <= last => Some(FromTo(first, last)),
+                _ => None
+            },
+            None => None
+        }
+    }
+}
+
+impl Show for ByteRange {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FromTo(first, last) => write!(fmt, "{}-{}", first, last),
+            AllFrom(first) => write!(fmt, "{}-", first),
+            Last(len) => write!(fmt, "-{}", len),
+        }
+    }
+}
+
+impl Header for Range {
+    fn header_name(_: Option<Range>) -> &'static str {
+        "Range"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<Range> {
+        from_one_raw_str(raw).and_then(|s: String| {
+            let s = s[].trim();
+            if !s.starts_with("bytes=") {
+                return None;
+            }
+            let ranges: Option<Vec<ByteRange>> = s["bytes=".len()..].split(',').map(from_str).collect();
+            match ranges {
+                Some(ranges) => if ranges.is_empty() { None } else { Some(Range(ranges)) },
+                None => None
+            }
+        })
+    }
+}
+
+impl HeaderFormat for Range {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(fmt.write(b"bytes="));
+        fmt_comma_delimited(fmt, self[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::Header;
+    use header::HeaderFormatter;
+    use super::Range;
+    use super::ByteRange::{FromTo, AllFrom, Last};
+
+    #[test]
+    fn test_parse_range() {
+        let range: Option<Range> = Header::parse_header([b"bytes=0-499, 500-, -20".to_vec()][]);
+        assert_eq!(range, Some(Range(vec![FromTo(0, 499), AllFrom(500), Last(20)])));
+
+        let range: Option<Range> = Header::parse_header([b"bytes=500-499".to_vec()][]);
+        assert_eq!(range, None);
+        let range: Option<Range> = Header::parse_header([b"items=0-9".to_vec()][]);
+        assert_eq!(range, None);
+        let range: Option<Range> = Header::parse_header([b"bytes=".to_vec()][]);
+        assert_eq!(range, None);
+    }
+
+    #[test]
+    fn test_format_range() {
+        let range = Range(vec![FromTo(0, 499), AllFrom(500)]);
+        assert_eq!(format!("{}", HeaderFormatter(&range))[], "bytes=0-499, 500-");
+    }
+}
+
+bench_header!(bench, Range, { vec![b"bytes=0-499, 500-".to_vec()] })
@@ -0,0 +1,102 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::str::FromStr;
+use header::{Header, HeaderFormat};
+use super::util::from_one_raw_str;
+
+/// The `Strict-Transport-Security` header field, of
+/// [RFC6797](http://tools.ietf.org/html/rfc6797#section-6.1).
+///
+/// It tells a client to only connect to the host over TLS, for the next
+/// `max_age` seconds.
+#[deriving(Clone, PartialEq, Show)]
+pub struct StrictTransportSecurity {
+    /// How many seconds the policy lasts for. Zero removes it.
+    pub max_age: u64,
+    /// Whether the policy applies to subdomains of the host too.
+    pub include_subdomains: bool,
+}
+
+impl Header for StrictTransportSecurity {
+    fn header_name(_: Option<StrictTransportSecurity>) -> &'static str {
+        "Strict-Transport-Security"
+    }
+
+    fn parse_header(raw: &[Vec<u8>]) -> Option<StrictTransportSecurity> {
+        from_one_raw_str(raw)
+    }
+}
+
+impl HeaderFormat for StrictTransportSecurity {
+    fn fmt_header(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(fmt, "max-age={}", self.max_age));
+        if self.include_subdomains {
+            try!(write!(fmt, "; includeSubDomains"));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StrictTransportSecurity {
+    fn from_str(s: &str) -> Option<StrictTransportSecurity> {
+        let mut max_age = None;
+        let mut include_subdomains = false;
+        for directive in s.split(';').map(|directive| directive.trim()) {
+            let (name, value) = match directive.find('=') {
+                Some(i) => (directive[..i].trim(), Some(directive[i + 1..].trim().trim_chars('"'))),
+                None => (directive, None)
+            };
+            if name.eq_ignore_ascii_case("max-age") {
+                // max-age is required, and may only be given once
+                if max_age.is_some() {
+                    return None;
+                }
+                max_age = value.and_then(|value| from_str::<u64>(value));
+                if max_age.is_none() {
+                    return None;
+                }
+            } else if name.eq_ignore_ascii_case("includeSubDomains") {
+                include_subdomains = true;
+            }
+        }
+        max_age.map(|max_age| StrictTransportSecurity {
+            max_age: max_age,
+            include_subdomains: include_subdomains,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use header::{Header, HeaderFormatter};
+    use super::StrictTransportSecurity;
+
+    fn parse(s: &str) -> Option<StrictTransportSecurity> {
+        Header::parse_header([s.as_bytes().to_vec()][])
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("max-age=31536000"),
+                   Some(StrictTransportSecurity { max_age: 31536000, include_subdomains: false }));
+        assert_eq!(parse("max-age=\"600\"; includeSubDomains"),
+                   Some(StrictTransportSecurity { max_age: 600, include_subdomains: true }));
+        assert_eq!(parse("includesubdomains; MAX-AGE=0"),
+                   Some(StrictTransportSecurity { max_age: 0, include_subdomains: true }));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse("includeSubDomains"), None);
+        assert_eq!(parse("max-age=soon"), None);
+        assert_eq!(parse("max-age=1; max-age=2"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let sts = StrictTransportSecurity { max_age: 600, include_subdomains: true };
+        assert_eq!(format!("{}", HeaderFormatter(&sts))[], "max-age=600; includeSubDomains");
+    }
+}
+
+bench_header!(bench, StrictTransportSecurity, { vec![b"max-age=31536000; includeSubDomains".to_vec()] })
@@ -7,19 +7,22 @@
 use std::any::Any;
 use std::ascii::{AsciiExt, AsciiCast};
 use std::borrow::Cow::{Borrowed, Owned};
+use std::cmp;
 use std::fmt::{mod, Show};
 use std::intrinsics::TypeId;
 use std::raw::TraitObject;
 use std::str::SendStr;
 use std::collections::HashMap;
 use std::collections::hash_map::{Entries, Occupied, Vacant};
+use std::default::Default;
 use std::{hash, mem};
 
 use mucell::MuCell;
 use uany::{UncheckedAnyDowncast, UncheckedAnyMutDowncast};
 
-use http::{mod, LineEnding};
+use http::{mod, HeaderLimits, LineEnding};
 use {HttpResult};
+use HttpError::HttpHeadersTooLargeError;
 
 pub use self::common::*;
 
@@ -123,10 +126,26 @@ impl Headers {
 
     #[doc(hidden)]
     pub fn from_raw<R: Reader>(rdr: &mut R) -> HttpResult<Headers> {
+        Headers::from_raw_with_limits(rdr, &Default::default())
+    }
+
+    #[doc(hidden)]
+    pub fn from_raw_with_limits<R: Reader>(rdr: &mut R, limits: &HeaderLimits) -> HttpResult<Headers> {
         let mut headers = Headers::new();
+        let mut remaining = limits.max_size;
+        let mut count = 0u;
         loop {
-            match try!(http::read_header(rdr)) {
+            let mut line = cmp::min(remaining, limits.max_line_size);
+            let allowed = line;
+            let header = try!(http::read_header_within(rdr, &mut line));
+            remaining -= allowed - line;
+            match header {
                 Some((name, value)) => {
+                    count += 1;
+                    if count > limits.max_count {
+                        debug!("more than {} headers", limits.max_count);
+                        return Err(HttpHeadersTooLargeError);
+                    }
                     debug!("raw header: {}={}", name, value[].to_ascii());
                     let name = CaseInsensitive(Owned(name));
                     let mut item = match headers.data.entry(name) {
@@ -511,6 +530,8 @@ mod tests {
     use super::CaseInsensitive;
     use super::{Headers, Header, HeaderFormat};
     use super::common::{ContentLength, ContentType, Accept, Host};
+    use http::HeaderLimits;
+    use HttpError::HttpHeadersTooLargeError;
 
     use test::Bencher;
 
@@ -527,6 +548,22 @@ mod tests {
         assert_eq!(hash(&a), hash(&b));
     }
 
+    #[test]
+    fn test_from_raw_with_limits() {
+        let raw = "Content-Length: 10\r\nContent-Type: text/plain\r\n\r\n";
+        let limits = HeaderLimits { max_size: 100, max_count: 1, max_line_size: 100 };
+        assert_eq!(Headers::from_raw_with_limits(&mut mem(raw), &limits).err(),
+                   Some(HttpHeadersTooLargeError));
+        let limits = HeaderLimits { max_size: 30, max_count: 10, max_line_size: 100 };
+        assert_eq!(Headers::from_raw_with_limits(&mut mem(raw), &limits).err(),
+                   Some(HttpHeadersTooLargeError));
+        let limits = HeaderLimits { max_size: 100, max_count: 10, max_line_size: 20 };
+        assert_eq!(Headers::from_raw_with_limits(&mut mem(raw), &limits).err(),
+                   Some(HttpHeadersTooLargeError));
+        let limits = HeaderLimits { max_size: 100, max_count: 2, max_line_size: 30 };
+        assert!(Headers::from_raw_with_limits(&mut mem(raw), &limits).is_ok());
+    }
+
     #[test]
     fn test_from_raw() {
         let headers = Headers::from_raw(&mut mem("Content-Length: 10\r\n\r\n")).unwrap();
@@ -1,9 +1,12 @@
 //! Pieces pertaining to the HTTP message protocol.
 use std::borrow::Cow::{Borrowed, Owned};
 use std::cmp::min;
+use std::default::Default;
+use std::error::FromError;
 use std::fmt;
 use std::io::{mod, Reader, IoResult, BufWriter};
 use std::num::from_u16;
+use std::uint;
 use std::str::{mod, SendStr};
 
 use url::Url;
@@ -14,8 +17,8 @@ use uri;
 use uri::RequestUri::{AbsolutePath, AbsoluteUri, Authority, Star};
 use version::HttpVersion;
 use version::HttpVersion::{Http09, Http10, Http11, Http20};
-use HttpError::{HttpHeaderError, HttpIoError, HttpMethodError, HttpStatusError,
-                HttpUriError, HttpVersionError};
+use HttpError::{HttpHeaderError, HttpHeadersTooLargeError, HttpMethodError, HttpStatusError,
+                HttpUriError, HttpUriTooLongError, HttpVersionError};
 use HttpResult;
 
 use self::HttpReader::{SizedReader, ChunkedReader, EofReader, EmptyReader};
@@ -62,6 +65,20 @@ impl<R: Reader> HttpReader<R> {
             EmptyReader(r) => r,
         }
     }
+
+    /// Access the inner Reader mutably.
+    ///
+    /// Warning: You should not read from this directly, as you can corrupt
+    /// the state.
+    #[inline]
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut R {
+        match *self {
+            SizedReader(ref mut r, _) => r,
+            ChunkedReader(ref mut r, _) => r,
+            EofReader(ref mut r) => r,
+            EmptyReader(ref mut r) => r,
+        }
+    }
 }
 
 impl<R: Reader> Reader for HttpReader<R> {
@@ -72,12 +89,10 @@ impl<R: Reader> Reader for HttpReader<R> {
                 if *remaining == 0 {
                     Err(io::standard_error(io::EndOfFile))
                 } else {
-                    let num = try!(body.read(buf));
-                    if num > *remaining {
-                        *remaining = 0;
-                    } else {
-                        *remaining -= num;
-                    }
+                    // never read past the body, into whatever follows it
+                    let to_read = min(*remaining, buf.len());
+                    let num = try!(body.read(buf.slice_to_mut(to_read)));
+                    *remaining -= num;
                     Ok(num)
                 }
             },
@@ -400,8 +415,22 @@ pub fn read_method<R: Reader>(stream: &mut R) -> HttpResult<method::Method> {
 
 /// Read a `RequestUri` from a raw stream.
 pub fn read_uri<R: Reader>(stream: &mut R) -> HttpResult<uri::RequestUri> {
+    read_uri_within(stream, uint::MAX)
+}
+
+/// Read a `RequestUri` of at most `max_size` bytes from a raw stream.
+///
+/// A longer one is an `HttpUriTooLongError`, as is one preceded by so many
+/// spaces that they and it are longer together.
+pub fn read_uri_within<R: Reader>(stream: &mut R, max_size: uint) -> HttpResult<uri::RequestUri> {
     let mut b = try!(stream.read_byte());
+    let mut spaces = 0u;
     while b == SP {
+        spaces += 1;
+        if spaces >= max_size {
+            debug!("more than {} spaces before uri", max_size);
+            return Err(HttpUriTooLongError)
+        }
         b = try!(stream.read_byte());
     }
 
@@ -419,6 +448,10 @@ pub fn read_uri<R: Reader>(stream: &mut R) -> HttpResult<uri::RequestUri> {
                 CR | LF => {
                     return Err(HttpUriError)
                 },
+                _ if spaces + s.len() >= max_size => {
+                    debug!("uri longer than {} bytes", max_size);
+                    return Err(HttpUriTooLongError)
+                },
                 b => s.push(b as char)
             }
         }
@@ -513,13 +546,24 @@ pub type RawHeaderLine = (String, Vec<u8>);
 /// >                ; see Section 3.2.4
 /// > ```
 pub fn read_header<R: Reader>(stream: &mut R) -> HttpResult<Option<RawHeaderLine>> {
+    let mut unlimited = uint::MAX;
+    read_header_within(stream, &mut unlimited)
+}
+
+/// Read a RawHeaderLine from a Reader, of at most `remaining` bytes.
+///
+/// The bytes read are subtracted from `remaining`, so that it can limit the
+/// size of all the header lines of a message. A longer line is an
+/// `HttpHeadersTooLargeError`.
+pub fn read_header_within<R: Reader>(stream: &mut R, remaining: &mut uint)
+        -> HttpResult<Option<RawHeaderLine>> {
     let mut name = String::new();
     let mut value = vec![];
 
     loop {
-        match try!(stream.read_byte()) {
+        match try!(read_byte_within(stream, remaining)) {
             CR if name.len() == 0 => {
-                match try!(stream.read_byte()) {
+                match try!(read_byte_within(stream, remaining)) {
                     LF => return Ok(None),
                     _ => return Err(HttpHeaderError)
                 }
@@ -536,7 +580,7 @@ pub fn read_header<R: Reader>(stream: &mut R) -> HttpResult<Option<RawHeaderLine
 
     todo!("handle obs-folding (gross!)");
     loop {
-        match try!(stream.read_byte()) {
+        match try!(read_byte_within(stream, remaining)) {
             CR => break,
             LF => return Err(HttpHeaderError),
             b' ' if ows => {},
@@ -549,22 +593,68 @@ pub fn read_header<R: Reader>(stream: &mut R) -> HttpResult<Option<RawHeaderLine
 
     debug!("header value = {}", value[].to_ascii());
 
-    match try!(stream.read_byte()) {
+    match try!(read_byte_within(stream, remaining)) {
         LF => Ok(Some((name, value))),
         _ => Err(HttpHeaderError)
     }
 
 }
 
+fn read_byte_within<R: Reader>(stream: &mut R, remaining: &mut uint) -> HttpResult<u8> {
+    if *remaining == 0 {
+        return Err(HttpHeadersTooLargeError);
+    }
+    *remaining -= 1;
+    Ok(try!(stream.read_byte()))
+}
+
+/// Limits on the headers read from a message, so that a peer cannot send
+/// endless headers.
+///
+/// More limits may be added, so set only the ones to change, and take the
+/// rest from `Default`, as in
+/// `HeaderLimits { max_count: 50, ..Default::default() }`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct HeaderLimits {
+    /// The most bytes all header lines of a message may have together.
+    pub max_size: uint,
+    /// The most header lines a message may have.
+    pub max_count: uint,
+    /// The most bytes a single header line may have.
+    pub max_line_size: uint,
+}
+
+impl Default for HeaderLimits {
+    /// Allows 64KB of headers, in at most 100 lines of at most 8KB.
+    fn default() -> HeaderLimits {
+        HeaderLimits {
+            max_size: 64 * 1024,
+            max_count: 100,
+            max_line_size: 8 * 1024,
+        }
+    }
+}
+
 /// `request-line   = method SP request-target SP HTTP-version CRLF`
 pub type RequestLine = (method::Method, uri::RequestUri, HttpVersion);
 
 /// Read the `RequestLine`, such as `GET / HTTP/1.1`.
 pub fn read_request_line<R: Reader>(stream: &mut R) -> HttpResult<RequestLine> {
+    read_request_line_within(stream, uint::MAX)
+}
+
+/// Read the `RequestLine`, with a request-target of at most `max_uri_size`
+/// bytes.
+///
+/// The whole line is then limited too, as methods are read to at most 16
+/// bytes, and the version is always 8; a longer request-target, or spaces
+/// before it, are an `HttpUriTooLongError`.
+pub fn read_request_line_within<R: Reader>(stream: &mut R, max_uri_size: uint)
+        -> HttpResult<RequestLine> {
     debug!("read request line");
     let method = try!(read_method(stream));
     debug!("method = {}", method);
-    let uri = try!(read_uri(stream));
+    let uri = try!(read_uri_within(stream, max_uri_size));
     debug!("uri = {}", uri);
     let version = try!(read_http_version(stream));
     debug!("version = {}", version);
@@ -594,6 +684,13 @@ impl Clone for RawStatus {
     }
 }
 
+/// Whether a status is that of an interim response, which is followed by
+/// another response to the same request: any `1xx` but `101 Switching
+/// Protocols`, which ends the response to an upgrade.
+pub fn is_interim(status: &RawStatus) -> bool {
+    status.0 >= 100 && status.0 < 200 && status.0 != 101
+}
+
 /// Read the StatusLine, such as `HTTP/1.1 200 OK`.
 ///
 /// > The first line of a response message is the status-line, consisting
@@ -690,7 +787,7 @@ fn expect(r: IoResult<u8>, expected: u8) -> HttpResult<()> {
     match r {
         Ok(b) if b == expected => Ok(()),
         Ok(_) => Err(HttpVersionError),
-        Err(e) => Err(HttpIoError(e))
+        Err(e) => Err(FromError::from_error(e))
     }
 }
 
@@ -704,11 +801,11 @@ mod tests {
     use method;
     use version::HttpVersion;
     use version::HttpVersion::{Http10, Http11, Http20};
-    use HttpError::{HttpVersionError, HttpMethodError};
+    use HttpError::{HttpVersionError, HttpMethodError, HttpHeadersTooLargeError, HttpUriTooLongError};
     use HttpResult;
     use url::Url;
 
-    use super::{read_method, read_uri, read_http_version, read_header,
+    use super::{read_method, read_uri, read_uri_within, read_http_version, read_header, read_header_within,
                 RawHeaderLine, read_status, RawStatus};
 
     fn mem(s: &str) -> MemReader {
@@ -746,6 +843,15 @@ mod tests {
         read("/ ", Ok(AbsolutePath("/".to_string())));
     }
 
+    #[test]
+    fn test_read_uri_within() {
+        assert_eq!(read_uri_within(&mut mem("/abc "), 4), Ok(AbsolutePath("/abc".to_string())));
+        assert_eq!(read_uri_within(&mut mem("/abcd "), 4), Err(HttpUriTooLongError));
+        assert_eq!(read_uri_within(&mut mem("  /a "), 4), Ok(AbsolutePath("/a".to_string())));
+        assert_eq!(read_uri_within(&mut mem("    /a "), 4), Err(HttpUriTooLongError));
+        assert_eq!(read_uri_within(&mut mem("        "), 4), Err(HttpUriTooLongError));
+    }
+
     #[test]
     fn test_read_http_version() {
         fn read(s: &str, result: HttpResult<HttpVersion>) {
@@ -795,6 +901,15 @@ mod tests {
                                                 "rust-lang.org".as_bytes().to_vec()))));
     }
 
+    #[test]
+    fn test_read_header_within() {
+        let mut remaining = 25;
+        assert!(read_header_within(&mut mem("Host: rust-lang.org\r\n"), &mut remaining).is_ok());
+        assert_eq!(remaining, 4);
+        assert_eq!(read_header_within(&mut mem("Host: rust-lang.org\r\n"), &mut remaining),
+                   Err(HttpHeadersTooLargeError));
+    }
+
     #[test]
     fn test_write_chunked() {
         use std::str::from_utf8;
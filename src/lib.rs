@@ -1,4 +1,4 @@
-#![feature(macro_rules, phase, default_type_params, slicing_syntax, globs)]
+#![feature(macro_rules, phase, default_type_params, slicing_syntax, globs, unsafe_destructor)]
 #![deny(missing_docs)]
 #![deny(warnings)]
 #![experimental]
@@ -124,6 +124,12 @@
 //! a `NetworkStream` and contain headers, a status, and an http version. They
 //! implement `Reader` and can be read to get the data out of a `Response`.
 //!
+//! #### Client
+//!
+//! A `Client` builds on top of `Request` and `Response`, adding a redirect
+//! policy and a pool of keep-alive connections that are reused between
+//! requests to the same host.
+//!
 
 extern crate serialize;
 extern crate time;
@@ -141,15 +147,23 @@ pub use url::Url;
 pub use method::Method::{Get, Head, Post, Delete};
 pub use status::StatusCode::{Ok, BadRequest, NotFound};
 pub use server::Server;
+pub use client::Client;
 
 use std::fmt;
 use std::error::{Error, FromError};
-use std::io::IoError;
+use std::io::{IoError, ConnectionRefused, InvalidInput, TimedOut};
+use std::time::Duration;
 
 use std::rt::backtrace;
 
+use time::precise_time_ns;
+
 use self::HttpError::{HttpMethodError, HttpUriError, HttpVersionError,
-                      HttpHeaderError, HttpStatusError, HttpIoError};
+                      HttpHeaderError, HttpStatusError, HttpIoError,
+                      HttpTooManyRedirectsError, HttpTimeoutError,
+                      HttpHeadersTooLargeError, HttpCancelledError,
+                      HttpCertificatePinError, HttpIntegrityError, HttpUriTooLongError,
+                      HttpResolveError, HttpTlsError};
 
 macro_rules! todo(
     ($($arg:tt)*) => (if cfg!(not(ndebug)) {
@@ -181,6 +195,10 @@ macro_rules! inspect(
     })
 )
 
+#[cfg(test)]
+#[macro_escape]
+mod mock;
+
 pub mod client;
 pub mod method;
 pub mod header;
@@ -191,8 +209,6 @@ pub mod status;
 pub mod uri;
 pub mod version;
 
-#[cfg(test)] mod mock;
-
 mod mimewrapper {
     /// Re-exporting the mime crate, for convenience.
     extern crate mime;
@@ -217,6 +233,90 @@ pub enum HttpError {
     HttpStatusError,
     /// An `IoError` that occured while trying to read or write to a network stream.
     HttpIoError(IoError),
+    /// A `Client` followed more redirects than its `RedirectPolicy` allows.
+    HttpTooManyRedirectsError,
+    /// A network operation did not complete within its configured timeout.
+    HttpTimeoutError(IoError),
+    /// The headers of a message were larger, or more numerous, than its
+    /// `HeaderLimits` allow.
+    HttpHeadersTooLargeError,
+    /// The request-target of a request was longer than allowed.
+    HttpUriTooLongError,
+    /// A request was cancelled through its `CancelHandle`.
+    HttpCancelledError,
+    /// A server presented a key that its connector has not pinned.
+    HttpCertificatePinError(IoError),
+    /// A response body did not match its expected digest.
+    HttpIntegrityError(IoError),
+    /// The host of a request could not be resolved to any address.
+    HttpResolveError(IoError),
+    /// The TLS handshake failed, or the server presented a certificate that
+    /// is not trusted.
+    HttpTlsError(IoError),
+}
+
+/// What kind of failure an `HttpError` is, to decide whether to retry a
+/// request, or how to report its failure.
+#[deriving(Show, PartialEq, Clone)]
+pub enum HttpErrorKind {
+    /// The host could not be resolved to any address.
+    Resolve,
+    /// The server refused the connection.
+    ConnectionRefused,
+    /// The TLS handshake failed, or the server presented a certificate or
+    /// key that is not trusted.
+    Tls,
+    /// A network operation, or the whole request, did not complete in time.
+    Timeout,
+    /// More redirects were followed than the `RedirectPolicy` allows.
+    TooManyRedirects,
+    /// A url was invalid.
+    Url,
+    /// The other side sent something that is not valid HTTP, or more than
+    /// the `HeaderLimits` allow.
+    Protocol,
+    /// A response body did not match its expected digest.
+    Integrity,
+    /// A request was cancelled through its `CancelHandle`.
+    Cancelled,
+    /// Any other failure of the connection, such as it being reset.
+    Io,
+}
+
+impl HttpError {
+    /// What kind of failure this is.
+    ///
+    /// The `IoError` underneath a failure of the connection, if any, is
+    /// returned by `cause()`.
+    pub fn kind(&self) -> HttpErrorKind {
+        match *self {
+            HttpMethodError | HttpVersionError | HttpHeaderError | HttpStatusError |
+            HttpHeadersTooLargeError | HttpUriTooLongError => HttpErrorKind::Protocol,
+            HttpUriError => HttpErrorKind::Url,
+            HttpResolveError(_) => HttpErrorKind::Resolve,
+            HttpTlsError(_) | HttpCertificatePinError(_) => HttpErrorKind::Tls,
+            HttpIoError(IoError { kind: ConnectionRefused, .. }) => HttpErrorKind::ConnectionRefused,
+            HttpIoError(_) => HttpErrorKind::Io,
+            HttpTooManyRedirectsError => HttpErrorKind::TooManyRedirects,
+            HttpTimeoutError(_) => HttpErrorKind::Timeout,
+            HttpCancelledError => HttpErrorKind::Cancelled,
+            HttpIntegrityError(_) => HttpErrorKind::Integrity,
+        }
+    }
+
+    /// This error as an `IoError`, such as for a connector to return from
+    /// `connect`.
+    fn into_io_error(self) -> IoError {
+        match self {
+            HttpIoError(e) | HttpTimeoutError(e) | HttpCertificatePinError(e) |
+                HttpIntegrityError(e) | HttpResolveError(e) | HttpTlsError(e) => e,
+            other => IoError {
+                kind: InvalidInput,
+                desc: "Invalid HTTP",
+                detail: Some(other.to_string())
+            }
+        }
+    }
 }
 
 impl Error for HttpError {
@@ -228,12 +328,23 @@ impl Error for HttpError {
             HttpHeaderError => "Invalid Header provided",
             HttpStatusError => "Invalid Status provided",
             HttpIoError(_) => "An IoError occurred while connecting to the specified network",
+            HttpTooManyRedirectsError => "Too many redirects were followed",
+            HttpTimeoutError(_) => "A network operation timed out",
+            HttpHeadersTooLargeError => "Headers were larger than allowed",
+            HttpUriTooLongError => "Request URI was longer than allowed",
+            HttpCancelledError => "The request was cancelled",
+            HttpCertificatePinError(_) => "Server presented a key that is not pinned",
+            HttpIntegrityError(_) => "Response body does not match its digest",
+            HttpResolveError(_) => "The host could not be resolved to any address",
+            HttpTlsError(_) => "The TLS handshake failed",
         }
     }
 
     fn cause(&self) -> Option<&Error> {
         match *self {
-            HttpIoError(ref error) => Some(error as &Error),
+            HttpIoError(ref error) | HttpTimeoutError(ref error) |
+                HttpCertificatePinError(ref error) | HttpIntegrityError(ref error) |
+                HttpResolveError(ref error) | HttpTlsError(ref error) => Some(error as &Error),
             _ => None,
         }
     }
@@ -241,10 +352,18 @@ impl Error for HttpError {
 
 impl FromError<IoError> for HttpError {
     fn from_error(err: IoError) -> HttpError {
-        HttpIoError(err)
+        match err.kind {
+            TimedOut => HttpTimeoutError(err),
+            _ => HttpIoError(err)
+        }
     }
 }
 
+/// The time passed since `start`, a time from `precise_time_ns`.
+fn elapsed_since(start: u64) -> Duration {
+    Duration::nanoseconds((precise_time_ns() - start) as i64)
+}
+
 //FIXME: when Opt-in Built-in Types becomes a thing, we can force these structs
 //to be Send. For now, this has the compiler do a static check.
 fn _assert_send<T: Send>() {
@@ -1,7 +1,8 @@
 use std::fmt;
 use std::io::{IoResult, MemReader, MemWriter};
-use std::io::net::ip::{SocketAddr, ToSocketAddr};
+use std::io::net::ip::{SocketAddr, Port};
 
+use client::{ContentDecoder, Decoder};
 use net::{NetworkStream, NetworkConnector};
 
 pub struct MockStream {
@@ -69,7 +70,59 @@ impl NetworkStream for MockStream {
 pub struct MockConnector;
 
 impl NetworkConnector<MockStream> for MockConnector {
-    fn connect<To: ToSocketAddr>(&mut self, _addr: To, _scheme: &str) -> IoResult<MockStream> {
+    fn connect(&mut self, _host: &str, _port: Port, _scheme: &str) -> IoResult<MockStream> {
         Ok(MockStream::new())
     }
 }
+
+/// A `ContentDecoder` of a made up content-coding, which reverses the bytes
+/// of a body.
+pub struct MockReverse;
+
+impl ContentDecoder for MockReverse {
+    fn decoder(&self) -> Box<Decoder + Send> {
+        box MockReversed(None) as Box<Decoder + Send>
+    }
+}
+
+struct MockReversed(Option<MemReader>);
+
+impl Decoder for MockReversed {
+    fn read(&mut self, body: &mut Reader, buf: &mut [u8]) -> IoResult<uint> {
+        if self.0.is_none() {
+            let mut reversed = try!(body.read_to_end());
+            reversed.reverse();
+            self.0 = Some(MemReader::new(reversed));
+        }
+        self.0.as_mut().unwrap().read(buf)
+    }
+}
+
+/// A macro to create a `NetworkConnector` which returns canned responses,
+/// keyed by the `scheme://host` that is connected to.
+macro_rules! mock_connector(
+    ($name:ident {
+        $($url:expr => $res:expr)*
+    }) => (
+
+        struct $name;
+
+        impl ::net::NetworkConnector<::mock::MockStream> for $name {
+            fn connect(&mut self, host: &str, port: ::Port, scheme: &str)
+                    -> ::std::io::IoResult<::mock::MockStream> {
+                use std::collections::HashMap;
+                debug!("MockStream::connect({}, {}, {})", host, port, scheme);
+                let mut map = HashMap::new();
+                $(map.insert($url, $res);)*
+
+                let key = format!("{}://{}", scheme, host);
+                // ignore port for now
+                match map.get(&key.as_slice()) {
+                    Some(res) => Ok(::mock::MockStream::with_input(res.as_bytes())),
+                    None => panic!("{} doesn't know url {}", stringify!($name), key)
+                }
+            }
+        }
+
+    )
+)
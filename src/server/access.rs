@@ -0,0 +1,206 @@
+//! Access logs
+//!
+//! A `Server` given an `AccessLogger` tells it of every request its handler
+//! answers, once the handler returns, and of every request it answers
+//! itself, such as with `400 Bad Request`. `LogFormat` formats these records as
+//! lines of a log, and `WriterLogger` writes those lines to a file, or any
+//! other `Writer`.
+use std::io::net::ip::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(feature = "json")]
+use serialize::json;
+use time::Tm;
+
+use method::Method;
+use status::StatusCode;
+use uri::RequestUri;
+use uri::RequestUri::{AbsolutePath, AbsoluteUri, Authority, Star};
+use version::HttpVersion;
+
+use self::LogFormat::Common;
+#[cfg(feature = "json")]
+use self::LogFormat::Json;
+
+/// A request a server has answered.
+pub struct AccessRecord<'a> {
+    /// The address of the client.
+    pub remote_addr: SocketAddr,
+    /// When the head of the request was read, in UTC.
+    pub time: Tm,
+    /// The method of the request, or `None` if its head could not be read.
+    pub method: Option<&'a Method>,
+    /// The request-target of the request, or `None` if its head could not
+    /// be read.
+    pub uri: Option<&'a RequestUri>,
+    /// The HTTP version of the request, or `None` if its head could not be
+    /// read.
+    pub version: Option<HttpVersion>,
+    /// The status of the response, or `None` if the handler never started
+    /// it.
+    pub status: Option<StatusCode>,
+    /// The bytes of body the response had.
+    pub bytes: u64,
+    /// How long the request took, from reading its head until the handler
+    /// returned, or until the server answered it.
+    pub duration: Duration,
+}
+
+/// Logs the requests a server has answered.
+pub trait AccessLogger: Send + Sync {
+    /// Log a request, after its handler has returned, or the server has
+    /// answered it.
+    fn log(&self, record: &AccessRecord);
+}
+
+/// The formats of the lines of an access log.
+pub enum LogFormat {
+    /// The Common Log Format of web servers, such as
+    /// `127.0.0.1 - - [10/Oct/2014:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`.
+    Common,
+    /// A JSON object, with the fields of the `AccessRecord`, and the
+    /// duration in milliseconds.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl LogFormat {
+    /// Format a record as a line of a log, without the line break.
+    pub fn format(&self, record: &AccessRecord) -> String {
+        match *self {
+            Common => common(record),
+            #[cfg(feature = "json")]
+            Json => json(record),
+        }
+    }
+}
+
+/// Logs each request with `info!`.
+impl AccessLogger for LogFormat {
+    fn log(&self, record: &AccessRecord) {
+        info!("{}", self.format(record));
+    }
+}
+
+/// Writes a line for each request to a `Writer`, such as a file.
+pub struct WriterLogger<W> {
+    writer: Mutex<W>,
+    format: LogFormat,
+}
+
+impl<W: Writer + Send> WriterLogger<W> {
+    /// Create a logger writing lines in `format` to `writer`.
+    pub fn new(writer: W, format: LogFormat) -> WriterLogger<W> {
+        WriterLogger {
+            writer: Mutex::new(writer),
+            format: format,
+        }
+    }
+}
+
+impl<W: Writer + Send> AccessLogger for WriterLogger<W> {
+    fn log(&self, record: &AccessRecord) {
+        let line = self.format.format(record);
+        let mut writer = self.writer.lock();
+        match writer.write_line(line[]).and_then(|_| writer.flush()) {
+            Ok(()) => (),
+            Err(e) => error!("error writing access log = {}", e)
+        }
+    }
+}
+
+static MONTHS: [&'static str, ..12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                       "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+fn common(record: &AccessRecord) -> String {
+    let tm = &record.time;
+    let status = record.status.map_or("-".to_string(), |status| (status as u16).to_string());
+    let bytes = match record.bytes {
+        0 => "-".to_string(),
+        n => n.to_string()
+    };
+    // a request line that could not be read is logged as `-`, as by Apache
+    let request = match (record.method, record.uri, record.version) {
+        (Some(method), Some(uri), Some(version)) => format!("{} {} {}", method, target(uri), version),
+        _ => "-".to_string()
+    };
+    format!("{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{}\" {} {}",
+            record.remote_addr.ip, tm.tm_mday, MONTHS[tm.tm_mon as uint], tm.tm_year + 1900,
+            tm.tm_hour, tm.tm_min, tm.tm_sec, request, status, bytes)
+}
+
+#[cfg(feature = "json")]
+fn json(record: &AccessRecord) -> String {
+    let status = record.status.map_or("null".to_string(), |status| (status as u16).to_string());
+    format!("{{\"remote_addr\":{},\"time\":{},\"method\":{},\"uri\":{},\"version\":{},\
+             \"status\":{},\"bytes\":{},\"duration_ms\":{}}}",
+            json::encode(&record.remote_addr.to_string()),
+            json::encode(&record.time.rfc3339().to_string()),
+            json_or_null(record.method), json_or_null(record.uri.map(target)),
+            json_or_null(record.version), status, record.bytes,
+            record.duration.num_milliseconds())
+}
+
+/// `value` as a JSON string, or `null`.
+#[cfg(feature = "json")]
+fn json_or_null<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |value| json::encode(&value.to_string()))
+}
+
+/// The request-target as it was sent.
+fn target(uri: &RequestUri) -> String {
+    match *uri {
+        AbsolutePath(ref path) => path.clone(),
+        AbsoluteUri(ref url) => url.to_string(),
+        Authority(ref authority) => authority.clone(),
+        Star => "*".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::net::ip::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+    use time::{Timespec, at_utc};
+
+    use method::Method::Get;
+    use status::StatusCode;
+    use uri::RequestUri::AbsolutePath;
+    use version::HttpVersion::Http11;
+    use super::{AccessRecord, LogFormat};
+
+    #[test]
+    fn test_common_log_format() {
+        let uri = AbsolutePath("/index.html".to_string());
+        let record = AccessRecord {
+            remote_addr: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 4000 },
+            // 2014-10-10 13:55:36 UTC
+            time: at_utc(Timespec::new(1412949336, 0)),
+            method: Some(&Get),
+            uri: Some(&uri),
+            version: Some(Http11),
+            status: Some(StatusCode::Ok),
+            bytes: 2326,
+            duration: Duration::milliseconds(3),
+        };
+        assert_eq!(LogFormat::Common.format(&record)[],
+                   "127.0.0.1 - - [10/Oct/2014:13:55:36 +0000] \"GET /index.html HTTP/1.1\" 200 2326");
+    }
+
+    #[test]
+    fn test_common_log_format_unread_request() {
+        let record = AccessRecord {
+            remote_addr: SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 4000 },
+            time: at_utc(Timespec::new(1412949336, 0)),
+            method: None,
+            uri: None,
+            version: None,
+            status: Some(StatusCode::BadRequest),
+            bytes: 0,
+            duration: Duration::milliseconds(3),
+        };
+        assert_eq!(LogFormat::Common.format(&record)[],
+                   "127.0.0.1 - - [10/Oct/2014:13:55:36 +0000] \"-\" 400 -");
+    }
+}
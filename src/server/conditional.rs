@@ -0,0 +1,117 @@
+//! Conditional requests
+//!
+//! A client with a copy of a representation asks for it again with the
+//! `Etag` or `Last-Modified` it came with, in `If-None-Match` or
+//! `If-Modified-Since`. If the copy is still current, `not_modified`
+//! answers `304 Not Modified` instead of sending the representation again.
+use std::io::IoResult;
+
+use header::common::{ContentEncoding, ContentLength, ContentRange, ContentType, Etag,
+                     IfModifiedSince, IfNoneMatch, LastModified, TransferEncoding};
+use method::Method::{Get, Head};
+use net::Fresh;
+use status::StatusCode::NotModified;
+
+use super::{Request, Response};
+
+/// Whether the copy `req` has of a representation, with the `etag` and
+/// `modified` date given, is still current.
+///
+/// `If-None-Match` is compared weakly with `etag`. `If-Modified-Since` is
+/// only compared with `modified` when there is no `If-None-Match`, and
+/// neither applies to methods other than `GET` and `HEAD`.
+pub fn is_fresh(req: &Request, etag: Option<&Etag>, modified: Option<&LastModified>) -> bool {
+    if req.method != Get && req.method != Head {
+        return false;
+    }
+    match (req.headers.get::<IfNoneMatch>(), etag) {
+        (Some(matcher), Some(etag)) => return matcher.matches(etag),
+        (Some(&IfNoneMatch::Any), None) => return true,
+        (Some(_), None) => return false,
+        (None, _) => ()
+    }
+    match (req.headers.get::<IfModifiedSince>(), modified) {
+        (Some(since), Some(modified)) => since.to_timespec() >= modified.to_timespec(),
+        _ => false
+    }
+}
+
+/// Answer `304 Not Modified` if the copy `req` has is current, given the
+/// `Etag` and `Last-Modified` set on `res`, or give `res` back to send the
+/// representation with.
+///
+/// The `304` keeps the other headers set on `res`, such as `Cache-Control`
+/// and `Expires`, but not those describing a body, which it doesn't have.
+pub fn not_modified<'a>(req: &Request, mut res: Response<'a, Fresh>)
+                        -> IoResult<Option<Response<'a, Fresh>>> {
+    let fresh = is_fresh(req, res.headers().get::<Etag>(), res.headers().get::<LastModified>());
+    if !fresh {
+        return Ok(Some(res));
+    }
+    *res.status_mut() = NotModified;
+    res.headers_mut().remove::<ContentType>();
+    res.headers_mut().remove::<ContentLength>();
+    res.headers_mut().remove::<ContentRange>();
+    res.headers_mut().remove::<ContentEncoding>();
+    res.headers_mut().remove::<TransferEncoding>();
+    try!(res.start().and_then(|res| res.end()));
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemWriter;
+    use time::{Timespec, at_utc};
+
+    use header::common::{ContentLength, Etag, LastModified};
+    use mock::MockStream;
+    use server::{Request, Response};
+    use super::{is_fresh, not_modified};
+
+    macro_rules! sock(
+        ($s:expr) => (::std::str::from_str::<::std::io::net::ip::SocketAddr>($s).unwrap())
+    )
+
+    fn request(method: &str, conditions: &str) -> MockStream {
+        MockStream::with_input(format!("{} / HTTP/1.1\r\n{}\r\n", method, conditions).as_bytes())
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let etag = Etag { weak: false, tag: "v1".to_string() };
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let modified = LastModified(at_utc(Timespec::new(784111777, 0)));
+
+        let mut stream = request("GET", "If-None-Match: W/\"v1\"\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(is_fresh(&req, Some(&etag), None));
+        let mut stream = request("GET", "If-None-Match: \"v0\"\r\n\
+                                         If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(!is_fresh(&req, Some(&etag), Some(&modified)));
+        let mut stream = request("GET", "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(is_fresh(&req, Some(&etag), Some(&modified)));
+        let mut stream = request("POST", "If-None-Match: *\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(!is_fresh(&req, Some(&etag), None));
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let mut stream = request("GET", "If-None-Match: \"v1\"\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        let mut w = MemWriter::new();
+        {
+            let mut res = Response::new(&mut w);
+            res.headers_mut().set(Etag { weak: false, tag: "v1".to_string() });
+            res.headers_mut().set(ContentLength(11));
+            assert!(not_modified(&req, res).unwrap().is_none());
+        }
+        let res = String::from_utf8(w.unwrap()).unwrap();
+        assert!(res[].starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(res[].contains("Etag: \"v1\"\r\n"));
+        assert!(!res[].contains("Content-Length"));
+        assert!(res[].ends_with("\r\n\r\n"));
+    }
+}
@@ -1,26 +1,62 @@
 //! HTTP Server
-use std::io::{Listener, EndOfFile, BufferedReader, BufferedWriter};
+use std::any::{Any, AnyRefExt};
+use std::cmp;
+use std::collections::HashMap;
+use std::default::Default;
+use std::cell::Cell;
+use std::io::{Listener, Buffer, EndOfFile, BufferedReader, BufferedWriter, IoError, IoResult, TimedOut};
 use std::io::net::ip::{IpAddr, Port, SocketAddr};
+use std::io::net::tcp::TcpListener;
+use std::io::timer::sleep;
 use std::os;
-use std::sync::{Arc, TaskPool};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Semaphore, TaskPool};
+use std::sync::atomic::{AtomicBool, AtomicUint, SeqCst};
 use std::task::TaskBuilder;
+use std::time::Duration;
 
+use time::{Timespec, Tm, at_utc, get_time, now_utc, precise_time_ns};
 
-pub use self::request::Request;
-pub use self::response::Response;
+
+pub use self::request::{DEFAULT_MAX_URI_SIZE, Request};
+pub use self::response::{Outcome, Response};
 
 pub use net::{Fresh, Streaming};
 
-use HttpError::HttpIoError;
+use HttpError::{HttpHeadersTooLargeError, HttpIoError, HttpTimeoutError, HttpUriTooLongError};
 use {HttpResult};
-use header::common::Connection;
+use header::common::{Connection, ContentLength, Expect};
+use header::common::Server as ServerHeader;
 use header::common::connection::{KeepAlive, Close};
+use http::HeaderLimits;
+use method::Method;
+use method::Method::Head;
 use net::{NetworkListener, NetworkAcceptor, NetworkStream,
-          HttpAcceptor, HttpListener, HttpStream};
+          HttpAcceptor, HttpListener, HttpsAcceptor, HttpsListener, HttpStream};
+use status::StatusCode;
+use status::StatusCode::{InternalServerError, BadRequest, RequestTimeout, RequestEntityTooLarge, RequestUriTooLong,
+                         RequestHeaderFieldsTooLarge, ExpectationFailed, ServiceUnavailable};
+use uri::RequestUri;
+use version::HttpVersion;
 use version::HttpVersion::{Http10, Http11};
 
+use self::ContinuePolicy::{AcceptAll, RejectAll, AcceptIf};
+use self::OverloadPolicy::{StopAccepting, Reject};
+use self::access::{AccessLogger, AccessRecord};
+
+/// The `Server` header a server sends unless it is given another with
+/// `Server::set_server_header`.
+pub const DEFAULT_SERVER_HEADER: &'static str = concat!("hyper/", env!("CARGO_PKG_VERSION"));
+
+pub mod access;
+pub mod conditional;
+pub mod multipart;
 pub mod request;
 pub mod response;
+pub mod range;
+pub mod sse;
+pub mod staticfile;
+pub mod ws;
 
 /// A server can listen on a TCP socket.
 ///
@@ -28,7 +64,30 @@ pub mod response;
 /// incoming connection, and hand them to the provided handler.
 pub struct Server<L = HttpListener> {
     ip: IpAddr,
-    port: Port
+    port: Port,
+    keep_alive_timeout: Option<Duration>,
+    header_timeout: Option<Duration>,
+    body_timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
+    max_requests: Option<uint>,
+    max_uri_size: uint,
+    header_limits: HeaderLimits,
+    max_body_size: Option<u64>,
+    continue_policy: ContinuePolicy,
+    // binds each socket the server listens on
+    binder: Box<Bind<L> + Send>,
+    server_header: Option<String>,
+    access_logger: Option<Arc<Box<AccessLogger + Send + Sync>>>,
+    error_handler: Arc<Box<ErrorHandler + Send + Sync>>,
+    // a socket bound already, rather than ip and port
+    listener: Option<TcpListener>,
+    // more sockets to bind and listen on
+    addresses: Vec<(IpAddr, Port)>,
+    nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    max_connections: Option<uint>,
+    overload_policy: OverloadPolicy,
+    min_transfer_rate: Option<u64>,
 }
 
 macro_rules! try_option(
@@ -45,7 +104,360 @@ impl Server<HttpListener> {
     pub fn http(ip: IpAddr, port: Port) -> Server {
         Server {
             ip: ip,
-            port: port
+            port: port,
+            keep_alive_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            handshake_timeout: None,
+            max_requests: None,
+            max_uri_size: DEFAULT_MAX_URI_SIZE,
+            header_limits: Default::default(),
+            max_body_size: None,
+            continue_policy: AcceptAll,
+            binder: box BindHttp as Box<Bind<HttpListener> + Send>,
+            server_header: Some(DEFAULT_SERVER_HEADER.to_string()),
+            access_logger: None,
+            error_handler: Arc::new(box DefaultErrorHandler as Box<ErrorHandler + Send + Sync>),
+            listener: None,
+            addresses: Vec::new(),
+            nodelay: false,
+            tcp_keepalive: None,
+            max_connections: None,
+            overload_policy: StopAccepting,
+            min_transfer_rate: None,
+        }
+    }
+
+    /// Creates a new server that will handle `HttpStream`s accepted by a
+    /// socket bound already, such as one bound to a privileged port before
+    /// the process dropped its privileges.
+    pub fn from_listener(mut listener: TcpListener) -> HttpResult<Server> {
+        let addr = try!(listener.socket_name());
+        let mut server = Server::http(addr.ip, addr.port);
+        server.listener = Some(listener);
+        Ok(server)
+    }
+}
+
+impl Server<HttpsListener> {
+    /// Creates a new server that will handle `HttpStream`s over TLS,
+    /// presenting the certificate of the PEM file `cert`, with the private
+    /// key of the PEM file `key`.
+    pub fn https(ip: IpAddr, port: Port, cert: Path, key: Path) -> Server<HttpsListener> {
+        Server {
+            ip: ip,
+            port: port,
+            keep_alive_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            handshake_timeout: None,
+            max_requests: None,
+            max_uri_size: DEFAULT_MAX_URI_SIZE,
+            header_limits: Default::default(),
+            max_body_size: None,
+            continue_policy: AcceptAll,
+            binder: box BindHttps { cert: cert, key: key } as Box<Bind<HttpsListener> + Send>,
+            server_header: Some(DEFAULT_SERVER_HEADER.to_string()),
+            access_logger: None,
+            error_handler: Arc::new(box DefaultErrorHandler as Box<ErrorHandler + Send + Sync>),
+            listener: None,
+            addresses: Vec::new(),
+            nodelay: false,
+            tcp_keepalive: None,
+            max_connections: None,
+            overload_policy: StopAccepting,
+            min_transfer_rate: None,
+        }
+    }
+
+    /// Set how long a new connection may take to make the TLS handshake.
+    ///
+    /// Pass `None` to use the header timeout instead, or ten seconds if
+    /// there is none, which is the default.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Binds to a socket and starts handling connections over TLS with the
+    /// specified number of tasks.
+    ///
+    /// The TLS handshake is made by the task a connection is handed to, and
+    /// the `tls_info` of each `Request` tells what it negotiated.
+    ///
+    /// Panics if `threads` is 0.
+    pub fn listen_threads<H: Handler>(self, handler: H, threads: uint) -> HttpResult<Listening<HttpsAcceptor>> {
+        assert!(threads > 0, "a server needs at least one task to handle connections");
+        let mut acceptors = Vec::new();
+        for &(ip, port) in Some((self.ip, self.port)).iter().chain(self.addresses.iter()) {
+            debug!("binding to {}:{} with TLS", ip, port);
+            let mut listener = try!(self.binder.bind((ip, port)));
+            match self.handshake_timeout.or(self.header_timeout) {
+                Some(timeout) => listener.set_handshake_timeout(Some(timeout)),
+                None => ()
+            }
+            let socket = try!(listener.socket_name());
+            acceptors.push((try!(listener.listen()), socket));
+        }
+        Ok(self.serve(acceptors, handler, threads))
+    }
+
+    /// Binds to a socket and starts handling connections over TLS.
+    ///
+    /// This uses `default_threads()` tasks to handle connections.
+    pub fn listen<H: Handler>(self, handler: H) -> HttpResult<Listening<HttpsAcceptor>> {
+        self.listen_threads(handler, default_threads())
+    }
+}
+
+impl<L> Server<L> {
+    /// Set how long a kept alive connection may wait for the head of its
+    /// next request, once its first request has been handled, before it is
+    /// closed.
+    ///
+    /// A connection that sent nothing more is closed without a response,
+    /// while one that sent part of a head is answered with `408 Request
+    /// Timeout`. Pass `None` to use the header timeout instead, which is
+    /// the default.
+    pub fn set_keep_alive_timeout(&mut self, timeout: Option<Duration>) {
+        self.keep_alive_timeout = timeout;
+    }
+
+    /// Set how long a new connection may take to send the head of its
+    /// first request, however many reads it arrives in.
+    ///
+    /// When it runs out, a `408 Request Timeout` response is sent and the
+    /// connection is closed, so that a client that sends nothing does not
+    /// hold a task forever. Pass `None` to wait as long as the client keeps
+    /// the connection open, which is the default.
+    pub fn set_header_timeout(&mut self, timeout: Option<Duration>) {
+        self.header_timeout = timeout;
+    }
+
+    /// Set how long a handler may take to read the body of a request, once
+    /// its head has been read.
+    ///
+    /// When it runs out, reading the `Request` fails with a `TimedOut`
+    /// error, which the handler may answer with a `408 Request Timeout`, and
+    /// the connection is closed after the handler returns. Pass `None` to
+    /// wait as long as the client keeps the connection open, which is the
+    /// default.
+    pub fn set_body_timeout(&mut self, timeout: Option<Duration>) {
+        self.body_timeout = timeout;
+    }
+
+    /// Set the fewest bytes a second a client may send the head, and then
+    /// the body, of a request at, so that a client trickling them can't
+    /// hold a connection open for as long as the timeouts allow.
+    ///
+    /// Each is measured from its first byte, after a second of grace. A
+    /// head sent slower is answered with `408 Request Timeout`, and a body
+    /// fails to read as if its timeout ran out. Pass `None` for no minimum,
+    /// which is the default.
+    pub fn set_min_transfer_rate(&mut self, bytes_per_sec: Option<u64>) {
+        self.min_transfer_rate = bytes_per_sec;
+    }
+
+    /// Set the most requests that are handled on one connection, after
+    /// which it is closed.
+    ///
+    /// The response to the last request has a `Connection: close` header.
+    /// Pass `None` to handle any number, which is the default.
+    pub fn set_max_requests(&mut self, max: Option<uint>) {
+        self.max_requests = max;
+    }
+
+    /// Set the most bytes the request-target of each request may have.
+    ///
+    /// A request with a longer one is answered with `414 Request-URI Too
+    /// Long`, and its connection closed. This bounds the whole request line,
+    /// whose method and version are short. Defaults to `DEFAULT_MAX_URI_SIZE`.
+    pub fn set_max_uri_size(&mut self, max: uint) {
+        self.max_uri_size = max;
+    }
+
+    /// Set the limits on the size and number of the headers of each request.
+    ///
+    /// A request with headers beyond them is answered with `431 Request
+    /// Header Fields Too Large`, and its connection closed, rather than
+    /// being read into memory. Defaults to `HeaderLimits::default()`.
+    pub fn set_header_limits(&mut self, limits: HeaderLimits) {
+        self.header_limits = limits;
+    }
+
+    /// Set the most bytes of body each request may have.
+    ///
+    /// A request whose Content-Length is larger is answered with `413
+    /// Request Entity Too Large`, and its connection closed, before the
+    /// handler is called. Reading a body of unknown size, such as a chunked
+    /// one, past the limit fails instead, and its connection is closed once
+    /// the handler returns, after answering `413` if the handler had not
+    /// started a response. Pass `None` to allow bodies of any size, which is
+    /// the default.
+    pub fn set_max_body_size(&mut self, max: Option<u64>) {
+        self.max_body_size = max;
+    }
+
+    /// Set how requests sent with `Expect: 100-continue` are answered.
+    ///
+    /// A client sending one waits for a `100 Continue` before it sends the
+    /// body, which the server writes before calling the handler if the
+    /// policy accepts the request. Otherwise, and for any other expectation,
+    /// the server answers `417 Expectation Failed` and closes the
+    /// connection. `Expect` is ignored in HTTP/1.0 requests, whose clients
+    /// don't wait. Defaults to `ContinuePolicy::AcceptAll`.
+    pub fn set_continue_policy(&mut self, policy: ContinuePolicy) {
+        self.continue_policy = policy;
+    }
+
+    /// Set the `Server` header of every response, unless its handler sets
+    /// another. Defaults to `DEFAULT_SERVER_HEADER`; pass `None` to send none.
+    pub fn set_server_header(&mut self, server: Option<String>) {
+        self.server_header = server;
+    }
+
+    /// Log every request a handler answers to `logger`, such as
+    /// `LogFormat::Common`, once the handler returns, and every request the
+    /// server answers itself, such as with `400 Bad Request` or `408 Request
+    /// Timeout`. No requests are logged by default.
+    pub fn set_access_logger<A: AccessLogger>(&mut self, logger: A) {
+        self.access_logger = Some(Arc::new(box logger as Box<AccessLogger + Send + Sync>));
+    }
+
+    /// Answer the requests whose handler panics with `handler`, rather than
+    /// `DefaultErrorHandler`.
+    pub fn set_error_handler<E: ErrorHandler>(&mut self, handler: E) {
+        self.error_handler = Arc::new(box handler as Box<ErrorHandler + Send + Sync>);
+    }
+
+    /// Also listen on `ip` and `port`, handling the connections accepted
+    /// there with the same handler and tasks, and stopping with the rest of
+    /// the server.
+    pub fn add_address(&mut self, ip: IpAddr, port: Port) {
+        self.addresses.push((ip, port));
+    }
+
+    /// Set `TCP_NODELAY` on every connection accepted, so small responses
+    /// are sent at once instead of waiting to be coalesced. Off by default.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    /// Send TCP keepalive probes on every connection accepted, after it has
+    /// been idle for `idle`, so connections to clients that vanished are
+    /// eventually closed. Pass `None` to send none, which is the default.
+    pub fn set_tcp_keepalive(&mut self, idle: Option<Duration>) {
+        self.tcp_keepalive = idle;
+    }
+
+    /// Set the most connections the server keeps open at once, and what it
+    /// does with more, so a flood of connections can't make it take on
+    /// unbounded work. Pass `None` to allow any number, which is the default.
+    pub fn set_max_connections(&mut self, max: Option<uint>, policy: OverloadPolicy) {
+        self.max_connections = max;
+        self.overload_policy = policy;
+    }
+
+    /// Handle the connections of `acceptors` with a pool of `threads` tasks.
+    fn serve<H, S, A>(self, acceptors: Vec<(A, SocketAddr)>, handler: H, threads: uint) -> Listening<A>
+    where H: Handler,
+          S: NetworkStream + Clone,
+          A: NetworkAcceptor<S>, {
+        let connections = Arc::new(Connections::new(self.max_connections, self.overload_policy));
+        let (done_tx, done_rx) = channel();
+
+        let worker = Arc::new(Worker {
+            handler: handler,
+            dates: DateCache::new(),
+            keep_alive_timeout: self.keep_alive_timeout.or(self.header_timeout),
+            header_timeout: self.header_timeout,
+            body_timeout: self.body_timeout,
+            max_requests: self.max_requests,
+            max_uri_size: self.max_uri_size,
+            header_limits: self.header_limits,
+            max_body_size: self.max_body_size,
+            min_transfer_rate: self.min_transfer_rate,
+            nodelay: self.nodelay,
+            tcp_keepalive: self.tcp_keepalive,
+            continue_policy: self.continue_policy,
+            server_header: self.server_header,
+            access_logger: self.access_logger,
+            error_handler: self.error_handler,
+        });
+        // each socket is accepted from by a task of its own, which hands its
+        // connections to the task of the pool
+        let (stream_tx, stream_rx) = channel();
+        for &(ref acceptor, socket) in acceptors.iter() {
+            let mut captured = acceptor.clone();
+            let stream_tx = stream_tx.clone();
+            let shared = connections.clone();
+            TaskBuilder::new().named("hyper acceptor").spawn(proc() {
+                loop {
+                    match captured.accept() {
+                        Ok(stream) => {
+                            // the permit is only waited for once a connection
+                            // is accepted, so that an acceptor waiting on an
+                            // idle socket doesn't hold one another needs
+                            shared.wait_for_room();
+                            // one refused is answered by a task of the pool
+                            // too, as it may need a handshake first
+                            let conn = if shared.has_room() {
+                                let clone = box stream.clone() as Box<NetworkStream + Send>;
+                                Some(OpenConnection::new(&shared, clone))
+                            } else {
+                                None
+                            };
+                            stream_tx.send((stream, captured.clone(), conn));
+                        },
+                        Err(ref e) if e.kind == EndOfFile => {
+                            debug!("server closed {}", socket);
+                            break;
+                        },
+                        Err(e) => {
+                            error!("Connection failed: {}", e);
+                            continue;
+                        }
+                    }
+                }
+            });
+        }
+        drop(stream_tx);
+        TaskBuilder::new().named("hyper pool").spawn(proc() {
+            debug!("threads = {}", threads);
+            let pool = TaskPool::new(threads);
+            // until every acceptor is closed
+            for (stream, acceptor, conn) in stream_rx.iter() {
+                debug!("Incoming stream");
+                let worker = worker.clone();
+                pool.execute(proc() {
+                    let mut stream = match acceptor.handshake(stream) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            debug!("handshake error = {}", e);
+                            return;
+                        }
+                    };
+                    match conn {
+                        Some(conn) => supervise(worker, stream, conn),
+                        None => {
+                            debug!("too many connections, refusing {}", stream.peer_name());
+                            respond_and_close(Response::new(&mut stream), ServiceUnavailable);
+                        }
+                    }
+                });
+            }
+            // workers finish the connections they have, then exit
+            drop(pool);
+            let _ = done_tx.send_opt(());
+        });
+
+        let sockets: Vec<SocketAddr> = acceptors.iter().map(|&(_, socket)| socket).collect();
+        Listening {
+            acceptors: acceptors.into_iter().map(|(acceptor, _)| acceptor).collect(),
+            socket: sockets[0],
+            sockets: sockets,
+            threads: threads,
+            connections: connections,
+            acceptor_done: Some(done_rx),
         }
     }
 }
@@ -60,109 +472,803 @@ impl<L: NetworkListener<S, A>, S: NetworkStream, A: NetworkAcceptor<S>> Server<L
           S: NetworkStream + Clone,
           A: NetworkAcceptor<S>,
           L: NetworkListener<S, A>, {
-        debug!("binding to {}:{}", self.ip, self.port);
-        let mut listener: L = try!(NetworkListener::<S, A>::bind((self.ip, self.port)));
+        assert!(threads > 0, "a server needs at least one task to handle connections");
+        let mut acceptors = Vec::new();
+        for &(ip, port) in Some((self.ip, self.port)).iter().chain(self.addresses.iter()) {
+            debug!("binding to {}:{}", ip, port);
+            let mut listener: L = try!(NetworkListener::<S, A>::bind((ip, port)));
+            let socket = try!(listener.socket_name());
+            acceptors.push((try!(listener.listen()), socket));
+        }
 
-        let socket = try!(listener.socket_name());
+        Ok(self.serve(acceptors, handler, threads))
+    }
 
-        let acceptor = try!(listener.listen());
+    /// Binds to a socket and starts handling connections with the specified number of tasks.
+    ///
+    /// Connections are accepted by a task of their own, and handled by a pool
+    /// of `threads` tasks, so at most `threads` are handled at once. Binding
+    /// to port 0 picks a free port, which `Listening::socket` tells. A server
+    /// made with `Server::from_listener` uses its socket instead.
+    ///
+    /// Panics if `threads` is 0.
+    pub fn listen_threads<H: Handler>(mut self, handler: H, threads: uint) -> HttpResult<Listening<HttpAcceptor>> {
+        match self.listener.take() {
+            Some(listener) => {
+                assert!(threads > 0, "a server needs at least one task to handle connections");
+                let mut listeners = vec![HttpListener::from_tcp(listener)];
+                for &(ip, port) in self.addresses.iter() {
+                    debug!("binding to {}:{}", ip, port);
+                    listeners.push(try!(NetworkListener::bind((ip, port))));
+                }
+                let mut acceptors = Vec::new();
+                for mut listener in listeners.into_iter() {
+                    let socket = try!(listener.socket_name());
+                    acceptors.push((try!(listener.listen()), socket));
+                }
+                Ok(self.serve(acceptors, handler, threads))
+            },
+            None => self.listen_network::<H, HttpStream, HttpAcceptor, HttpListener>(handler, threads)
+        }
+    }
 
-        let mut captured = acceptor.clone();
-        TaskBuilder::new().named("hyper acceptor").spawn(proc() {
-            let handler = Arc::new(handler);
-            debug!("threads = {}", threads);
-            let pool = TaskPool::new(threads);
-            for conn in captured.incoming() {
-                match conn {
-                    Ok(mut stream) => {
-                        debug!("Incoming stream");
-                        let handler = handler.clone();
-                        pool.execute(proc() {
-                            let addr = match stream.peer_name() {
-                                Ok(addr) => addr,
-                                Err(e) => {
-                                    error!("Peer Name error: {}", e);
-                                    return;
-                                }
-                            };
-                            let mut rdr = BufferedReader::new(stream.clone());
-                            let mut wrt = BufferedWriter::new(stream);
-
-                            let mut keep_alive = true;
-                            while keep_alive {
-                                let mut res = Response::new(&mut wrt);
-                                let req = match Request::new(&mut rdr, addr) {
-                                    Ok(req) => req,
-                                    Err(e@HttpIoError(_)) => {
-                                        debug!("ioerror in keepalive loop = {}", e);
-                                        return;
-                                    }
-                                    Err(e) => {
-                                        //TODO: send a 400 response
-                                        error!("request error = {}", e);
-                                        return;
-                                    }
-                                };
-
-                                keep_alive = match (req.version, req.headers.get::<Connection>()) {
-                                    (Http10, Some(conn)) if !conn.0.contains(&KeepAlive) => false,
-                                    (Http11, Some(conn)) if conn.0.contains(&Close)  => false,
-                                    _ => true
-                                };
-                                res.version = req.version;
-                                handler.handle(req, res);
-                                debug!("keep_alive = {}", keep_alive);
-                            }
-
-                        });
-                    },
-                    Err(ref e) if e.kind == EndOfFile => {
-                        debug!("server closed");
-                        break;
+    /// Binds to a socket and starts handling connections.
+    ///
+    /// This uses `default_threads()` tasks to handle connections.
+    pub fn listen<H: Handler>(self, handler: H) -> HttpResult<Listening<HttpAcceptor>> {
+        self.listen_threads(handler, default_threads())
+    }
+
+}
+
+/// Behavior regarding requests sent with `Expect: 100-continue` within a
+/// Server.
+pub enum ContinuePolicy {
+    /// Read the body of every request.
+    AcceptAll,
+    /// Refuse every request that would wait to send its body.
+    RejectAll,
+    /// Read the body of a request if the contained function returns true,
+    /// given its head.
+    AcceptIf(fn(&Request) -> bool),
+}
+
+impl ContinuePolicy {
+    fn accepts(&self, req: &Request) -> bool {
+        match *self {
+            AcceptAll => true,
+            RejectAll => false,
+            AcceptIf(accept) => accept(req),
+        }
+    }
+}
+
+impl Default for ContinuePolicy {
+    fn default() -> ContinuePolicy {
+        AcceptAll
+    }
+}
+
+/// What a Server does with connections past its maximum.
+pub enum OverloadPolicy {
+    /// Stop accepting connections until one closes, leaving new ones queued
+    /// by the operating system.
+    StopAccepting,
+    /// Accept them, but answer `503 Service Unavailable` and close them at
+    /// once.
+    Reject,
+}
+
+impl Default for OverloadPolicy {
+    fn default() -> OverloadPolicy {
+        StopAccepting
+    }
+}
+
+/// The number of tasks `Server::listen` handles connections with, which is
+/// a quarter more than the number of CPUs.
+pub fn default_threads() -> uint {
+    cmp::max(os::num_cpus() * 5 / 4, 1)
+}
+
+/// Write a response of only `status` to a request that is not handled,
+/// before closing its connection.
+/// Handle a connection on a task of its own, so that a handler that panics
+/// ends only that task, then answer the request it left unanswered.
+fn supervise<H: Handler, S: NetworkStream + Clone>(worker: Arc<Worker<H>>, stream: S,
+                                                   conn: OpenConnection) {
+    let conn = Arc::new(conn);
+    let (panicked_tx, panicked_rx) = channel();
+    let (task_worker, task_stream, task_conn) = (worker.clone(), stream.clone(), conn.clone());
+    let result = TaskBuilder::new().named("hyper connection").try(proc() {
+        task_worker.handle_connection(task_stream, &*task_conn, panicked_tx)
+    });
+    let cause = match result {
+        Ok(()) => return,
+        Err(cause) => cause
+    };
+    error!("handler panicked = {}", panic_message(&*cause));
+    let Panicked { addr, outcome, request, start } = match panicked_rx.try_recv() {
+        Ok(panicked) => panicked,
+        // not in a handler, so with no request to answer
+        Err(_) => return
+    };
+    let mut outcome = outcome;
+    // a response already started can only be cut short
+    if outcome.is_none() {
+        let reported = Cell::new(None);
+        {
+            let mut wrt = BufferedWriter::new(stream);
+            let mut res = Response::new(&mut wrt);
+            res.report_to(&reported);
+            res.headers_mut().set_raw("Date", vec![worker.dates.now()]);
+            res.headers_mut().set(Connection(vec![Close]));
+            worker.error_handler.handle_error(&*cause, res);
+        }
+        outcome = reported.get();
+    }
+    if request.is_some() {
+        worker.log(addr, request.as_ref(), &start,
+                   outcome.as_ref().map(|outcome| outcome.status),
+                   outcome.map_or(0, |outcome| outcome.body_bytes));
+    }
+}
+
+/// How a request stood when its handler panicked.
+struct Panicked {
+    addr: SocketAddr,
+    outcome: Option<Outcome>,
+    request: Option<(Method, RequestUri, HttpVersion)>,
+    start: Option<(Tm, u64)>,
+}
+
+/// Sends how a request stands to the supervisor of its connection if
+/// dropped while armed, as when its handler panics.
+struct PanicGuard<'a> {
+    addr: SocketAddr,
+    outcome: &'a Cell<Option<Outcome>>,
+    request: &'a Option<(Method, RequestUri, HttpVersion)>,
+    start: &'a Option<(Tm, u64)>,
+    panicked: &'a Sender<Panicked>,
+    armed: bool,
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for PanicGuard<'a> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.panicked.send_opt(Panicked {
+                addr: self.addr,
+                outcome: self.outcome.get(),
+                request: self.request.clone(),
+                start: self.start.clone(),
+            });
+        }
+    }
+}
+
+fn respond_and_close(mut res: Response<Fresh>, status: StatusCode) {
+    *res.status_mut() = status;
+    res.headers_mut().set(Connection(vec![Close]));
+    res.headers_mut().set(ContentLength(0));
+    match res.start().and_then(|res| res.end()) {
+        Ok(()) => (),
+        Err(e) => debug!("error responding {} = {}", status, e)
+    }
+}
+
+// how long a transfer may take before it is held to the minimum rate
+const MIN_RATE_GRACE_NS: u64 = 1_000_000_000;
+
+/// The read timeout of a connection, shared by its `TimedReader` and the
+/// task handling it.
+struct ReadTimeout {
+    next: Cell<Option<Option<Duration>>>,
+    expired: Cell<bool>,
+    // whether anything was read since the timeout was last set
+    read: Cell<bool>,
+    // when the timeout last set runs out, by precise_time_ns
+    deadline: Cell<Option<u64>>,
+    min_rate: Option<u64>,
+    // when the first byte of the transfer was read, and the bytes read since
+    transfer: Cell<Option<(u64, u64)>>,
+}
+
+impl ReadTimeout {
+    fn new(min_rate: Option<u64>) -> ReadTimeout {
+        ReadTimeout {
+            next: Cell::new(None),
+            expired: Cell::new(false),
+            read: Cell::new(false),
+            deadline: Cell::new(None),
+            min_rate: min_rate,
+            transfer: Cell::new(None),
+        }
+    }
+
+    /// Set the timeout of the reads from now on, which starts with the next
+    /// read and is of all of them together, and start measuring the rate of
+    /// a new transfer.
+    fn set(&self, timeout: Option<Duration>) {
+        self.next.set(Some(timeout));
+        self.read.set(false);
+        self.transfer.set(None);
+    }
+
+    /// When the transfer falls below the minimum rate, unless more is read.
+    fn rate_deadline(&self) -> Option<u64> {
+        match (self.min_rate, self.transfer.get()) {
+            (Some(rate), Some((start, bytes))) if rate > 0 => {
+                Some(start + MIN_RATE_GRACE_NS + bytes * 1_000_000_000 / rate)
+            },
+            _ => None
+        }
+    }
+
+    fn transferred(&self, bytes: uint) {
+        if self.min_rate.is_none() || bytes == 0 {
+            return;
+        }
+        self.transfer.set(match self.transfer.get() {
+            Some((start, total)) => Some((start, total + bytes as u64)),
+            None => Some((precise_time_ns(), bytes as u64))
+        });
+    }
+}
+
+/// Take what `rdr` has read ahead of the last request, without waiting for
+/// anything more.
+fn read_ahead<R: Reader>(rdr: &mut BufferedReader<R>, timeout: &ReadTimeout) -> Vec<u8> {
+    timeout.set(Some(Duration::milliseconds(0)));
+    let buffered = match rdr.fill_buf() {
+        Ok(buf) => buf.to_vec(),
+        Err(_) => vec![]
+    };
+    rdr.consume(buffered.len());
+    buffered
+}
+
+/// Reads a connection, with the read timeout last set on its `ReadTimeout`.
+struct TimedReader<S> {
+    stream: S,
+    timeout: Rc<ReadTimeout>,
+}
+
+impl<S: NetworkStream> Reader for TimedReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let now = precise_time_ns();
+        match self.timeout.next.get() {
+            Some(timeout) => {
+                self.timeout.next.set(None);
+                self.timeout.deadline.set(timeout.map(|t| {
+                    now + cmp::max(t.num_milliseconds(), 0) as u64 * 1_000_000
+                }));
+            },
+            None => ()
+        }
+        // wait only for what is left of the timeout, rather than all of it
+        // for each read, and only as long as the transfer keeps to the
+        // minimum rate, so that it fails only if nothing more has arrived
+        // by then
+        let rate_deadline = self.timeout.rate_deadline();
+        let end = match (self.timeout.deadline.get(), rate_deadline) {
+            (Some(deadline), Some(rate_end)) => Some(cmp::min(deadline, rate_end)),
+            (deadline, rate_end) => deadline.or(rate_end)
+        };
+        self.stream.set_read_timeout(end.map(|end| {
+            Duration::nanoseconds(end.saturating_sub(now) as i64)
+        }));
+        match self.stream.read(buf) {
+            Ok(n) => {
+                if n > 0 {
+                    self.timeout.read.set(true);
+                }
+                self.timeout.transferred(n);
+                Ok(n)
+            },
+            Err(e) => {
+                if e.kind != TimedOut {
+                    return Err(e);
+                }
+                self.timeout.expired.set(true);
+                match rate_deadline {
+                    Some(end) if end <= precise_time_ns() => {
+                        debug!("transfer slower than {} bytes a second", self.timeout.min_rate);
+                        Err(IoError {
+                            kind: TimedOut,
+                            desc: "Transfer slower than the minimum rate",
+                            detail: None
+                        })
                     },
+                    _ => Err(e)
+                }
+            }
+        }
+    }
+}
+
+/// Binds the sockets of a server to its addresses.
+trait Bind<L> {
+    fn bind(&self, addr: (IpAddr, Port)) -> IoResult<L>;
+}
+
+// binds sockets for plain HTTP
+struct BindHttp;
+
+impl Bind<HttpListener> for BindHttp {
+    fn bind(&self, addr: (IpAddr, Port)) -> IoResult<HttpListener> {
+        NetworkListener::bind(addr)
+    }
+}
+
+// binds sockets for HTTP over TLS, presenting `cert` with its key `key`
+struct BindHttps {
+    cert: Path,
+    key: Path,
+}
+
+impl Bind<HttpsListener> for BindHttps {
+    fn bind(&self, addr: (IpAddr, Port)) -> IoResult<HttpsListener> {
+        HttpsListener::bind(addr, &self.cert, &self.key)
+    }
+}
+
+/// What the tasks of a server handle connections with, shared by them.
+struct Worker<H> {
+    handler: H,
+    dates: DateCache,
+    keep_alive_timeout: Option<Duration>,
+    header_timeout: Option<Duration>,
+    body_timeout: Option<Duration>,
+    max_requests: Option<uint>,
+    max_uri_size: uint,
+    header_limits: HeaderLimits,
+    max_body_size: Option<u64>,
+    min_transfer_rate: Option<u64>,
+    nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    continue_policy: ContinuePolicy,
+    server_header: Option<String>,
+    access_logger: Option<Arc<Box<AccessLogger + Send + Sync>>>,
+    error_handler: Arc<Box<ErrorHandler + Send + Sync>>,
+}
+
+impl<H: Handler> Worker<H> {
+    /// Handle the requests of a connection, one after another, until it is
+    /// closed.
+    ///
+    /// If a handler panics, how its request stood is sent to `panicked`.
+    fn handle_connection<S: NetworkStream + Clone>(&self, mut stream: S, conn: &OpenConnection,
+                                                   panicked: Sender<Panicked>) {
+        let addr = match stream.peer_name() {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Peer Name error: {}", e);
+                return;
+            }
+        };
+        match stream.set_nodelay(self.nodelay).and(stream.set_keepalive(self.tcp_keepalive)) {
+            Ok(()) => (),
+            Err(e) => debug!("error setting socket options = {}", e)
+        }
+        let local_addr = stream.socket_name().ok();
+        let tls_info = stream.tls_info();
+        let timeout = Rc::new(ReadTimeout::new(self.min_transfer_rate));
+        let mut rdr = BufferedReader::new(TimedReader {
+            stream: stream.clone(),
+            timeout: timeout.clone(),
+        });
+        let mut wrt = BufferedWriter::new(stream);
+
+        let mut keep_alive = true;
+        let mut handled = 0u;
+        while keep_alive {
+            if conn.set_idle(true) {
+                debug!("server is shutting down, closing connection");
+                return;
+            }
+            timeout.set(if handled == 0 { self.header_timeout } else { self.keep_alive_timeout });
+            let outcome = Cell::new(None);
+            let finished = Cell::new(false);
+            let too_large = Cell::new(false);
+            let mut logged = None;
+            let mut start = None;
+            let mut upgrade = None;
+            {
+                let mut res = Response::upgradable(&mut wrt, &mut upgrade);
+                res.report_to(&outcome);
+                match self.server_header {
+                    Some(ref server) => res.headers_mut().set(ServerHeader(server.clone())),
+                    None => ()
+                }
+                if self.access_logger.is_some() {
+                    start = Some((now_utc(), precise_time_ns()));
+                }
+                let req = Request::with_limits(&mut rdr, addr, self.max_uri_size,
+                                               &self.header_limits);
+                let mut req = match req {
+                    Ok(req) => req,
+                    Err(HttpTimeoutError(_)) if handled > 0 && !timeout.read.get() => {
+                        // no request was started, so none is answered
+                        debug!("kept alive connection idle too long");
+                        return;
+                    }
+                    Err(HttpTimeoutError(_)) => {
+                        debug!("timed out reading request head");
+                        self.reject(res, RequestTimeout, addr, None, &start);
+                        return;
+                    }
+                    Err(HttpUriTooLongError) => {
+                        debug!("request uri longer than {} bytes", self.max_uri_size);
+                        self.reject(res, RequestUriTooLong, addr, None, &start);
+                        return;
+                    }
+                    Err(HttpHeadersTooLargeError) => {
+                        debug!("request headers beyond {}", self.header_limits);
+                        self.reject(res, RequestHeaderFieldsTooLarge, addr, None, &start);
+                        return;
+                    }
+                    Err(e@HttpIoError(_)) => {
+                        debug!("ioerror in keepalive loop = {}", e);
+                        return;
+                    }
                     Err(e) => {
-                        error!("Connection failed: {}", e);
-                        continue;
+                        error!("request error = {}", e);
+                        self.reject(res, BadRequest, addr, None, &start);
+                        return;
+                    }
+                };
+                conn.set_idle(false);
+                if self.access_logger.is_some() {
+                    start = Some((now_utc(), precise_time_ns()));
+                    logged = Some((req.method.clone(), req.uri.clone(), req.version));
+                }
+                res.headers_mut().set_raw("Date", vec![self.dates.now()]);
+                if req.method == Head {
+                    res.discard_body();
+                }
+                match (self.max_body_size, req.headers.get::<ContentLength>()) {
+                    (Some(max), Some(&ContentLength(len)))
+                        if len as u64 > max => {
+                        debug!("request body of {} bytes is larger than {}", len, max);
+                        self.reject(res, RequestEntityTooLarge, addr, logged.as_ref(), &start);
+                        return;
+                    },
+                    _ => ()
+                }
+                // Expect is HTTP/1.1, so it is ignored in HTTP/1.0 requests
+                if req.version != Http10 && req.headers.has::<Expect>() {
+                    let continues = req.headers.get::<Expect>() == Some(&Expect::Continue);
+                    let accepted = continues && self.continue_policy.accepts(&req);
+                    if !accepted {
+                        debug!("expectation failed");
+                        self.reject(res, ExpectationFailed, addr, logged.as_ref(), &start);
+                        return;
+                    }
+                    match res.write_continue() {
+                        Ok(()) => (),
+                        Err(e) => {
+                            debug!("error writing 100 Continue = {}", e);
+                            return;
+                        }
                     }
                 }
+                req.set_max_body_size(self.max_body_size);
+                req.report_to(&finished);
+                req.report_too_large_to(&too_large);
+                timeout.set(self.body_timeout);
+                req.local_addr = local_addr;
+                req.tls_info = tls_info.clone();
+                handled += 1;
+
+                keep_alive = match (req.version, req.headers.get::<Connection>()) {
+                    (Http10, Some(conn)) if !conn.0.contains(&KeepAlive) => false,
+                    (Http10, None) => false,
+                    (Http11, Some(conn)) if conn.0.contains(&Close)  => false,
+                    _ => true
+                };
+                if self.max_requests.map_or(false, |max| handled >= max) {
+                    keep_alive = false;
+                }
+                match (req.version, keep_alive) {
+                    (Http10, true) =>
+                        res.headers_mut().set(Connection(vec![KeepAlive])),
+                    (Http11, false) =>
+                        res.headers_mut().set(Connection(vec![Close])),
+                    _ => ()
+                }
+                res.version = req.version;
+                // a panic in the handler ends this task, which the supervisor
+                // of the connection learns of from the guard as it unwinds
+                let mut guard = PanicGuard {
+                    addr: addr,
+                    outcome: &outcome,
+                    request: &logged,
+                    start: &start,
+                    panicked: &panicked,
+                    armed: true,
+                };
+                self.handler.handle(req, res);
+                guard.armed = false;
             }
-        });
+            // the handler gave up on a body that went past the limit, without
+            // answering
+            if too_large.get() && outcome.get().is_none() {
+                debug!("request body larger than {}", self.max_body_size);
+                let mut res = Response::new(&mut wrt);
+                res.report_to(&outcome);
+                res.headers_mut().set_raw("Date", vec![self.dates.now()]);
+                respond_and_close(res, RequestEntityTooLarge);
+            }
+            if logged.is_some() {
+                let outcome = outcome.get();
+                self.log(addr, logged.as_ref(), &start,
+                         outcome.as_ref().map(|outcome| outcome.status),
+                         outcome.map_or(0, |outcome| outcome.body_bytes));
+            }
+            if timeout.expired.get() {
+                debug!("timed out reading request body, closing connection");
+                keep_alive = false;
+            } else if !finished.get() {
+                // what is left of it would be taken for the next request
+                debug!("request body was not read to its end, closing connection");
+                keep_alive = false;
+            }
+            match upgrade.take() {
+                Some(protocol) => {
+                    debug!("upgrading connection");
+                    let buffered = read_ahead(&mut rdr, &*timeout);
+                    let mut stream = box wrt.unwrap() as Box<NetworkStream + Send>;
+                    // the timeouts of the server are not for the protocol
+                    stream.set_read_timeout(None);
+                    // the connection is no longer the server's to count or
+                    // close once this returns, freeing its task for others
+                    TaskBuilder::new().named("hyper upgrade").spawn(proc() {
+                        let mut protocol = protocol;
+                        protocol.upgrade(stream, buffered);
+                    });
+                    return;
+                },
+                None => ()
+            }
+            debug!("keep_alive = {}", keep_alive);
+        }
+    }
 
-        Ok(Listening {
-            acceptor: acceptor,
-            socket: socket,
-        })
+    /// Answer with `status` and close, logging the answer.
+    fn reject(&self, res: Response<Fresh>, status: StatusCode, addr: SocketAddr,
+              request: Option<&(Method, RequestUri, HttpVersion)>, start: &Option<(Tm, u64)>) {
+        respond_and_close(res, status);
+        self.log(addr, request, start, Some(status), 0);
     }
 
-    /// Binds to a socket and starts handling connections with the specified number of tasks.
-    pub fn listen_threads<H: Handler>(self, handler: H, threads: uint) -> HttpResult<Listening<HttpAcceptor>> {
-        self.listen_network::<H, HttpStream, HttpAcceptor, HttpListener>(handler, threads)
+    /// Log the answer to `request`, or to a request whose head could not be
+    /// read, begun at `start`. Does nothing without an access logger.
+    fn log(&self, addr: SocketAddr, request: Option<&(Method, RequestUri, HttpVersion)>,
+           start: &Option<(Tm, u64)>, status: Option<StatusCode>, bytes: u64) {
+        match (&self.access_logger, start) {
+            (&Some(ref logger), &Some((ref time, began))) => {
+                let elapsed = (precise_time_ns() - began) as i64;
+                logger.log(&AccessRecord {
+                    remote_addr: addr,
+                    time: time.clone(),
+                    method: request.map(|&(ref method, _, _)| method),
+                    uri: request.map(|&(_, ref uri, _)| uri),
+                    version: request.map(|&(_, _, version)| version),
+                    status: status,
+                    bytes: bytes,
+                    duration: Duration::nanoseconds(elapsed),
+                });
+            },
+            _ => ()
+        }
     }
+}
 
-    /// Binds to a socket and starts handling connections.
-    pub fn listen<H: Handler>(self, handler: H) -> HttpResult<Listening<HttpAcceptor>> {
-        self.listen_threads(handler, os::num_cpus() * 5 / 4)
+/// The `Date` of the responses of a server, shared by its tasks, which is
+/// formatted once a second rather than for every response.
+struct DateCache {
+    // the second last formatted, and its raw Date header
+    cached: Mutex<(i64, Vec<u8>)>,
+}
+
+impl DateCache {
+    fn new() -> DateCache {
+        DateCache { cached: Mutex::new((-1, Vec::new())) }
+    }
+
+    /// The raw `Date` header of a response sent now.
+    fn now(&self) -> Vec<u8> {
+        let now = get_time().sec;
+        let mut cached = self.cached.lock();
+        if cached.0 != now {
+            let date = at_utc(Timespec::new(now, 0)).rfc822().to_string();
+            *cached = (now, date.into_bytes());
+        }
+        cached.1.clone()
+    }
+}
+
+/// The connections a server is handling, shared by its tasks and its
+/// `Listening`.
+struct Connections {
+    // a clone of the stream of each, and whether it is idle, between
+    // requests or queued for a task to handle it
+    open: Mutex<HashMap<uint, (Box<NetworkStream + Send>, bool)>>,
+    next_id: AtomicUint,
+    closing: AtomicBool,
+    max: Option<uint>,
+    // a permit for each connection, if accepting waits for one
+    permits: Option<Semaphore>,
+}
+
+impl Connections {
+    fn new(max: Option<uint>, policy: OverloadPolicy) -> Connections {
+        let permits = match (max, policy) {
+            (Some(max), StopAccepting) => Some(Semaphore::new(max as int)),
+            _ => None
+        };
+        Connections {
+            open: Mutex::new(HashMap::new()),
+            next_id: AtomicUint::new(0),
+            closing: AtomicBool::new(false),
+            max: max,
+            permits: permits,
+        }
+    }
+
+    /// Block until another connection may be accepted, if accepting waits.
+    fn wait_for_room(&self) {
+        match self.permits {
+            Some(ref permits) => permits.acquire(),
+            None => ()
+        }
+    }
+
+    /// Give back the room taken by a connection that was closed.
+    fn make_room(&self) {
+        match self.permits {
+            Some(ref permits) => permits.release(),
+            None => ()
+        }
+    }
+
+    /// Whether a connection just accepted may be kept open.
+    fn has_room(&self) -> bool {
+        match (self.max, &self.permits) {
+            // it waited for a permit already
+            (_, &Some(_)) => true,
+            (Some(max), &None) => self.open.lock().len() < max,
+            (None, &None) => true,
+        }
+    }
+}
+
+/// A connection counted as open until it is dropped.
+struct OpenConnection {
+    connections: Arc<Connections>,
+    id: uint,
+}
+
+impl OpenConnection {
+    fn new(connections: &Arc<Connections>, stream: Box<NetworkStream + Send>) -> OpenConnection {
+        let id = connections.next_id.fetch_add(1, SeqCst);
+        // idle until a task takes it from the queue, so that shutdown closes
+        // it rather than waits for a task to be free
+        connections.open.lock().insert(id, (stream, true));
+        OpenConnection { connections: connections.clone(), id: id }
+    }
+
+    /// Mark whether the connection is waiting for its next request,
+    /// returning whether the server is shutting down.
+    fn set_idle(&self, idle: bool) -> bool {
+        let mut open = self.connections.open.lock();
+        match open.get_mut(&self.id) {
+            Some(conn) => conn.1 = idle,
+            None => ()
+        }
+        // read while locked, so that shutdown either sees this as idle or
+        // has already set closing
+        self.connections.closing.load(SeqCst)
     }
+}
 
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.connections.open.lock().remove(&self.id);
+        self.connections.make_room();
+    }
 }
 
 /// A listening server, which can later be closed.
 pub struct Listening<A = HttpAcceptor> {
-    acceptor: A,
-    /// The socket addresses that the server is bound to.
+    acceptors: Vec<A>,
+    /// The socket address that the server is bound to, or the first of
+    /// them, if it listens on several.
     pub socket: SocketAddr,
+    /// All the socket addresses that the server is bound to.
+    pub sockets: Vec<SocketAddr>,
+    /// The number of tasks handling connections.
+    pub threads: uint,
+    connections: Arc<Connections>,
+    acceptor_done: Option<Receiver<()>>,
 }
 
 impl<A: NetworkAcceptor<S>, S: NetworkStream> Listening<A> {
-    /// Stop the server from listening to its socket address.
+    /// Stop the server from listening to its socket addresses.
+    ///
+    /// Connections already accepted are still handled. Every socket is
+    /// closed, even if closing one fails, and the first error is returned.
     pub fn close(&mut self) -> HttpResult<()> {
         debug!("closing server");
-        try!(self.acceptor.close());
-        Ok(())
+        let mut result = Ok(());
+        for acceptor in self.acceptors.iter_mut() {
+            match acceptor.close() {
+                Ok(()) => (),
+                Err(e) => {
+                    debug!("error closing acceptor = {}", e);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        Ok(try!(result))
+    }
+
+    /// Stop the server from listening, and wait for it to finish handling
+    /// the requests it has.
+    ///
+    /// Connections waiting between requests are closed at once, and others
+    /// once their current request has been handled. Any still open after
+    /// `deadline` are closed as well, cutting off their requests, and this
+    /// returns an `HttpTimeoutError` once their handlers return. When this
+    /// returns, the tasks of the server are done with all connections, but
+    /// those upgraded to another protocol.
+    pub fn shutdown(&mut self, deadline: Duration) -> HttpResult<()> {
+        try!(self.close());
+        debug!("shutting down server");
+        self.connections.closing.store(true, SeqCst);
+        let end = precise_time_ns() + cmp::max(deadline.num_nanoseconds().unwrap_or(0), 0) as u64;
+        let mut timed_out = false;
+        loop {
+            {
+                let mut open = self.connections.open.lock();
+                if open.is_empty() {
+                    break;
+                }
+                let expired = precise_time_ns() >= end;
+                for (_, conn) in open.iter_mut() {
+                    if conn.1 || expired {
+                        let _ = conn.0.shutdown();
+                    }
+                }
+                timed_out = timed_out || expired;
+            }
+            sleep(Duration::milliseconds(10));
+        }
+        match self.acceptor_done.take() {
+            Some(done) => { let _ = done.recv_opt(); },
+            None => ()
+        }
+        if timed_out {
+            Err(HttpTimeoutError(IoError {
+                kind: TimedOut,
+                desc: "Server shutdown deadline exceeded",
+                detail: None
+            }))
+        } else {
+            Ok(())
+        }
     }
 }
 
 /// A handler that can handle incoming requests for a server.
+///
+/// A server shares one handler between all of its tasks, behind an `Arc`,
+/// so a handler may hold state for every request, such as configuration or
+/// a cache, as long as it can be shared: anything mutable needs a `Mutex`,
+/// an atomic, or the like.
 pub trait Handler: Sync + Send {
     /// Receives a `Request`/`Response` pair, and should perform some action on them.
     ///
@@ -170,9 +1276,564 @@ pub trait Handler: Sync + Send {
     fn handle(&self, Request, Response<Fresh>);
 }
 
+/// A protocol that a connection is switched to, by answering a request with
+/// `Response::upgrade`.
+pub trait Upgrade: Send {
+    /// Takes over the connection, once the `101 Switching Protocols` response
+    /// is written. `buffered` holds what the client sent after its request
+    /// that the server had already read.
+    ///
+    /// This runs on a task of its own. The connection no longer counts
+    /// toward the most the server keeps open, nor is it closed when the
+    /// server shuts down.
+    fn upgrade(&mut self, stream: Box<NetworkStream + Send>, buffered: Vec<u8>);
+}
+
+/// Answers a request whose handler panicked before starting its response.
+///
+/// The panic is logged, and the connection closed after this response. Only
+/// the task of the connection ends: the task of the pool that supervised it
+/// carries on with other connections. State a handler
+/// shares between requests behind a `Mutex` is poisoned by the panic, so
+/// later requests that lock it panic too rather than see it half changed.
+pub trait ErrorHandler: Send + Sync {
+    /// Answer a request, given what its handler panicked with.
+    fn handle_error(&self, cause: &(Any + Send), res: Response<Fresh>);
+}
+
+/// The `ErrorHandler` of a server unless it is given another, which answers
+/// `500 Internal Server Error` with no body.
+pub struct DefaultErrorHandler;
+
+impl ErrorHandler for DefaultErrorHandler {
+    fn handle_error(&self, _: &(Any + Send), mut res: Response<Fresh>) {
+        *res.status_mut() = InternalServerError;
+        res.headers_mut().set(ContentLength(0));
+        match res.start().and_then(|res| res.end()) {
+            Ok(()) => (),
+            Err(e) => debug!("error responding {} = {}", InternalServerError, e)
+        }
+    }
+}
+
+/// What a handler panicked with, if it was a message.
+fn panic_message(cause: &(Any + Send)) -> &str {
+    let cause = cause as &Any;
+    match cause.downcast_ref::<&'static str>() {
+        Some(message) => *message,
+        None => match cause.downcast_ref::<String>() {
+            Some(message) => message[],
+            None => "Box<Any>"
+        }
+    }
+}
+
 impl Handler for fn(Request, Response<Fresh>) {
     fn handle(&self, req: Request, res: Response<Fresh>) {
         (*self)(req, res)
     }
 }
 
+/// A handler that is also shared with code other than the server, such as
+/// another server, or whatever updates its state.
+impl<H: Handler> Handler for Arc<H> {
+    fn handle(&self, req: Request, res: Response<Fresh>) {
+        (**self).handle(req, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Acceptor, Listener, TimedOut};
+    use std::io::net::ip::Ipv4Addr;
+    use std::io::net::tcp::{TcpListener, TcpStream};
+    use std::io::timer::sleep;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUint, SeqCst};
+    use std::time::Duration;
+    use time::{get_time, precise_time_ns};
+
+    use client::Client;
+    use header::common::Connection;
+    use header::common::connection::Close;
+    use header::common::Upgrade as UpgradeHeader;
+    use header::common::upgrade::ProtocolExt;
+    use http::HeaderLimits;
+    use mock::MockStream;
+    use net::NetworkStream;
+    use net::HttpStream::Http;
+    use uri::RequestUri::AbsolutePath;
+    use super::access::{AccessLogger, AccessRecord, LogFormat};
+    use super::{DEFAULT_SERVER_HEADER, Connections, ContinuePolicy, DateCache, Handler, Listening,
+                OpenConnection, OverloadPolicy, ReadTimeout, Request, Response, Server, TimedReader,
+                Upgrade, default_threads};
+
+    fn hello(_: Request, res: Response) {
+        let mut res = res.start().unwrap();
+        res.write(b"hello").unwrap();
+        res.end().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_closes_idle_connections() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 2).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        let mut client = Client::new();
+        assert_eq!(client.get(url[]).send().unwrap().read_to_string().unwrap()[], "hello");
+
+        // the pool of the client keeps the connection open, idle
+        listening.shutdown(Duration::seconds(5)).unwrap();
+        assert!(client.get(url[]).send().is_err());
+    }
+
+    #[test]
+    fn test_server_header() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(res[].contains(format!("Server: {}\r\n", DEFAULT_SERVER_HEADER)[]));
+        listening.close().unwrap();
+
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_server_header(None);
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(!res[].contains("Server:"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_head() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, "HEAD / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res[].contains("Content-Length: 5\r\n"));
+        assert!(!res[].contains("Transfer-Encoding"));
+        assert!(res[].ends_with("\r\n\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_unread_body_kept_alive() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 1).unwrap();
+        // a body the handler ignores must not be read as the next request
+        let body = "GET /smuggled HTTP/1.1\r\n\r\n";
+        let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}\
+                               GET / HTTP/1.1\r\nConnection: close\r\n\r\n", body.len(), body);
+        let res = raw_response(&listening, request[]);
+        assert_eq!(res[].match_indices("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(!res[].contains("400 Bad Request"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_continue_policy() {
+        let request = "POST / HTTP/1.1\r\nConnection: close\r\nExpect: 100-continue\r\n\
+                       Content-Length: 5\r\n\r\nhello";
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, request);
+        assert!(res[].starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        listening.close().unwrap();
+
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_continue_policy(ContinuePolicy::RejectAll);
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, request);
+        assert!(res[].starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+        // an HTTP/1.0 request is handled as if it expected nothing
+        let res = raw_response(&listening, "POST / HTTP/1.0\r\nExpect: 100-continue\r\n\
+                                            Content-Length: 5\r\n\r\nhello");
+        assert!(res[].starts_with("HTTP/1.0 200 OK\r\n"));
+        listening.close().unwrap();
+    }
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl AccessLogger for Recorder {
+        fn log(&self, record: &AccessRecord) {
+            self.0.lock().push(LogFormat::Common.format(record));
+        }
+    }
+
+    #[test]
+    fn test_access_logger() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_access_logger(Recorder(lines.clone()));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let url = format!("http://127.0.0.1:{}/path", listening.socket.port);
+        Client::new().get(url[]).send().unwrap().read_to_string().unwrap();
+        // the connection is closed once it has been logged
+        listening.shutdown(Duration::seconds(5)).unwrap();
+
+        let lines = lines.lock();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0][].starts_with("127.0.0.1 - - ["));
+        assert!(lines[0][].ends_with("\"GET /path HTTP/1.1\" 200 5"));
+    }
+
+    #[test]
+    fn test_access_logger_rejected() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_access_logger(Recorder(lines.clone()));
+        server.set_max_body_size(Some(4));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        raw_response(&listening, "GARBAGE\r\n\r\n");
+        raw_response(&listening, "POST /up HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        listening.shutdown(Duration::seconds(5)).unwrap();
+
+        let lines = lines.lock();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0][].ends_with("\"-\" 400 -"));
+        assert!(lines[1][].ends_with("\"POST /up HTTP/1.1\" 413 -"));
+    }
+
+    fn panics(req: Request, res: Response) {
+        match req.uri {
+            AbsolutePath(ref path) if path[] == "/panic" => panic!("handler failed"),
+            _ => hello(req, res)
+        }
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(panics, 1).unwrap();
+        let res = raw_response(&listening, "GET /panic HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(res[].contains("Connection: close\r\n"));
+        // the only task of the server is still handling connections
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_handler_panics_in_a_row() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(panics, 1).unwrap();
+        for _ in range(0u, 2) {
+            let res = raw_response(&listening, "GET /panic HTTP/1.1\r\n\r\n");
+            assert!(res[].starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        }
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_add_address() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.add_address(Ipv4Addr(127, 0, 0, 1), 0);
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        assert_eq!(listening.sockets.len(), 2);
+        assert_eq!(listening.socket, listening.sockets[0]);
+        for socket in listening.sockets.iter() {
+            let url = format!("http://127.0.0.1:{}/", socket.port);
+            assert_eq!(Client::new().get(url[]).send().unwrap().read_to_string().unwrap()[], "hello");
+        }
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_nodelay(true);
+        server.set_tcp_keepalive(Some(Duration::seconds(60)));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        assert_eq!(Client::new().get(url[]).send().unwrap().read_to_string().unwrap()[], "hello");
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_connections(Some(1), OverloadPolicy::Reject);
+        let mut listening = server.listen_threads(hello, 2).unwrap();
+        let mut first = TcpStream::connect(listening.socket).unwrap();
+        first.write(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        // the response, with the connection kept open
+        let mut buf = [0u8, ..1024];
+        assert!(first.read(&mut buf).unwrap() > 0);
+
+        let mut second = TcpStream::connect(listening.socket).unwrap();
+        second.write(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let res = second.read_to_string().unwrap();
+        assert!(res[].starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        drop(first);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_max_connections_stop_accepting() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.add_address(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_connections(Some(1), OverloadPolicy::StopAccepting);
+        let mut listening = server.listen_threads(hello, 2).unwrap();
+        // whichever socket its acceptor is waiting on, the first is served
+        let mut first = TcpStream::connect(listening.sockets[1]).unwrap();
+        first.write(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let mut buf = [0u8, ..1024];
+        assert!(first.read(&mut buf).unwrap() > 0);
+
+        // the second waits for the first to close, rather than being refused
+        let mut second = TcpStream::connect(listening.sockets[0]).unwrap();
+        second.write(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").unwrap();
+        second.set_read_timeout(Some(200));
+        assert_eq!(second.read(&mut buf).err().unwrap().kind, TimedOut);
+        drop(first);
+        second.set_read_timeout(None);
+        let res = second.read_to_string().unwrap();
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_min_transfer_rate() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_min_transfer_rate(Some(1000));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        // a head that never ends is dropped after the grace second
+        stream.write(b"GET / HTTP/1.1\r\n").unwrap();
+        let res = stream.read_to_string().unwrap();
+        assert!(res[].starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_from_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut listening = Server::from_listener(listener).unwrap().listen_threads(hello, 1).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        assert_eq!(Client::new().get(url[]).send().unwrap().read_to_string().unwrap()[], "hello");
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_requests(Some(1));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        let res = Client::new().get(url[]).send().unwrap();
+        assert_eq!(res.headers.get(), Some(&Connection(vec![Close])));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_timeout() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_keep_alive_timeout(Some(Duration::milliseconds(100)));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        // an idle connection is closed without a response it did not ask for
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!res[].contains("408 Request Timeout"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_read_timeout_is_for_all_reads() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.socket_name().unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        let timeout = Rc::new(ReadTimeout::new(None));
+        let mut rdr = TimedReader { stream: Http(acceptor.accept().unwrap()), timeout: timeout.clone() };
+
+        timeout.set(Some(Duration::milliseconds(400)));
+        client.write(b"a").unwrap();
+        let mut buf = [0u8, ..8];
+        assert_eq!(rdr.read(&mut buf), Ok(1));
+        sleep(Duration::milliseconds(300));
+        // only what is left of the timeout is waited for, not all of it again
+        let start = precise_time_ns();
+        assert_eq!(rdr.read(&mut buf).err().unwrap().kind, TimedOut);
+        assert!(precise_time_ns() - start < 300_000_000);
+    }
+
+    #[test]
+    fn test_header_timeout() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_header_timeout(Some(Duration::milliseconds(100)));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        // a client that sends nothing is answered, and the connection closed
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        let response = stream.read_to_string().unwrap();
+        assert!(response[].starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        listening.close().unwrap();
+    }
+
+    fn raw_response(listening: &Listening, request: &str) -> String {
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        stream.write(request.as_bytes()).unwrap();
+        stream.read_to_string().unwrap()
+    }
+
+    fn addresses(req: Request, res: Response) {
+        let body = format!("{} {}", req.remote_addr().ip, req.local_addr().unwrap());
+        let mut res = res.start().unwrap();
+        res.write(body.as_bytes()).unwrap();
+        res.end().unwrap();
+    }
+
+    #[test]
+    fn test_connection_addresses() {
+        let server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        let mut listening = server.listen_threads(addresses, 1).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        let body = Client::new().get(url[]).send().unwrap().read_to_string().unwrap();
+        assert_eq!(body, format!("127.0.0.1 {}", listening.socket));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_queued_connection_idle() {
+        let connections = Arc::new(Connections::new(None, OverloadPolicy::StopAccepting));
+        let conn = OpenConnection::new(&connections, box MockStream::new() as Box<NetworkStream + Send>);
+        assert!(connections.open.lock().get(&conn.id).unwrap().1);
+        conn.set_idle(false);
+        assert!(!connections.open.lock().get(&conn.id).unwrap().1);
+        drop(conn);
+        assert!(connections.open.lock().is_empty());
+    }
+
+    #[test]
+    fn test_date_cache() {
+        let dates = DateCache::new();
+        let date = dates.now();
+        assert!(String::from_utf8(date.clone()).unwrap()[].ends_with(" GMT"));
+        // formatted again only once the second has passed
+        let sec = dates.cached.lock().0;
+        assert!(dates.now() == date || get_time().sec != sec);
+    }
+
+    struct Echo;
+
+    impl Upgrade for Echo {
+        fn upgrade(&mut self, mut stream: Box<NetworkStream + Send>, buffered: Vec<u8>) {
+            let rest = stream.read_exact(5 - buffered.len()).unwrap();
+            stream.write(buffered[]).unwrap();
+            stream.write(rest[]).unwrap();
+        }
+    }
+
+    fn echo(_: Request, mut res: Response) {
+        res.headers_mut().set(UpgradeHeader(vec![ProtocolExt("echo".to_string())]));
+        res.upgrade(Echo).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(echo, 1).unwrap();
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nUpgrade: echo\r\n\r\nhello");
+        assert!(res[].starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(res[].contains("Connection: Upgrade\r\n"));
+        assert!(!res[].contains("Transfer-Encoding"));
+        assert!(res[].ends_with("\r\n\r\nhello"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_frees_task() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(echo, 1).unwrap();
+        let mut upgraded = TcpStream::connect(listening.socket).unwrap();
+        upgraded.write(b"GET / HTTP/1.1\r\nUpgrade: echo\r\n\r\n").unwrap();
+        // the only task of the server answers others while it waits
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nUpgrade: echo\r\n\r\nhello");
+        assert!(res[].ends_with("\r\n\r\nhello"));
+        upgraded.write(b"world").unwrap();
+        let res = upgraded.read_to_string().unwrap();
+        assert!(res[].ends_with("\r\n\r\nworld"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_uri_size(8);
+        server.set_header_limits(HeaderLimits { max_size: 1024, max_count: 1, max_line_size: 1024 });
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+
+        let res = raw_response(&listening, "GET /a/long/path HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 414 Request-URI Too Long\r\n"));
+        let res = raw_response(&listening, "GET          / HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 414 Request-URI Too Long\r\n"));
+        let res = raw_response(&listening, "GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_max_body_size() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_body_size(Some(4));
+        let mut listening = server.listen_threads(hello, 1).unwrap();
+        let res = raw_response(&listening, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(res[].starts_with("HTTP/1.1 413 Request Entity Too Large\r\n"));
+        listening.close().unwrap();
+    }
+
+    fn read_body(mut req: Request, res: Response) {
+        match req.read_to_end() {
+            Ok(_) => hello(req, res),
+            Err(_) => ()
+        }
+    }
+
+    #[test]
+    fn test_max_body_size_chunked() {
+        let mut server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        server.set_max_body_size(Some(4));
+        let mut listening = server.listen_threads(read_body, 1).unwrap();
+        // the connection is closed after the answer, not read for the next
+        let res = raw_response(&listening, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                            5\r\nhello\r\n0\r\n\r\n\
+                                            GET / HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 413 Request Entity Too Large\r\n"));
+        assert!(!res[].contains("200 OK"));
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_https_without_certificate() {
+        let server = Server::https(Ipv4Addr(127, 0, 0, 1), 0, Path::new("/nonexistent/cert.pem"),
+                                   Path::new("/nonexistent/key.pem"));
+        assert!(server.listen_threads(hello, 1).is_err());
+    }
+
+    struct Counter(AtomicUint);
+
+    impl Handler for Counter {
+        fn handle(&self, _: Request, res: Response) {
+            let count = self.0.fetch_add(1, SeqCst) + 1;
+            let mut res = res.start().unwrap();
+            res.write(count.to_string().as_bytes()).unwrap();
+            res.end().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shared_handler() {
+        let counter = Arc::new(Counter(AtomicUint::new(0)));
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0)
+            .listen_threads(counter.clone(), 2).unwrap();
+        let url = format!("http://127.0.0.1:{}/", listening.socket.port);
+        let mut client = Client::new();
+        assert_eq!(client.get(url[]).send().unwrap().read_to_string().unwrap()[], "1");
+        assert_eq!(client.get(url[]).send().unwrap().read_to_string().unwrap()[], "2");
+        assert_eq!(counter.0.load(SeqCst), 2);
+        listening.close().unwrap();
+    }
+
+    #[test]
+    fn test_listen_threads() {
+        let mut listening = Server::http(Ipv4Addr(127, 0, 0, 1), 0).listen_threads(hello, 3).unwrap();
+        assert!(listening.socket.port != 0);
+        assert_eq!(listening.socket.ip, Ipv4Addr(127, 0, 0, 1));
+        assert_eq!(listening.threads, 3);
+        assert!(default_threads() >= 1);
+        listening.close().unwrap();
+    }
+}
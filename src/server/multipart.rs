@@ -0,0 +1,390 @@
+//! Multipart form data
+//!
+//! A form with files is sent as a `multipart/form-data` body, whose parts
+//! are the fields of the form, each with headers of its own. `Multipart`
+//! reads such a body a part at a time, and each `Part` is a `Reader` of its
+//! own bytes, so an upload can be written to a file as it arrives rather
+//! than held in memory.
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::io::{BufReader, EndOfFile, IoError, IoResult, InvalidInput, RefReader};
+use std::io::util::{NullWriter, copy};
+use std::slice::bytes::copy_memory;
+use std::str::from_utf8;
+
+use {HttpError, HttpResult};
+use HttpError::{HttpHeaderError, HttpIoError};
+use header::Headers;
+
+use super::Request;
+
+/// The most bytes a `Multipart` reads ahead of what has been read from it.
+const BUF_SIZE: uint = 8 * 1024;
+
+/// The most bytes the headers of a part may have.
+pub const MAX_PART_HEADERS_SIZE: uint = 8 * 1024;
+
+/// A `multipart/form-data` body, read a part at a time.
+pub struct Multipart<R> {
+    body: R,
+    // a line break, "--", and the boundary, which ends each part
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: uint,
+    eof: bool,
+    // whether the part being read has reached its delimiter
+    part_done: bool,
+    done: bool,
+}
+
+/// A part of a `Multipart` body, which reads its bytes.
+pub struct Part<'a, R: 'a> {
+    /// The headers of the part, such as its `Content-Type`.
+    pub headers: Headers,
+    /// The name of the form field, from its `Content-Disposition`.
+    pub name: Option<String>,
+    /// The name of the file the client sent, if the part is one.
+    pub filename: Option<String>,
+    multipart: &'a mut Multipart<R>,
+}
+
+impl<R: Reader> Multipart<R> {
+    /// Read the parts of `body`, delimited by `boundary`.
+    pub fn new(body: R, boundary: &str) -> Multipart<R> {
+        Multipart {
+            body: body,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // the first delimiter is at the start of the body, without a
+            // line break, so it is given one, as if it ended a preamble
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            eof: false,
+            part_done: false,
+            done: false,
+        }
+    }
+
+    /// The next part of the body, or `None` after the last.
+    ///
+    /// What was left unread of the part before is skipped. A body that
+    /// ends before its last delimiter, or a part whose headers are larger
+    /// than `MAX_PART_HEADERS_SIZE`, fails with an `HttpIoError`.
+    pub fn next_part<'a>(&'a mut self) -> HttpResult<Option<Part<'a, R>>> {
+        if self.done {
+            return Ok(None);
+        }
+        // skip the preamble, or the rest of the part before
+        try!(copy(&mut PartReader(self), &mut NullWriter));
+        try!(self.fill(2));
+        let after = self.buf[self.pos..cmp::min(self.pos + 2, self.buf.len())].to_vec();
+        if after[] == b"--" {
+            // the epilogue after the last delimiter is ignored
+            self.done = true;
+            return Ok(None);
+        } else if after[] != b"\r\n" {
+            return Err(malformed("Invalid multipart delimiter"));
+        }
+        let headers = try!(self.read_headers());
+        let (name, filename) = match headers.get_raw("Content-Disposition") {
+            Some(raw) if raw.len() == 1 => match from_utf8(raw[0][]) {
+                Some(value) => (param(value, "name"), param(value, "filename")),
+                None => (None, None)
+            },
+            _ => (None, None)
+        };
+        self.part_done = false;
+        Ok(Some(Part {
+            headers: headers,
+            name: name,
+            filename: filename,
+            multipart: self,
+        }))
+    }
+
+    /// Read the headers of a part, which follow the line break after its
+    /// delimiter.
+    fn read_headers(&mut self) -> HttpResult<Headers> {
+        let mut searched = 0;
+        let end;
+        loop {
+            let (found, unread) = {
+                let unread = self.buf[self.pos..];
+                (find(unread[searched..], b"\r\n\r\n"), unread.len())
+            };
+            match found {
+                // the line break after the delimiter also starts the block
+                // of headers, so a part without any has only one more
+                Some(i) => {
+                    end = searched + i + 4;
+                    break;
+                },
+                None if unread > MAX_PART_HEADERS_SIZE => {
+                    return Err(malformed("Part headers are larger than allowed"));
+                },
+                None if self.eof => return Err(malformed("Body ends within part headers")),
+                None => {
+                    searched = unread.saturating_sub(3);
+                    try!(self.fill(unread + 1));
+                }
+            }
+        }
+        let block = self.buf[self.pos + 2..self.pos + end].to_vec();
+        self.pos += end;
+        match Headers::from_raw(&mut BufReader::new(block[])) {
+            Ok(headers) => Ok(headers),
+            Err(HttpIoError(e)) => Err(HttpIoError(e)),
+            Err(_) => Err(malformed("Invalid part headers"))
+        }
+    }
+
+    /// Read until at least `min` bytes are unread, or the body has ended.
+    fn fill(&mut self, min: uint) -> IoResult<()> {
+        if self.pos > 0 {
+            self.buf = self.buf[self.pos..].to_vec();
+            self.pos = 0;
+        }
+        let mut chunk = [0u8, ..BUF_SIZE];
+        while self.buf.len() < min && !self.eof {
+            match self.body.read(&mut chunk) {
+                Ok(n) => self.buf.push_all(chunk[..n]),
+                Err(ref e) if e.kind == EndOfFile => self.eof = true,
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the bytes of the current part, up to its delimiter.
+    fn read_part(&mut self, out: &mut [u8]) -> IoResult<uint> {
+        if self.part_done || self.done {
+            return Err(IoError { kind: EndOfFile, desc: "End of part", detail: None });
+        }
+        if self.buf.len() - self.pos < self.delimiter.len() {
+            let want = self.delimiter.len() + BUF_SIZE;
+            try!(self.fill(want));
+        }
+        let available = match find(self.buf[self.pos..], self.delimiter[]) {
+            Some(0) => {
+                self.pos += self.delimiter.len();
+                self.part_done = true;
+                return Err(IoError { kind: EndOfFile, desc: "End of part", detail: None });
+            },
+            Some(i) => i,
+            None if self.eof => {
+                return Err(IoError {
+                    kind: InvalidInput,
+                    desc: "Multipart body ends within a part",
+                    detail: None
+                });
+            },
+            // all but what could be the start of the delimiter
+            None => self.buf.len() - self.pos - (self.delimiter.len() - 1)
+        };
+        let count = cmp::min(available, out.len());
+        copy_memory(out, self.buf[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+impl<'a, R: Reader> Part<'a, R> {
+    /// Whether this part is a file, rather than a value.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Write the rest of this part to `sink`, such as a file, as it is
+    /// read, returning how many bytes were written.
+    pub fn save<W: Writer>(&mut self, sink: &mut W) -> IoResult<u64> {
+        let mut buf = [0u8, ..BUF_SIZE];
+        let mut written = 0u64;
+        loop {
+            match self.read(&mut buf) {
+                Ok(n) => {
+                    try!(sink.write(buf[..n]));
+                    written += n as u64;
+                },
+                Err(ref e) if e.kind == EndOfFile => return Ok(written),
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+impl<'a, R: Reader> Reader for Part<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.multipart.read_part(buf)
+    }
+}
+
+/// Reads the current part of a `Multipart`.
+struct PartReader<'a, R: 'a>(&'a mut Multipart<R>);
+
+impl<'a, R: Reader> Reader for PartReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        self.0.read_part(buf)
+    }
+}
+
+impl<'a> Request<'a> {
+    /// Read the body of this request as `multipart/form-data`.
+    ///
+    /// This fails with an `HttpHeaderError` if the `Content-Type` isn't
+    /// `multipart/form-data` with a `boundary`.
+    pub fn multipart<'b>(&'b mut self) -> HttpResult<Multipart<RefReader<'b, Request<'a>>>> {
+        let boundary = match self.headers.get_raw("Content-Type") {
+            Some(raw) if raw.len() == 1 => match from_utf8(raw[0][]) {
+                Some(value) if is_form_data(value) => param(value, "boundary"),
+                _ => None
+            },
+            _ => None
+        };
+        match boundary {
+            Some(ref boundary) if !boundary.is_empty() => {
+                Ok(Multipart::new(self.by_ref(), boundary[]))
+            },
+            _ => Err(HttpHeaderError)
+        }
+    }
+}
+
+fn is_form_data(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    mime.len() == "multipart/form-data".len() &&
+        mime.to_ascii_lower()[] == "multipart/form-data"
+}
+
+/// The value of a parameter of a header, such as the `name` of a
+/// `Content-Disposition`, unquoted.
+fn param(value: &str, name: &str) -> Option<String> {
+    let mut rest = match value.find(';') {
+        Some(i) => value[i + 1..],
+        None => return None
+    };
+    loop {
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => return None
+        };
+        let key = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_left();
+        let (val, next) = if after.starts_with("\"") {
+            // a quoted string, in which a backslash escapes the next char
+            let mut val = String::new();
+            let mut escaped = false;
+            let mut end = None;
+            for (i, c) in after[1..].char_indices() {
+                if escaped {
+                    val.push(c);
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    end = Some(i + 2);
+                    break;
+                } else {
+                    val.push(c);
+                }
+            }
+            match end {
+                Some(end) => (val, after[end..]),
+                None => return None
+            }
+        } else {
+            match after.find(';') {
+                Some(i) => (after[..i].trim().to_string(), after[i..]),
+                None => (after.trim().to_string(), "")
+            }
+        };
+        if key.to_ascii_lower()[] == name {
+            return Some(val);
+        }
+        rest = match next.find(';') {
+            Some(i) => next[i + 1..],
+            None => return None
+        };
+    }
+}
+
+/// The index of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<uint> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    range(0, haystack.len() - needle.len() + 1).find(|&i| haystack[i..i + needle.len()] == needle)
+}
+
+fn malformed(desc: &'static str) -> HttpError {
+    HttpIoError(IoError { kind: InvalidInput, desc: desc, detail: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, MemWriter};
+
+    use mock::MockStream;
+    use server::Request;
+    use super::{Multipart, param};
+
+    macro_rules! sock(
+        ($s:expr) => (::std::str::from_str::<::std::io::net::ip::SocketAddr>($s).unwrap())
+    )
+
+    const BODY: &'static [u8] = b"preamble\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\n--xy\r\nline two\r\n\
+        --xyz--\r\n\
+        epilogue";
+
+    #[test]
+    fn test_param() {
+        let disposition = "form-data; name=\"a;b\"; filename=c.txt";
+        assert_eq!(param(disposition, "name"), Some("a;b".to_string()));
+        assert_eq!(param(disposition, "filename"), Some("c.txt".to_string()));
+        assert_eq!(param(disposition, "size"), None);
+    }
+
+    #[test]
+    fn test_multipart() {
+        let mut multipart = Multipart::new(BufReader::new(BODY), "xyz");
+        {
+            let mut title = multipart.next_part().unwrap().unwrap();
+            assert_eq!(title.name, Some("title".to_string()));
+            assert!(!title.is_file());
+            assert_eq!(title.read_to_string().unwrap()[], "hello");
+        }
+        {
+            let mut upload = multipart.next_part().unwrap().unwrap();
+            assert_eq!(upload.filename, Some("a \"b\".txt".to_string()));
+            assert_eq!(upload.headers.get_raw("Content-Type").unwrap()[0], b"text/plain".to_vec());
+            let mut sink = MemWriter::new();
+            assert_eq!(upload.save(&mut sink).unwrap(), 24);
+            assert_eq!(sink.unwrap(), b"line one\r\n--xy\r\nline two".to_vec());
+        }
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_request_multipart() {
+        let mut head = b"POST /upload HTTP/1.1\r\n\
+                         Content-Type: multipart/form-data; boundary=xyz\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n".to_vec();
+        head.push_all(format!("{:x}\r\n", BODY.len()).as_bytes());
+        head.push_all(BODY);
+        head.push_all(b"\r\n0\r\n\r\n");
+        let mut stream = MockStream::with_input(head[]);
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        let mut multipart = req.multipart().unwrap();
+        // the value is skipped unread
+        assert_eq!(multipart.next_part().unwrap().unwrap().name, Some("title".to_string()));
+        assert_eq!(multipart.next_part().unwrap().unwrap().read_to_end().unwrap().len(), 24);
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+}
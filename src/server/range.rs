@@ -0,0 +1,277 @@
+//! Range requests
+//!
+//! A client that has part of a representation, or wants only part of it,
+//! asks for byte ranges of it with a `Range` header. `respond` answers such
+//! a request with only those bytes, as `206 Partial Content`, so that a
+//! handler serving media or downloads doesn't have to.
+use std::cmp;
+use std::io::{IoResult, MemWriter, Seek, SeekSet};
+use std::io::util::{LimitReader, copy};
+
+use time::precise_time_ns;
+
+use header::Header;
+use header::common::{ContentLength, ContentRange, ContentType, Etag, LastModified, Range};
+use header::common::range::ByteRange;
+use header::common::range::ByteRange::{FromTo, AllFrom, Last};
+use method::Method::{Get, Head};
+use net::Fresh;
+use status::StatusCode::{PartialContent, RequestedRangeNotSatisfiable};
+
+use super::{Request, Response};
+
+use self::Ranges::{Whole, Partial, Unsatisfiable};
+
+/// The most ranges a request is answered with, once those that overlap or
+/// touch are joined. A request for more is answered with the whole body.
+pub const MAX_RANGES: uint = 16;
+
+/// What a request asks for of a representation.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Ranges {
+    /// The whole of it, as it has no `Range`, or one that doesn't apply.
+    Whole,
+    /// The first and last bytes of each range it asks for, in order, with
+    /// those that overlap or touch joined into one.
+    Partial(Vec<(u64, u64)>),
+    /// Only bytes past its end.
+    Unsatisfiable,
+}
+
+/// The ranges `req` asks for of a representation of `len` bytes, given the
+/// `Etag` and `Last-Modified` it is answered with.
+///
+/// Only a `GET` can ask for ranges, and one with an `If-Range` only if it
+/// matches the representation, so that ranges of a representation that has
+/// changed aren't pieced together with an older one.
+///
+/// As [RFC7233](http://tools.ietf.org/html/rfc7233#section-6.1) advises,
+/// ranges that overlap or touch are joined, and a request for more than
+/// `MAX_RANGES` is answered with the whole representation, so that a few
+/// bytes of request can't ask for the same bytes over and over.
+pub fn ranges(req: &Request, len: u64, etag: Option<&Etag>,
+              modified: Option<&LastModified>) -> Ranges {
+    if req.method != Get {
+        return Whole;
+    }
+    let requested = match req.headers.get::<Range>() {
+        Some(&Range(ref requested)) => requested,
+        None => return Whole
+    };
+    match req.headers.get_raw("If-Range") {
+        Some(raw) if !if_range_matches(raw, etag, modified) => return Whole,
+        _ => ()
+    }
+    let mut satisfiable: Vec<(u64, u64)> = requested.iter()
+        .filter_map(|range| satisfy(range, len))
+        .collect();
+    if satisfiable.is_empty() {
+        return Unsatisfiable;
+    }
+    satisfiable.sort();
+    let mut joined: Vec<(u64, u64)> = Vec::with_capacity(satisfiable.len());
+    for &(first, last) in satisfiable.iter() {
+        match joined.last_mut() {
+            Some(prev) if first <= prev.1 + 1 => {
+                prev.1 = cmp::max(prev.1, last);
+                continue;
+            },
+            _ => ()
+        }
+        joined.push((first, last));
+    }
+    if joined.len() > MAX_RANGES {
+        debug!("{} ranges asked for, answering with the whole body", joined.len());
+        Whole
+    } else {
+        Partial(joined)
+    }
+}
+
+/// Answer `req` with the ranges of `body`, which is `len` bytes long, that
+/// it asks for.
+///
+/// The `Etag`, `Last-Modified` and `Content-Type` are taken from those set
+/// on `res`. A single range is answered with `206 Partial Content` and its
+/// `Content-Range`, and several with a `multipart/byteranges` body holding
+/// each of them. A request for none of the bytes is answered with `416
+/// Requested Range Not Satisfiable`, and the rest with the whole body.
+pub fn respond<R: Reader + Seek>(req: &Request, mut res: Response<Fresh>, body: &mut R,
+                                 len: u64) -> IoResult<()> {
+    let ranges = ranges(req, len, res.headers().get::<Etag>(),
+                        res.headers().get::<LastModified>());
+    res.headers_mut().set_raw("Accept-Ranges", vec![b"bytes".to_vec()]);
+    let content_len = match ranges {
+        Whole => len,
+        Unsatisfiable => {
+            *res.status_mut() = RequestedRangeNotSatisfiable;
+            res.headers_mut().set(ContentRange { range: None, instance_length: Some(len) });
+            res.headers_mut().set(ContentLength(0));
+            return res.start().and_then(|res| res.end());
+        },
+        Partial(ref ranges) if ranges.len() == 1 => {
+            let (first, last) = ranges[0];
+            *res.status_mut() = PartialContent;
+            res.headers_mut().set(ContentRange {
+                range: Some((first, last)),
+                instance_length: Some(len)
+            });
+            last - first + 1
+        },
+        Partial(ref ranges) => return multipart(req, res, body, len, ranges[]),
+    };
+    res.headers_mut().set(ContentLength(content_len as uint));
+    let mut res = try!(res.start());
+    if req.method != Head {
+        let first = match ranges {
+            Partial(ref ranges) => ranges[0].0,
+            _ => 0
+        };
+        try!(body.seek(first as i64, SeekSet));
+        try!(copy(&mut LimitReader::new(body.by_ref(), content_len as uint), &mut res));
+    }
+    res.end()
+}
+
+/// Answer with several ranges, as the parts of a `multipart/byteranges`
+/// body.
+fn multipart<R: Reader + Seek>(req: &Request, mut res: Response<Fresh>, body: &mut R, len: u64,
+                               ranges: &[(u64, u64)]) -> IoResult<()> {
+    let boundary = format!("hyper-{:x}", precise_time_ns());
+    let part_type = res.headers().get_raw("Content-Type").map(|raw| raw[0].clone());
+    // the head of each part, so the length of the body is known beforehand
+    let heads: Vec<Vec<u8>> = ranges.iter().map(|&(first, last)| {
+        let mut head = MemWriter::new();
+        let _ = write!(&mut head, "--{}\r\n", boundary);
+        match part_type {
+            Some(ref mime) => {
+                let _ = head.write(b"Content-Type: ");
+                let _ = head.write(mime[]);
+                let _ = head.write(b"\r\n");
+            },
+            None => ()
+        }
+        let _ = write!(&mut head, "Content-Range: bytes {}-{}/{}\r\n\r\n", first, last, len);
+        head.unwrap()
+    }).collect();
+    let end = format!("--{}--\r\n", boundary);
+    let mut content_len = end.len() as u64;
+    for (&(first, last), head) in ranges.iter().zip(heads.iter()) {
+        // each part's bytes are followed by a line break
+        content_len += head.len() as u64 + last - first + 1 + 2;
+    }
+
+    *res.status_mut() = PartialContent;
+    res.headers_mut().set(ContentType(from_str(format!("multipart/byteranges; boundary={}",
+                                                       boundary)[]).unwrap()));
+    res.headers_mut().set(ContentLength(content_len as uint));
+    let mut res = try!(res.start());
+    if req.method != Head {
+        for (&(first, last), head) in ranges.iter().zip(heads.iter()) {
+            try!(res.write(head[]));
+            try!(body.seek(first as i64, SeekSet));
+            try!(copy(&mut LimitReader::new(body.by_ref(), (last - first + 1) as uint), &mut res));
+            try!(res.write(b"\r\n"));
+        }
+        try!(res.write(end.as_bytes()));
+    }
+    res.end()
+}
+
+/// The first and last bytes of a representation of `len` bytes that a
+/// range asks for, or `None` if it asks for none of them.
+pub fn satisfy(range: &ByteRange, len: u64) -> Option<(u64, u64)> {
+    match *range {
+        FromTo(first, last) if first < len => Some((first, cmp::min(last, len - 1))),
+        AllFrom(first) if first < len => Some((first, len - 1)),
+        Last(n) if n > 0 && len > 0 => Some((len - cmp::min(n, len), len - 1)),
+        _ => None
+    }
+}
+
+/// Whether an `If-Range` names the representation: by its strong `Etag`,
+/// or by exactly its `Last-Modified` date.
+fn if_range_matches(raw: &[Vec<u8>], etag: Option<&Etag>, modified: Option<&LastModified>) -> bool {
+    let as_etag: Option<Etag> = Header::parse_header(raw);
+    match (as_etag, etag) {
+        (Some(ref theirs), Some(ours)) => {
+            return !theirs.weak && !ours.weak && theirs.tag == ours.tag;
+        },
+        (Some(_), None) => return false,
+        _ => ()
+    }
+    let as_date: Option<LastModified> = Header::parse_header(raw);
+    match (as_date, modified) {
+        (Some(theirs), Some(ours)) => theirs.to_timespec() == ours.to_timespec(),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, MemWriter};
+
+    use header::common::Etag;
+    use mock::MockStream;
+    use server::{Request, Response};
+    use super::{MAX_RANGES, ranges, respond};
+    use super::Ranges::{Partial, Unsatisfiable, Whole};
+
+    macro_rules! sock(
+        ($s:expr) => (::std::str::from_str::<::std::io::net::ip::SocketAddr>($s).unwrap())
+    )
+
+    fn request(range: &str) -> MockStream {
+        MockStream::with_input(format!("GET / HTTP/1.1\r\n{}\r\n", range).as_bytes())
+    }
+
+    #[test]
+    fn test_ranges() {
+        let etag = Etag { weak: false, tag: "v1".to_string() };
+        let mut stream = request("Range: bytes=0-4, 20-, -3\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(ranges(&req, 11, None, None), Partial(vec![(0, 4), (8, 10)]));
+        assert_eq!(ranges(&req, 0, None, None), Unsatisfiable);
+
+        let mut stream = request("Range: bytes=0-4\r\nIf-Range: \"v0\"\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(ranges(&req, 11, Some(&etag), None), Whole);
+        let mut stream = request("Range: bytes=0-4\r\nIf-Range: \"v1\"\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(ranges(&req, 11, Some(&etag), None), Partial(vec![(0, 4)]));
+    }
+
+    #[test]
+    fn test_ranges_joined() {
+        let mut stream = request("Range: bytes=7-8, 0-4, 3-6, 10-\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(ranges(&req, 11, None, None), Partial(vec![(0, 8), (10, 10)]));
+
+        let many: Vec<String> = range(0u, MAX_RANGES + 1).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+        let mut stream = request(format!("Range: bytes={}\r\n", many.connect(", "))[]);
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(ranges(&req, 100, None, None), Whole);
+    }
+
+    #[test]
+    fn test_respond_multipart() {
+        let mut stream = request("Range: bytes=0-4, 6-\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        let mut w = MemWriter::new();
+        {
+            let mut res = Response::new(&mut w);
+            res.headers_mut().set_raw("Content-Type", vec![b"text/plain".to_vec()]);
+            let body = b"hello world";
+            respond(&req, res, &mut BufReader::new(body), body.len() as u64).unwrap();
+        }
+        let res = String::from_utf8(w.unwrap()).unwrap();
+        assert!(res[].starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(res[].contains("Content-Type: multipart/byteranges; boundary=hyper-"));
+        assert!(res[].contains("Content-Range: bytes 0-4/11\r\n\r\nhello\r\n"));
+        assert!(res[].contains("Content-Range: bytes 6-10/11\r\n\r\nworld\r\n"));
+        // the Content-Length counts the whole multipart body
+        let body_start = res[].find_str("\r\n\r\n").unwrap() + 4;
+        let len = res[].lines().find(|line| line.starts_with("Content-Length: ")).unwrap();
+        assert_eq!(len.trim()["Content-Length: ".len()..], (res.len() - body_start).to_string()[]);
+    }
+}
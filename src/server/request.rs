@@ -2,23 +2,46 @@
 //!
 //! These are requests that a `hyper::Server` receives, and include its method,
 //! target URI, headers, and message body.
-use std::io::IoResult;
+use std::cell::Cell;
+use std::default::Default;
+use std::io::{IoError, IoResult, EndOfFile, InvalidInput};
 use std::io::net::ip::SocketAddr;
+use std::io::util::LimitReader;
+
+use url::form_urlencoded;
 
 use {HttpResult};
+use HttpError::{HttpHeaderError, HttpIoError};
 use version::{HttpVersion};
-use method::Method::{mod, Get, Head};
+use method::Method;
 use header::Headers;
-use header::common::{ContentLength, TransferEncoding};
-use http::{read_request_line};
+use header::common::{ContentLength, ContentType, TransferEncoding};
+use header::common::transfer_encoding::Encoding::Chunked;
+use http::{read_request_line_within, HeaderLimits};
 use http::HttpReader;
+use net::TlsInfo;
 use http::HttpReader::{SizedReader, ChunkedReader, EmptyReader};
+use mime::Mime;
+use mime::TopLevel::Application;
+use mime::SubLevel::WwwFormUrlEncoded;
 use uri::RequestUri;
+use uri::RequestUri::{AbsolutePath, AbsoluteUri};
+
+/// The most bytes the request-target of a `Request` may have, unless its
+/// `Server` allows another size: 8KB.
+pub const DEFAULT_MAX_URI_SIZE: uint = 8 * 1024;
+
+/// The most bytes of body left unread by a handler that are read and
+/// thrown away, so its connection can be kept alive: 64KB.
+pub const MAX_DRAIN_SIZE: u64 = 64 * 1024;
 
 /// A request bundles several parts of an incoming `NetworkStream`, given to a `Handler`.
 pub struct Request<'a> {
     /// The IP address of the remote connection.
     pub remote_addr: SocketAddr,
+    /// The local address the connection was accepted on, if its stream has
+    /// one.
+    pub local_addr: Option<SocketAddr>,
     /// The `Method`, such as `Get`, `Post`, etc.
     pub method: Method,
     /// The headers of the incoming request.
@@ -27,7 +50,19 @@ pub struct Request<'a> {
     pub uri: RequestUri,
     /// The version of HTTP for this request.
     pub version: HttpVersion,
-    body: HttpReader<&'a mut (Reader + 'a)>
+    /// What was negotiated in the TLS handshake of the connection, if it
+    /// was made over TLS.
+    pub tls_info: Option<TlsInfo>,
+    body: HttpReader<&'a mut (Reader + 'a)>,
+    max_body_size: Option<u64>,
+    body_read: u64,
+    trailers: Option<Headers>,
+    // the trailers are held to the same limits as the headers
+    header_limits: HeaderLimits,
+    // where to leave whether the body was read to its end, once dropped
+    finished: Option<&'a Cell<bool>>,
+    // where to leave that reading the body went past its limit
+    too_large: Option<&'a Cell<bool>>,
 }
 
 
@@ -35,46 +70,252 @@ impl<'a> Request<'a> {
 
     /// Create a new Request, reading the StartLine and Headers so they are
     /// immediately useful.
-    pub fn new(mut stream: &'a mut (Reader + 'a), addr: SocketAddr) -> HttpResult<Request<'a>> {
-        let (method, uri, version) = try!(read_request_line(&mut stream));
+    pub fn new(stream: &'a mut (Reader + 'a), addr: SocketAddr) -> HttpResult<Request<'a>> {
+        Request::with_limits(stream, addr, DEFAULT_MAX_URI_SIZE, &Default::default())
+    }
+
+    /// Create a new Request like `new`, with a request-target of at most
+    /// `max_uri_size` bytes, and headers within `limits`.
+    ///
+    /// A longer request-target is an `HttpUriTooLongError`, and headers
+    /// beyond the limits are an `HttpHeadersTooLargeError`. The trailers of
+    /// a chunked body are held to the same limits, and reading a body whose
+    /// trailers are beyond them is an error.
+    pub fn with_limits(mut stream: &'a mut (Reader + 'a), addr: SocketAddr, max_uri_size: uint,
+                       limits: &HeaderLimits) -> HttpResult<Request<'a>> {
+        let (method, uri, version) = try!(read_request_line_within(&mut stream, max_uri_size));
         debug!("Request Line: {} {} {}", method, uri, version);
-        let headers = try!(Headers::from_raw(&mut stream));
+        let headers = try!(Headers::from_raw_with_limits(&mut stream, limits));
         debug!("Headers: [\n{}]", headers);
 
 
-        let body = if method == Get || method == Head {
-            EmptyReader(stream)
-        } else if headers.has::<ContentLength>() {
-            match headers.get::<ContentLength>() {
-                Some(&ContentLength(len)) => SizedReader(stream, len),
-                None => unreachable!()
-            }
-        } else if headers.has::<TransferEncoding>() {
-            todo!("check for Transfer-Encoding: chunked");
-            ChunkedReader(stream, None)
-        } else {
-            EmptyReader(stream)
+        // the body is framed by the headers alone, whatever the method, and
+        // Transfer-Encoding overrides any Content-Length
+        let body = match (headers.get::<TransferEncoding>(), headers.get::<ContentLength>()) {
+            (Some(&TransferEncoding(ref encodings)), _) => {
+                if encodings.last() != Some(&Chunked) {
+                    // the end of the body cannot be found, see RFC7230#3.3.3
+                    debug!("request body is not chunked last: {}", encodings);
+                    return Err(HttpHeaderError);
+                }
+                ChunkedReader(stream, None)
+            },
+            (None, Some(&ContentLength(len))) => SizedReader(stream, len),
+            (None, None) => EmptyReader(stream)
         };
 
         Ok(Request {
             remote_addr: addr,
+            local_addr: None,
             method: method,
             uri: uri,
             headers: headers,
             version: version,
-            body: body
+            tls_info: None,
+            body: body,
+            max_body_size: None,
+            body_read: 0,
+            trailers: None,
+            header_limits: limits.clone(),
+            finished: None,
+            too_large: None,
         })
     }
+
+    /// The address of the client that sent this request.
+    #[inline]
+    pub fn remote_addr(&self) -> SocketAddr { self.remote_addr }
+
+    /// The local address the request was received on, if it is known.
+    #[inline]
+    pub fn local_addr(&self) -> Option<SocketAddr> { self.local_addr }
+
+    /// What was negotiated in the TLS handshake of the connection, if it was
+    /// made over TLS, such as the certificate of the client.
+    #[inline]
+    pub fn tls_info(&self) -> Option<&TlsInfo> { self.tls_info.as_ref() }
+
+    /// How many bytes of body are left to read, if that is known.
+    ///
+    /// The size of a chunked body is not known until its last chunk has
+    /// been read.
+    pub fn remaining(&self) -> Option<u64> {
+        match self.body {
+            SizedReader(_, remaining) => Some(remaining as u64),
+            ChunkedReader(_, Some(0)) | EmptyReader(_) => Some(0),
+            _ => None
+        }
+    }
+
+    /// The trailers sent after the last chunk of a chunked body.
+    ///
+    /// These are only known once the body has been read to its end, and
+    /// are `None` until then, or if the body was not chunked.
+    pub fn trailers(&self) -> Option<&Headers> {
+        self.trailers.as_ref()
+    }
+
+    fn read_trailers(&mut self) -> IoResult<()> {
+        match self.body {
+            ChunkedReader(_, Some(0)) if self.trailers.is_none() => (),
+            _ => return Ok(())
+        }
+        match Headers::from_raw_with_limits(self.body.get_mut(), &self.header_limits) {
+            Ok(trailers) => {
+                debug!("trailers [\n{}]", trailers);
+                self.trailers = Some(trailers);
+                Ok(())
+            },
+            Err(HttpIoError(e)) => Err(e),
+            Err(e) => Err(IoError {
+                kind: InvalidInput,
+                desc: "Invalid trailers",
+                detail: Some(e.to_string())
+            })
+        }
+    }
+
+    /// Leave whether the body was read to its end in `finished` once this
+    /// is dropped, such as for a `Server` to tell whether the connection can
+    /// be kept alive.
+    ///
+    /// What is left of a body of at most `MAX_DRAIN_SIZE` bytes is read and
+    /// thrown away first, so that it isn't taken for the next request.
+    pub fn report_to(&mut self, finished: &'a Cell<bool>) {
+        self.finished = Some(finished);
+    }
+
+    /// Leave in `too_large` whether reading the body went past the limit set
+    /// with `set_max_body_size`, such as for a `Server` to answer `413
+    /// Request Entity Too Large` if the handler did not answer. Such a body
+    /// is never read to its end.
+    pub fn report_too_large_to(&mut self, too_large: &'a Cell<bool>) {
+        self.too_large = Some(too_large);
+    }
+
+    /// Read what is left of the body, if it is at most `max` bytes, and
+    /// whether it was read to its end.
+    fn drain(&mut self, max: u64) -> bool {
+        if self.max_body_size.map_or(false, |max| self.body_read > max) {
+            // the rest of it is not read, however little is left
+            return false;
+        }
+        match self.remaining() {
+            Some(left) if left > max => return false,
+            _ => ()
+        }
+        let mut buf = [0u8, ..4096];
+        let mut left = max;
+        loop {
+            match self.read(&mut buf) {
+                Ok(count) if count as u64 > left => return false,
+                Ok(count) => left -= count as u64,
+                Err(ref e) if e.kind == EndOfFile => return true,
+                Err(e) => {
+                    debug!("error draining request body = {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Set the most bytes of body that may be read from this Request.
+    ///
+    /// Reading past the limit returns an error instead, for bodies whose
+    /// size is not declared, such as chunked ones. Pass `None` to read
+    /// bodies of any size, which is the default.
+    pub fn set_max_body_size(&mut self, max: Option<u64>) {
+        self.max_body_size = max;
+    }
+
+    /// The names and values of the query of the request-target, decoded.
+    ///
+    /// A request-target without a query has none.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        match self.uri {
+            AbsolutePath(ref path) => match path[].find('?') {
+                Some(i) => form_urlencoded::parse_str(path[i + 1..]),
+                None => vec![]
+            },
+            AbsoluteUri(ref url) => url.query_pairs().unwrap_or(vec![]),
+            _ => vec![]
+        }
+    }
+
+    /// Read the body of this request as an `application/x-www-form-urlencoded`
+    /// form, of at most `max` bytes, and decode the names and values of its
+    /// fields.
+    ///
+    /// This fails with an `HttpHeaderError` if the `Content-Type` is another,
+    /// and with an `HttpIoError` if the body is larger than `max`.
+    pub fn form_pairs(&mut self, max: uint) -> HttpResult<Vec<(String, String)>> {
+        if !is_form_urlencoded(&self.headers) {
+            return Err(HttpHeaderError);
+        }
+        // one byte more than allowed tells a body that is too large
+        let body = try!(LimitReader::new(self.by_ref(), max.saturating_add(1)).read_to_end());
+        if body.len() > max {
+            return Err(HttpIoError(IoError {
+                kind: InvalidInput,
+                desc: "Request body is larger than allowed",
+                detail: Some(format!("more than {} bytes", max))
+            }));
+        }
+        Ok(form_urlencoded::parse_str(String::from_utf8_lossy(body[])[]))
+    }
+}
+
+fn is_form_urlencoded(headers: &Headers) -> bool {
+    match headers.get::<ContentType>() {
+        Some(&ContentType(Mime(Application, WwwFormUrlEncoded, _))) => true,
+        _ => false
+    }
 }
 
 impl<'a> Reader for Request<'a> {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
-        self.body.read(buf)
+        let count = match self.body.read(buf) {
+            Ok(count) => count,
+            Err(e) => {
+                // the trailers end the body, so they are read with it
+                if e.kind == EndOfFile {
+                    try!(self.read_trailers());
+                }
+                return Err(e);
+            }
+        };
+        self.body_read += count as u64;
+        match self.max_body_size {
+            Some(max) if self.body_read > max => {
+                self.too_large.map(|too_large| too_large.set(true));
+                Err(IoError {
+                    kind: InvalidInput,
+                    desc: "Request body is larger than allowed",
+                    detail: Some(format!("more than {} bytes", max))
+                })
+            },
+            _ => Ok(count)
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Request<'a> {
+    fn drop(&mut self) {
+        match self.finished {
+            Some(finished) => finished.set(self.drain(MAX_DRAIN_SIZE)),
+            None => ()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+    use std::default::Default;
+    use std::uint;
+
+    use http::HeaderLimits;
     use mock::MockStream;
     use super::Request;
 
@@ -82,6 +323,152 @@ mod tests {
         ($s:expr) => (::std::str::from_str::<::std::io::net::ip::SocketAddr>($s).unwrap())
     )
 
+    #[test]
+    fn test_max_body_size() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Host: example.domain\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            hello\
+        ");
+
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        req.set_max_body_size(Some(4));
+        assert!(req.read_to_string().is_err());
+    }
+
+    #[test]
+    fn test_sized_body_ends_at_content_length() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 5\r\n\
+            \r\n\
+            helloGET / HTTP/1.1\r\n\
+        ");
+
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(req.remaining(), Some(5));
+        assert_eq!(req.read_to_string(), Ok("hello".into_string()));
+        assert_eq!(req.remaining(), Some(0));
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            6\r\n world\r\n\
+            0\r\n\
+            Expires: 0\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+        ");
+
+        {
+            let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+            assert_eq!(req.remaining(), None);
+            assert_eq!(req.read_to_string(), Ok("hello world".into_string()));
+            assert_eq!(req.remaining(), Some(0));
+            assert_eq!(req.trailers().unwrap().get_raw("Expires"), Some([b"0".to_vec()][]));
+        }
+        // the trailers were read with the body, so the next request follows
+        assert_eq!(stream.read_exact(3), Ok(b"GET".to_vec()));
+    }
+
+    #[test]
+    fn test_trailers_within_limits() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            0\r\n\
+            Expires: 0\r\n\
+            X-Trailer: 1\r\n\
+            \r\n\
+        ");
+        let limits = HeaderLimits { max_count: 1, ..Default::default() };
+        let mut req = Request::with_limits(&mut stream, sock!("127.0.0.1:80"), 1024, &limits).unwrap();
+        assert!(req.read_to_string().is_err());
+        assert!(req.trailers().is_none());
+    }
+
+    #[test]
+    fn test_unread_body_is_drained() {
+        let finished = Cell::new(false);
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            0\r\n\
+            \r\n\
+            GET / HTTP/1.1\r\n\
+        ");
+
+        {
+            let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+            req.report_to(&finished);
+        }
+        assert!(finished.get());
+        assert_eq!(stream.read_exact(3), Ok(b"GET".to_vec()));
+    }
+
+    #[test]
+    fn test_body_too_large_is_not_finished() {
+        let finished = Cell::new(true);
+        let too_large = Cell::new(false);
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            5\r\nhello\r\n\
+            0\r\n\
+            \r\n\
+        ");
+
+        {
+            let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+            req.set_max_body_size(Some(4));
+            req.report_to(&finished);
+            req.report_too_large_to(&too_large);
+            assert!(req.read_to_string().is_err());
+        }
+        assert!(too_large.get());
+        assert!(!finished.get());
+    }
+
+    #[test]
+    fn test_large_unread_body_is_not_drained() {
+        let finished = Cell::new(true);
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Length: 1000000\r\n\
+            \r\n\
+            hello\
+        ");
+
+        {
+            let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+            req.report_to(&finished);
+        }
+        assert!(!finished.get());
+    }
+
+    #[test]
+    fn test_transfer_encoding_not_chunked() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Transfer-Encoding: gzip\r\n\
+            \r\n\
+        ");
+
+        assert!(Request::new(&mut stream, sock!("127.0.0.1:80")).is_err());
+    }
+
     #[test]
     fn test_get_empty_body() {
         let mut stream = MockStream::with_input(b"\
@@ -108,6 +495,62 @@ mod tests {
         assert_eq!(req.read_to_string(), Ok("".into_string()));
     }
 
+    #[test]
+    fn test_query_pairs() {
+        let mut stream = MockStream::with_input(b"GET /search?q=a+b%21&page=2 HTTP/1.1\r\n\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(req.query_pairs(), vec![("q".into_string(), "a b!".into_string()),
+                                           ("page".into_string(), "2".into_string())]);
+
+        let mut stream = MockStream::with_input(b"GET /search HTTP/1.1\r\n\r\n");
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(req.query_pairs(), vec![]);
+    }
+
+    #[test]
+    fn test_form_pairs() {
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Type: application/x-www-form-urlencoded; charset=utf-8\r\n\
+            Content-Length: 17\r\n\
+            \r\n\
+            name=J%C3%B6rg&x=\
+        ");
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(req.form_pairs(17), Ok(vec![("name".into_string(), "J\u00f6rg".into_string()),
+                                              ("x".into_string(), "".into_string())]));
+
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 17\r\n\
+            \r\n\
+            name=J%C3%B6rg&x=\
+        ");
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(req.form_pairs(16).is_err());
+
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            a=b\
+        ");
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert_eq!(req.form_pairs(uint::MAX), Ok(vec![("a".into_string(), "b".into_string())]));
+
+        let mut stream = MockStream::with_input(b"\
+            POST / HTTP/1.1\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: 3\r\n\
+            \r\n\
+            a=b\
+        ");
+        let mut req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        assert!(req.form_pairs(16).is_err());
+    }
+
     #[test]
     fn test_post_empty_body() {
         let mut stream = MockStream::with_input(b"\
@@ -2,28 +2,55 @@
 //!
 //! These are responses sent by a `hyper::Server` to clients, after
 //! receiving a request.
-use std::io::IoResult;
+use std::cell::Cell;
+use std::io::{IoError, IoResult, InvalidInput};
+use std::mem;
 
 use time::now_utc;
 
 use header;
 use header::common;
+use header::common::connection::ConnectionHeader;
 use http::{CR, LF, LINE_ENDING, HttpWriter};
-use http::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter};
+use http::HttpWriter::{ThroughWriter, ChunkedWriter, SizedWriter, EmptyWriter};
 use status;
+use status::StatusClass::Informational;
+use status::StatusCode::{NoContent, NotModified};
 use net::{Fresh, Streaming};
 use version;
 
+use super::Upgrade;
+
 /// The outgoing half for a Tcp connection, created by a `Server` and given to a `Handler`.
 pub struct Response<'a, W = Fresh> {
     /// The HTTP version of this response.
     pub version: version::HttpVersion,
-    // Stream the Response is writing to, not accessible through UnwrittenResponse
-    body: HttpWriter<&'a mut (Writer + 'a)>,
+    // Stream the Response is writing to, not accessible through UnwrittenResponse.
+    // Only taken as the response is consumed, since it is needed on drop.
+    body: Option<HttpWriter<&'a mut (Writer + 'a)>>,
     // The status code for the request.
     status: status::StatusCode,
     // The outgoing headers on this response.
-    headers: header::Headers
+    headers: header::Headers,
+    // Where the protocol the connection is upgraded to is left for the server.
+    upgrade: Option<&'a mut Option<Box<Upgrade + Send>>>,
+    // Where how the response ended is left for the server.
+    outcome: Option<&'a Cell<Option<Outcome>>>,
+    // Whether the body is counted, but not sent.
+    discard: bool,
+    // Whether the head is only written at the end, once the body is counted.
+    deferred_head: bool,
+    // The bytes of body written so far.
+    body_bytes: u64
+}
+
+/// How a response went, which a `Server` learns after its handler returns.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Outcome {
+    /// The status the response was sent with.
+    pub status: status::StatusCode,
+    /// The bytes of body written, including any that were discarded.
+    pub body_bytes: u64,
 }
 
 impl<'a, W> Response<'a, W> {
@@ -42,15 +69,35 @@ impl<'a, W> Response<'a, W> {
         Response {
             status: status,
             version: version,
-            body: body,
-            headers: headers
+            body: Some(body),
+            headers: headers,
+            upgrade: None,
+            outcome: None,
+            discard: false,
+            deferred_head: false,
+            body_bytes: 0
         }
     }
 
     /// Deconstruct this Response into its constituent parts.
-    pub fn deconstruct(self) -> (version::HttpVersion, HttpWriter<&'a mut (Writer + 'a)>,
-                                 status::StatusCode, header::Headers) {
-        (self.version, self.body, self.status, self.headers)
+    pub fn deconstruct(mut self) -> (version::HttpVersion, HttpWriter<&'a mut (Writer + 'a)>,
+                                     status::StatusCode, header::Headers) {
+        self.deferred_head = false;
+        let headers = mem::replace(&mut self.headers, header::Headers::new());
+        (self.version, self.body.take().unwrap(), self.status, headers)
+    }
+
+    fn body(&mut self) -> &mut HttpWriter<&'a mut (Writer + 'a)> {
+        self.body.as_mut().unwrap()
+    }
+
+    fn write_head(&mut self) -> IoResult<()> {
+        debug!("writing head: {} {}", self.version, self.status);
+        let stream = self.body().get_mut();
+        try!(write!(stream, "{} {}{}{}", self.version, self.status, CR as char, LF as char));
+        debug!("headers [\n{}]", self.headers);
+        try!(write!(stream, "{}", self.headers));
+        stream.write(LINE_ENDING)
     }
 }
 
@@ -61,29 +108,46 @@ impl<'a> Response<'a, Fresh> {
             status: status::StatusCode::Ok,
             version: version::HttpVersion::Http11,
             headers: header::Headers::new(),
-            body: ThroughWriter(stream)
+            body: Some(ThroughWriter(stream)),
+            upgrade: None,
+            outcome: None,
+            discard: false,
+            deferred_head: false,
+            body_bytes: 0
         }
     }
 
+    /// Creates a new Response whose connection can be upgraded to another
+    /// protocol, which `Response::upgrade` leaves in `upgrade`.
+    pub fn upgradable(stream: &'a mut (Writer + 'a),
+                      upgrade: &'a mut Option<Box<Upgrade + Send>>) -> Response<'a, Fresh> {
+        let mut res = Response::new(stream);
+        res.upgrade = Some(upgrade);
+        res
+    }
+
     /// Consume this Response<Fresh>, writing the Headers and Status and creating a Response<Streaming>
     pub fn start(mut self) -> IoResult<Response<'a, Streaming>> {
-        debug!("writing head: {} {}", self.version, self.status);
-        try!(write!(&mut self.body, "{} {}{}{}", self.version, self.status, CR as char, LF as char));
-
         if !self.headers.has::<common::Date>() {
             self.headers.set(common::Date(now_utc()));
         }
 
 
-        let mut chunked = true;
+        // 1xx, 204 and 304 responses end with their headers
+        let bodyless = self.status.class() == Informational || self.status == NoContent ||
+                       self.status == NotModified;
+        // a discarded body is counted for its Content-Length, unless one is
+        // set, so the head can only be written at its end
+        let deferred = self.discard && !bodyless && !self.headers.has::<common::ContentLength>();
+        let mut chunked = !bodyless && !self.discard;
         let mut len = 0;
 
         match self.headers.get::<common::ContentLength>() {
-            Some(cl) => {
+            Some(cl) if !bodyless => {
                 chunked = false;
                 len = **cl;
             },
-            None => ()
+            _ => ()
         };
 
         // cant do in match above, thanks borrowck
@@ -104,24 +168,86 @@ impl<'a> Response<'a, Fresh> {
         }
 
 
-        debug!("headers [\n{}]", self.headers);
-        try!(write!(&mut self.body, "{}", self.headers));
-
-        try!(self.body.write(LINE_ENDING));
+        if !deferred {
+            try!(self.write_head());
+        }
 
-        let stream = if chunked {
-            ChunkedWriter(self.body.unwrap())
+        let stream = self.body.take().unwrap().unwrap();
+        let stream = if bodyless || self.discard {
+            EmptyWriter(stream)
+        } else if chunked {
+            ChunkedWriter(stream)
         } else {
-            SizedWriter(self.body.unwrap(), len)
+            SizedWriter(stream, len)
         };
 
         // "copy" to change the phantom type
-        Ok(Response {
+        let res = Response {
             version: self.version,
-            body: stream,
+            body: Some(stream),
             status: self.status,
-            headers: self.headers
-        })
+            headers: mem::replace(&mut self.headers, header::Headers::new()),
+            upgrade: self.upgrade.take(),
+            outcome: self.outcome,
+            discard: self.discard,
+            deferred_head: deferred,
+            body_bytes: 0
+        };
+        res.report();
+        Ok(res)
+    }
+
+    /// Tell a client that sent `Expect: 100-continue` to send the body of
+    /// its request, by writing a `100 Continue` response ahead of this one.
+    ///
+    /// A `Server` does this before calling its handler, if its
+    /// `ContinuePolicy` accepts the request.
+    pub fn write_continue(&mut self) -> IoResult<()> {
+        let stream = self.body().get_mut();
+        try!(stream.write(b"HTTP/1.1 100 Continue\r\n\r\n"));
+        stream.flush()
+    }
+
+    /// Leave how this response went in `outcome` once it starts, and again
+    /// once it ends, such as for a `Server` to log it.
+    pub fn report_to(&mut self, outcome: &'a Cell<Option<Outcome>>) {
+        self.outcome = Some(outcome);
+    }
+
+    /// Send no body, as in a response to a `HEAD` request.
+    ///
+    /// What is written is counted rather than sent, so that, unless a
+    /// `Content-Length` is set, the response has the one its body would have
+    /// had, and is only sent when it ends, or without one if it is dropped
+    /// before it ends. A `Server` does this for every `HEAD` request, so
+    /// handlers can answer them as they would a `GET`.
+    pub fn discard_body(&mut self) {
+        self.discard = true;
+    }
+
+    /// Switch the connection to another protocol, such as WebSocket.
+    ///
+    /// This answers `101 Switching Protocols` with the headers set so far,
+    /// which should include `Upgrade`. The server then stops reading
+    /// requests from the connection, and gives its stream to `protocol` once
+    /// everything it buffered is written.
+    ///
+    /// Fails without writing anything if this response was not created by a
+    /// `Server`, so has no connection to give.
+    pub fn upgrade<U: Upgrade>(mut self, protocol: U) -> IoResult<()> {
+        let slot = match self.upgrade.take() {
+            Some(slot) => slot,
+            None => return Err(IoError {
+                kind: InvalidInput,
+                desc: "Response connection cannot be upgraded",
+                detail: None
+            })
+        };
+        self.status = status::StatusCode::SwitchingProtocols;
+        self.headers.set(common::Connection(vec![ConnectionHeader("Upgrade".to_string())]));
+        try!(try!(self.start()).end());
+        *slot = Some(box protocol as Box<Upgrade + Send>);
+        Ok(())
     }
 
     /// Get a mutable reference to the status.
@@ -134,21 +260,129 @@ impl<'a> Response<'a, Fresh> {
 
 impl<'a> Response<'a, Streaming> {
     /// Flushes all writing of a response to the client.
-    pub fn end(self) -> IoResult<()> {
+    pub fn end(mut self) -> IoResult<()> {
         debug!("ending");
-        try!(self.body.end());
+        self.report();
+        if self.deferred_head {
+            self.deferred_head = false;
+            self.headers.set(common::ContentLength(self.body_bytes as uint));
+            try!(self.write_head());
+        }
+        try!(self.body.take().unwrap().end());
         Ok(())
     }
+
+    /// End a chunked response with trailers after its last chunk, such as a
+    /// checksum of the body computed while it was written.
+    ///
+    /// Clients expect the trailers declared in a `Trailer` header, which
+    /// must be set before the response starts. Fails with `InvalidInput`,
+    /// without ending the body, if it is not chunked, as only a chunked body
+    /// has a place for trailers. A discarded body ends without them.
+    pub fn write_trailers(mut self, trailers: header::Headers) -> IoResult<()> {
+        if self.discard {
+            return self.end();
+        }
+        match self.body {
+            Some(ChunkedWriter(_)) => (),
+            _ => return Err(IoError {
+                kind: InvalidInput,
+                desc: "Only a chunked response can have trailers",
+                detail: None
+            })
+        }
+        self.report();
+        debug!("trailers [\n{}]", trailers);
+        let stream = self.body().get_mut();
+        try!(stream.write(b"0\r\n"));
+        try!(write!(stream, "{}", trailers));
+        try!(stream.write(LINE_ENDING));
+        stream.flush()
+    }
+
+    fn report(&self) {
+        match self.outcome {
+            Some(outcome) => outcome.set(Some(Outcome {
+                status: self.status,
+                body_bytes: self.body_bytes
+            })),
+            None => ()
+        }
+    }
 }
 
 impl<'a> Writer for Response<'a, Streaming> {
     fn write(&mut self, msg: &[u8]) -> IoResult<()> {
         debug!("write {} bytes", msg.len());
-        self.body.write(msg)
+        if !self.discard {
+            try!(self.body().write(msg));
+        }
+        self.body_bytes += msg.len() as u64;
+        Ok(())
     }
 
     fn flush(&mut self) -> IoResult<()> {
-        self.body.flush()
+        self.body().flush()
     }
 }
 
+#[unsafe_destructor]
+impl<'a, W> Drop for Response<'a, W> {
+    // a discarded body dropped before it ended still has a head to send,
+    // without a Content-Length, as its length is not known
+    fn drop(&mut self) {
+        if self.deferred_head {
+            self.deferred_head = false;
+            match self.write_head().and_then(|_| self.body().flush()) {
+                Ok(()) => (),
+                Err(e) => debug!("error writing head on drop = {}", e)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemWriter;
+
+    use header::Headers;
+    use header::common::ContentLength;
+    use super::Response;
+
+    #[test]
+    fn test_write_trailers() {
+        let mut w = MemWriter::new();
+        {
+            let mut res = Response::new(&mut w);
+            res.headers_mut().set_raw("Trailer", vec![b"X-Checksum".to_vec()]);
+            let mut res = res.start().unwrap();
+            res.write(b"hello").unwrap();
+            let mut trailers = Headers::new();
+            trailers.set_raw("X-Checksum", vec![b"5".to_vec()]);
+            res.write_trailers(trailers).unwrap();
+        }
+        let written = String::from_utf8(w.unwrap()).unwrap();
+        assert!(written[].ends_with("5\r\nhello\r\n0\r\nX-Checksum: 5\r\n\r\n"));
+
+        let mut w = MemWriter::new();
+        let mut res = Response::new(&mut w);
+        res.headers_mut().set(ContentLength(0));
+        assert!(res.start().unwrap().write_trailers(Headers::new()).is_err());
+    }
+
+    #[test]
+    fn test_discarded_body_dropped() {
+        let mut w = MemWriter::new();
+        {
+            let mut res = Response::new(&mut w);
+            res.discard_body();
+            let mut res = res.start().unwrap();
+            res.write(b"hello").unwrap();
+        }
+        let written = String::from_utf8(w.unwrap()).unwrap();
+        assert!(written[].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!written[].contains("Content-Length"));
+        assert!(written[].ends_with("\r\n\r\n"));
+    }
+}
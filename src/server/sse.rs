@@ -0,0 +1,168 @@
+//! Server-Sent Events
+//!
+//! An event stream is a response that never ends on its own, in which the
+//! server writes events as they happen, in the `text/event-stream` format
+//! that browsers read with `EventSource`.
+use std::io::{IoError, IoResult, InvalidInput, Timer};
+use std::time::Duration;
+
+use mime::Mime;
+use mime::TopLevel::Text;
+use mime::SubLevel::Ext;
+
+use header::common::{CacheControl, ContentType};
+use header::common::cache_control::CacheDirective::NoCache;
+use net::{Fresh, Streaming};
+
+use super::Response;
+
+/// An event to send on an `EventStream`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Event {
+    /// The name of the event, which is `message` if it has none.
+    pub name: Option<String>,
+    /// The data of the event, which may be several lines.
+    pub data: String,
+    /// The id of the event, which a client reconnecting asks to continue
+    /// after with `Last-Event-ID`.
+    pub id: Option<String>,
+}
+
+/// A response that sends Server-Sent Events.
+pub struct EventStream<'a> {
+    res: Response<'a, Streaming>,
+}
+
+impl<'a> EventStream<'a> {
+    /// Start an event stream, answering with `Content-Type:
+    /// text/event-stream` and the other headers set on `res`.
+    pub fn new(mut res: Response<'a, Fresh>) -> IoResult<EventStream<'a>> {
+        res.headers_mut().set(ContentType(Mime(Text, Ext("event-stream".to_string()), vec![])));
+        res.headers_mut().set(CacheControl(vec![NoCache]));
+        let mut res = try!(res.start());
+        // send the headers now, instead of with the first event
+        try!(res.flush());
+        Ok(EventStream { res: res })
+    }
+
+    /// Send an event, and flush it to the client.
+    ///
+    /// Each line of `data` is sent as a line of data. The `name` and `id`
+    /// are a single line, so fail with `InvalidInput` if they contain a line
+    /// break.
+    pub fn send_event(&mut self, name: Option<&str>, data: &str, id: Option<&str>) -> IoResult<()> {
+        let mut buf = String::new();
+        try!(field(&mut buf, "event", name));
+        try!(field(&mut buf, "id", id));
+        for line in data.lines_any() {
+            buf.push_str("data: ");
+            buf.push_str(line);
+            buf.push('\n');
+        }
+        if data.is_empty() {
+            buf.push_str("data\n");
+        }
+        buf.push('\n');
+        try!(self.res.write(buf.as_bytes()));
+        self.res.flush()
+    }
+
+    /// Send an `Event`, and flush it to the client.
+    pub fn send(&mut self, event: &Event) -> IoResult<()> {
+        self.send_event(event.name.as_ref().map(|s| s[]), event.data[],
+                        event.id.as_ref().map(|s| s[]))
+    }
+
+    /// Send a comment, which clients ignore, but which keeps the connection
+    /// from looking idle.
+    pub fn comment(&mut self, text: &str) -> IoResult<()> {
+        for line in text.lines_any() {
+            try!(write!(&mut self.res, ":{}\n", line));
+        }
+        if text.is_empty() {
+            try!(self.res.write(b":\n"));
+        }
+        self.res.flush()
+    }
+
+    /// Send the events received from `events`, until every sender is gone.
+    ///
+    /// Every `keep_alive`, this also sends an empty comment, so proxies and
+    /// clients don't close the connection while there are no events.
+    pub fn forward(&mut self, events: Receiver<Event>, keep_alive: Duration) -> IoResult<()> {
+        let mut timer = try!(Timer::new());
+        let ticks = timer.periodic(keep_alive);
+        loop {
+            select! {
+                event = events.recv_opt() => match event {
+                    Ok(event) => try!(self.send(&event)),
+                    Err(()) => return Ok(())
+                },
+                () = ticks.recv() => try!(self.comment(""))
+            }
+        }
+    }
+
+    /// End the event stream, and the response.
+    pub fn end(self) -> IoResult<()> {
+        self.res.end()
+    }
+}
+
+/// Write a single line field, if it has a value.
+fn field(buf: &mut String, name: &str, value: Option<&str>) -> IoResult<()> {
+    match value {
+        Some(value) if value.contains_char('\n') || value.contains_char('\r') => Err(IoError {
+            kind: InvalidInput,
+            desc: "Event name and id must be a single line",
+            detail: Some(format!("{} = {}", name, value))
+        }),
+        Some(value) => {
+            buf.push_str(name);
+            buf.push_str(": ");
+            buf.push_str(value);
+            buf.push('\n');
+            Ok(())
+        },
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemWriter;
+    use std::time::Duration;
+
+    use server::Response;
+    use super::{Event, EventStream};
+
+    #[test]
+    fn test_send_event() {
+        let mut w = MemWriter::new();
+        {
+            let mut events = EventStream::new(Response::new(&mut w)).unwrap();
+            events.send_event(Some("update"), "one\ntwo", Some("7")).unwrap();
+            events.send_event(None, "three", None).unwrap();
+            assert!(events.send_event(Some("bad\nname"), "", None).is_err());
+        }
+        let written = String::from_utf8(w.get_ref().to_vec()).unwrap();
+        assert!(written[].contains("Content-Type: text/event-stream\r\n"));
+        // the body is chunked, so look for the events in its chunks
+        assert!(written[].contains("event: update\nid: 7\ndata: one\ndata: two\n\n"));
+        assert!(written[].contains("data: three\n\n"));
+    }
+
+    #[test]
+    fn test_forward() {
+        let mut w = MemWriter::new();
+        {
+            let mut events = EventStream::new(Response::new(&mut w)).unwrap();
+            let (tx, rx) = channel();
+            tx.send(Event { name: None, data: "hi".to_string(), id: None });
+            drop(tx);
+            events.forward(rx, Duration::seconds(60)).unwrap();
+        }
+        let written = String::from_utf8(w.unwrap()).unwrap();
+        assert!(written[].contains("data: hi\n\n"));
+    }
+}
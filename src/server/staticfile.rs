@@ -0,0 +1,165 @@
+//! Static files
+//!
+//! `serve` answers a request with a file, as a web server does: with its
+//! type, length and modification time, answering conditional requests with
+//! `304 Not Modified`, and a `Range` with only the bytes asked for.
+use std::ascii::AsciiExt;
+use std::io::{File, FileNotFound, FileStat, IoResult, PermissionDenied, TypeFile};
+
+use mime::Mime;
+use time::{Timespec, at_utc};
+
+use header::common::{Allow, ContentLength, ContentType, Etag, LastModified};
+use method::Method::{Get, Head};
+use net::Fresh;
+use status::StatusCode;
+use status::StatusCode::{Forbidden, InternalServerError, MethodNotAllowed, NotFound};
+
+use super::{Request, Response};
+use super::{conditional, range};
+
+// the types of files by extension, of which anything else is
+// application/octet-stream
+static CONTENT_TYPES: [(&'static str, &'static str), ..18] = [
+    ("css", "text/css; charset=utf-8"),
+    ("gif", "image/gif"),
+    ("htm", "text/html; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("webm", "video/webm"),
+    ("woff", "application/font-woff"),
+    ("xml", "application/xml"),
+];
+
+/// The `Content-Type` of a file, by the extension of its path.
+pub fn content_type(path: &Path) -> Mime {
+    let ext = path.extension_str().map(|ext| ext.to_ascii_lower());
+    let mime = CONTENT_TYPES.iter()
+        .find(|&&(known, _)| Some(known) == ext.as_ref().map(|ext| ext[]))
+        .map_or("application/octet-stream", |&(_, mime)| mime);
+    from_str(mime).unwrap()
+}
+
+/// The strong `Etag` a file is served with, made of its size and its
+/// modification time.
+pub fn etag(stat: &FileStat) -> Etag {
+    Etag { weak: false, tag: format!("{:x}-{:x}", stat.size, stat.modified) }
+}
+
+/// Answer `req` with the file at `path`.
+///
+/// A `GET` or `HEAD` is answered with the file, its `Content-Type`,
+/// `Content-Length`, `Last-Modified` and `Etag`, or `304 Not Modified` if
+/// the request's copy of it is current, as by `conditional::not_modified`. A
+/// `Range` is answered with only the bytes it asks for, as by
+/// `range::respond`. Other methods are answered with `405 Method Not Allowed`.
+///
+/// A file that can't be read is answered with `404 Not Found`, `403
+/// Forbidden` or `500 Internal Server Error`, and its error returned.
+pub fn serve(req: &Request, mut res: Response<Fresh>, path: &Path) -> IoResult<()> {
+    if req.method != Get && req.method != Head {
+        res.headers_mut().set(Allow(vec![Get, Head]));
+        return answer(res, MethodNotAllowed);
+    }
+    let (mut file, stat) = match File::open(path).and_then(|mut file| {
+        file.stat().map(|stat| (file, stat))
+    }) {
+        Ok((_, ref stat)) if stat.kind != TypeFile => return answer(res, NotFound),
+        Ok(opened) => opened,
+        Err(e) => {
+            debug!("error opening {} = {}", path.display(), e);
+            try!(answer(res, match e.kind {
+                FileNotFound => NotFound,
+                PermissionDenied => Forbidden,
+                _ => InternalServerError
+            }));
+            return Err(e);
+        }
+    };
+
+    let modified = Timespec::new((stat.modified / 1000) as i64, 0);
+    res.headers_mut().set(LastModified(at_utc(modified)));
+    res.headers_mut().set(etag(&stat));
+    let mut res = match try!(conditional::not_modified(req, res)) {
+        Some(res) => res,
+        None => return Ok(())
+    };
+    res.headers_mut().set(ContentType(content_type(path)));
+    range::respond(req, res, &mut file, stat.size)
+}
+
+/// Answer with a status and no body.
+fn answer(mut res: Response<Fresh>, status: StatusCode) -> IoResult<()> {
+    *res.status_mut() = status;
+    res.headers_mut().set(ContentLength(0));
+    res.start().and_then(|res| res.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{File, MemWriter, TempDir};
+
+    use mock::MockStream;
+    use server::{Request, Response};
+    use super::{content_type, etag, serve};
+
+    macro_rules! sock(
+        ($s:expr) => (::std::str::from_str::<::std::io::net::ip::SocketAddr>($s).unwrap())
+    )
+
+    fn get(path: &Path, head: &str) -> String {
+        let mut stream = MockStream::with_input(head.as_bytes());
+        let req = Request::new(&mut stream, sock!("127.0.0.1:80")).unwrap();
+        let mut w = MemWriter::new();
+        let _ = serve(&req, Response::new(&mut w), path);
+        String::from_utf8(w.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type(&Path::new("a/index.HTML")).to_string()[],
+                   "text/html; charset=utf-8");
+        assert_eq!(content_type(&Path::new("a/b")).to_string()[], "application/octet-stream");
+    }
+
+    #[test]
+    fn test_serve() {
+        let dir = TempDir::new("hyper-staticfile").unwrap();
+        let path = dir.path().join("hello.txt");
+        File::create(&path).unwrap().write(b"hello world").unwrap();
+
+        let res = get(&path, "GET /hello.txt HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res[].contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(res[].contains("Content-Length: 11\r\n"));
+        assert!(res[].ends_with("\r\n\r\nhello world"));
+
+        let res = get(&path, "GET /hello.txt HTTP/1.1\r\nRange: bytes=6-\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(res[].contains("Content-Range: bytes 6-10/11\r\n"));
+        assert!(res[].ends_with("\r\n\r\nworld"));
+
+        let res = get(&path, "GET /hello.txt HTTP/1.1\r\nRange: bytes=20-\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 416 Requested Range Not Satisfiable\r\n"));
+
+        let stat = File::open(&path).unwrap().stat().unwrap();
+        let head = format!("GET /hello.txt HTTP/1.1\r\nIf-None-Match: \"{}\"\r\n\r\n",
+                           etag(&stat).tag);
+        let res = get(&path, head[]);
+        assert!(res[].starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(res[].ends_with("\r\n\r\n"));
+
+        let res = get(&dir.path().join("missing"), "GET /missing HTTP/1.1\r\n\r\n");
+        assert!(res[].starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
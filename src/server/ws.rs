@@ -0,0 +1,171 @@
+//! WebSocket handshakes
+//!
+//! A server accepts a WebSocket connection by checking the request that asks
+//! for it, and upgrading the connection with the answer RFC 6455 describes.
+//! `handshake` does both, leaving the WebSocket protocol itself to an
+//! `Upgrade`.
+use std::ascii::AsciiExt;
+use std::io::IoResult;
+use std::str::from_utf8;
+
+use openssl::crypto::hash::{hash, HashType};
+use serialize::base64::{ToBase64, FromBase64, Standard, Config};
+
+use {HttpResult};
+use HttpError::HttpHeaderError;
+use header::Headers;
+use header::common::ContentLength;
+use header::common::Upgrade as UpgradeHeader;
+use header::common::upgrade::WebSocket;
+use method::Method::Get;
+use net::Fresh;
+use status::StatusCode;
+use status::StatusCode::{BadRequest, UpgradeRequired};
+use version::HttpVersion::Http11;
+
+use super::{Request, Response, Upgrade};
+
+/// The version of the WebSocket protocol that `handshake` accepts.
+pub const VERSION: &'static str = "13";
+
+// appended to the Sec-WebSocket-Key of a request to answer it
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether a request asks to upgrade its connection to WebSocket.
+pub fn is_websocket(req: &Request) -> bool {
+    has_token(&req.headers, "Upgrade", "websocket")
+}
+
+/// The `Sec-WebSocket-Accept` value that answers a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = hash(HashType::SHA1, format!("{}{}", key, GUID).as_bytes());
+    digest[].to_base64(Config {
+        char_set: Standard,
+        pad: true,
+        line_length: None
+    })
+}
+
+/// Accept a WebSocket connection, giving it to `protocol`.
+///
+/// The request must be an HTTP/1.1 `GET` that asks to upgrade to WebSocket
+/// version 13, with a `Sec-WebSocket-Key` of 16 bytes. If it is, this
+/// answers `101 Switching Protocols` with the matching
+/// `Sec-WebSocket-Accept`, and the server gives the connection to `protocol`
+/// as with `Response::upgrade`. Headers already set on `res`, such as
+/// `Sec-WebSocket-Protocol`, are sent as well.
+///
+/// Otherwise this answers `426 Upgrade Required` if only the version is
+/// wrong, or `400 Bad Request`, and returns an `HttpHeaderError`.
+pub fn handshake<U: Upgrade>(req: &Request, mut res: Response<Fresh>, protocol: U) -> HttpResult<()> {
+    let key = match check(req) {
+        Ok(key) => key,
+        Err(status) => {
+            debug!("refusing websocket handshake with {}", status);
+            try!(refuse(res, status));
+            return Err(HttpHeaderError);
+        }
+    };
+    res.headers_mut().set(UpgradeHeader(vec![WebSocket]));
+    res.headers_mut().set_raw("Sec-WebSocket-Accept", vec![accept_key(key).into_bytes()]);
+    Ok(try!(res.upgrade(protocol)))
+}
+
+/// The `Sec-WebSocket-Key` of a handshake request, or the status to refuse
+/// the request with.
+fn check<'a>(req: &'a Request) -> Result<&'a str, StatusCode> {
+    if req.method != Get || req.version < Http11 || !is_websocket(req) ||
+       !has_token(&req.headers, "Connection", "upgrade") {
+        return Err(BadRequest);
+    }
+    match single(&req.headers, "Sec-WebSocket-Version") {
+        Some(version) if version.trim() == VERSION => (),
+        Some(_) => return Err(UpgradeRequired),
+        None => return Err(BadRequest)
+    }
+    let key = match single(&req.headers, "Sec-WebSocket-Key") {
+        Some(key) => key.trim(),
+        None => return Err(BadRequest)
+    };
+    match key.from_base64() {
+        Ok(ref nonce) if nonce.len() == 16 => Ok(key),
+        _ => Err(BadRequest)
+    }
+}
+
+/// Answer a request that is not a handshake, with the version this accepts
+/// if that was what was wrong.
+fn refuse(mut res: Response<Fresh>, status: StatusCode) -> IoResult<()> {
+    *res.status_mut() = status;
+    if status == UpgradeRequired {
+        res.headers_mut().set(UpgradeHeader(vec![WebSocket]));
+        res.headers_mut().set_raw("Sec-WebSocket-Version", vec![VERSION.as_bytes().to_vec()]);
+    }
+    res.headers_mut().set(ContentLength(0));
+    res.start().and_then(|res| res.end())
+}
+
+/// The value of a header that must appear once.
+fn single<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    match headers.get_raw(name) {
+        Some(raw) if raw.len() == 1 => from_utf8(raw[0][]),
+        _ => None
+    }
+}
+
+/// Whether a comma-delimited header has a token, ignoring case.
+fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+    headers.get_raw(name).map_or(false, |raw| raw.iter().any(|line| {
+        from_utf8(line[]).map_or(false, |line| {
+            line.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::net::ip::Ipv4Addr;
+    use std::io::net::tcp::TcpStream;
+
+    use net::NetworkStream;
+    use server::{Listening, Request, Response, Server, Upgrade};
+    use super::{accept_key, handshake};
+
+    #[test]
+    fn test_accept_key() {
+        // the example of RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ==")[], "s3pPLMBiTxaQ9kYGo+UxPHzmk+Q=");
+    }
+
+    struct Close;
+
+    impl Upgrade for Close {
+        fn upgrade(&mut self, _: Box<NetworkStream + Send>, _: Vec<u8>) {}
+    }
+
+    fn websocket(req: Request, res: Response) {
+        let _ = handshake(&req, res, Close);
+    }
+
+    fn request(listening: &Listening, version: &str) -> String {
+        let mut stream = TcpStream::connect(listening.socket).unwrap();
+        write!(&mut stream, "GET /chat HTTP/1.1\r\nHost: a\r\nUpgrade: websocket\r\n\
+                             Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                             Sec-WebSocket-Version: {}\r\n\r\n", version).unwrap();
+        stream.close_write().unwrap();
+        stream.read_to_string().unwrap()
+    }
+
+    #[test]
+    fn test_handshake() {
+        let server = Server::http(Ipv4Addr(127, 0, 0, 1), 0);
+        let mut listening = server.listen_threads(websocket, 1).unwrap();
+        let res = request(&listening, "13");
+        assert!(res[].starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(res[].contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGo+UxPHzmk+Q=\r\n"));
+        let res = request(&listening, "8");
+        assert!(res[].starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(res[].contains("Sec-WebSocket-Version: 13\r\n"));
+        listening.close().unwrap();
+    }
+}
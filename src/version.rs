@@ -7,7 +7,7 @@ use std::fmt;
 use self::HttpVersion::{Http09, Http10, Http11, Http20};
 
 /// Represents a version of the HTTP spec.
-#[deriving(PartialEq, PartialOrd)]
+#[deriving(Clone, PartialEq, PartialOrd)]
 pub enum HttpVersion {
     /// `HTTP/0.9`
     Http09,